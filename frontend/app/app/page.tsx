@@ -1047,7 +1047,7 @@ curl -L https://github.com/builders-garden/zk-wormhole-monorepo/archive/refs/hea
                             <Button
                               onClick={() => {
                                 navigator.clipboard.writeText(
-                                  "./executables/zk-wormhole-host --dead --secret <secret string> --nonce <secret nonce>"
+                                  "./executables/zk-wormhole-host new-note --out note.txt"
                                 );
                                 toast({
                                   title: "Copied to clipboard",
@@ -1062,8 +1062,8 @@ curl -L https://github.com/builders-garden/zk-wormhole-monorepo/archive/refs/hea
                             </Button>
                           </div>
                           <pre className="whitespace-pre-wrap">
-                            <code>{`# Generate a dead address
-./executables/zk-wormhole-host --dead --secret <secret string> --nonce <secret nonce>
+                            <code>{`# Generate a note and its dead address
+./executables/zk-wormhole-host new-note --out note.txt
 
 # Example output:
 Dead Address: 0x742d35Cc6634C0532925a3b844Bc454e4438f44e
@@ -1213,7 +1213,7 @@ Bytecode Hash: 0x5678...`}</code>
                             <Button
                               onClick={() => {
                                 navigator.clipboard.writeText(
-                                  "./executables/zk-wormhole-host --prove --dead --secret 1234 --nonce 1234"
+                                  "./executables/zk-wormhole-host prove --note note.txt --amount <amount>"
                                 );
                                 toast({
                                   title: "Copied to clipboard",
@@ -1229,7 +1229,7 @@ Bytecode Hash: 0x5678...`}</code>
                           </div>
                           <pre className="whitespace-pre-wrap">
                             <code>{`# Generate a proof
-./executables/zk-wormhole-host --prove --dead --secret 1234 --nonce 1234
+./executables/zk-wormhole-host prove --note note.txt --amount <amount>
 
 # Example output:
 Public Values: 0xabcd...
@@ -0,0 +1,400 @@
+//! A zero-copy form of [`EVMStateSketch`], read in place by the client.
+//!
+//! Decoding an [`EVMStateSketch`] rebuilds every trie node on the heap, and
+//! [`WitnessInput::witness_db`] then hashes every one of them, whether the calls read it or not.
+//! The archive instead holds the RLP of the trie nodes and the bytecodes in flat tables sorted by
+//! hash, laid out with [rkyv] so that they are read where they are. [`SketchArchive`] walks the
+//! tries on demand, and only hashes the nodes and bytecodes the calls read, once each.
+//!
+//! [`WitnessInput::witness_db`]: rsp_client_executor::io::WitnessInput::witness_db
+
+use std::{cell::RefCell, fmt};
+
+use alloy_rlp::{Decodable, Header as RlpHeader};
+use alloy_trie::{TrieAccount, EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use eyre::{bail, eyre, WrapErr};
+use reth_primitives::Header;
+use revm::DatabaseRef;
+use revm_primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, HashMap, B256, U256};
+use rkyv::{rancor, Archive, Serialize};
+use rsp_mpt::mpt::{MptNode, MptNodeData, MptNodeReference};
+
+use crate::io::EVMStateSketch;
+
+/// The magic bytes an archived [`EVMStateSketch`] starts with.
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"sp1ccarc";
+
+/// The version of the archive layout, written after [`ARCHIVE_MAGIC`].
+///
+/// It must be bumped whenever [`FlatSketch`] changes.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// The content of an archived sketch.
+#[derive(Archive, Serialize)]
+pub(crate) struct FlatSketch {
+    /// The RLP of the header.
+    header: Vec<u8>,
+    /// The RLP of the ancestor headers, newest first.
+    ancestor_headers: Vec<Vec<u8>>,
+    /// The RLP of the trie nodes referenced by hash, and of the roots, sorted by hash.
+    nodes: Vec<FlatEntry>,
+    /// The bytecodes, sorted by hash.
+    bytecodes: Vec<FlatEntry>,
+}
+
+/// Some data and its hash.
+#[derive(Archive, Serialize)]
+pub(crate) struct FlatEntry {
+    hash: [u8; 32],
+    data: Vec<u8>,
+}
+
+impl EVMStateSketch {
+    /// Encode the sketch as an archive, read in place by [`SketchArchive`].
+    ///
+    /// Like [`EVMStateSketch::encode`], equal sketches always have the same archive.
+    pub fn to_archive(&self) -> Vec<u8> {
+        let mut nodes = Vec::new();
+        collect_nodes(&self.state.state_trie, true, &mut nodes);
+        self.state.storage_tries.values().for_each(|trie| collect_nodes(trie, true, &mut nodes));
+        nodes.sort_unstable_by_key(|node| node.hash);
+        nodes.dedup_by_key(|node| node.hash);
+
+        let mut bytecodes = self
+            .bytecodes
+            .iter()
+            .map(|code| FlatEntry {
+                hash: code.hash_slow().0,
+                data: code.original_byte_slice().to_vec(),
+            })
+            .collect::<Vec<_>>();
+        bytecodes.sort_unstable_by_key(|code| code.hash);
+        bytecodes.dedup_by_key(|code| code.hash);
+
+        let sketch = FlatSketch {
+            header: alloy_rlp::encode(&self.header),
+            ancestor_headers: self.ancestor_headers.iter().map(alloy_rlp::encode).collect(),
+            nodes,
+            bytecodes,
+        };
+
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        let archived = rkyv::to_bytes::<rancor::Error>(&sketch)
+            .expect("serializing into a vector does not fail");
+        bytes.extend_from_slice(&archived);
+        bytes
+    }
+}
+
+/// Push the resolved nodes of the trie rooted at `node` that are referenced by hash to `nodes`.
+///
+/// Nodes shorter than a hash are embedded in their parent, except for the root.
+fn collect_nodes(node: &MptNode, root: bool, nodes: &mut Vec<FlatEntry>) {
+    match node.as_data() {
+        MptNodeData::Null | MptNodeData::Digest(_) => return,
+        MptNodeData::Branch(children) => {
+            children.iter().flatten().for_each(|child| collect_nodes(child, false, nodes))
+        }
+        MptNodeData::Extension(_, child) => collect_nodes(child, false, nodes),
+        MptNodeData::Leaf(..) => {}
+    }
+    if root || matches!(node.reference(), MptNodeReference::Digest(_)) {
+        nodes.push(FlatEntry { hash: node.hash().0, data: node.to_rlp() });
+    }
+}
+
+/// An error reading the state of a [`SketchArchive`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// A trie node needed to read the state is not in the archive.
+    MissingNode(B256),
+    /// A trie node does not have the hash it is stored under, or is not a valid node.
+    InvalidNode(B256),
+    /// A bytecode is not in the archive.
+    MissingCode(B256),
+    /// A bytecode does not have the hash it is stored under.
+    InvalidCode(B256),
+    /// The hash of a block is not in the archive.
+    MissingBlockHash(u64),
+    /// An account or storage value is not valid RLP.
+    Rlp(alloy_rlp::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingNode(hash) => write!(f, "missing trie node {}", hash),
+            Self::InvalidNode(hash) => write!(f, "invalid trie node {}", hash),
+            Self::MissingCode(hash) => write!(f, "missing bytecode {}", hash),
+            Self::InvalidCode(hash) => write!(f, "bytecode {} does not have its hash", hash),
+            Self::MissingBlockHash(number) => write!(f, "missing the hash of block {}", number),
+            Self::Rlp(error) => write!(f, "invalid state value: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<alloy_rlp::Error> for ArchiveError {
+    fn from(error: alloy_rlp::Error) -> Self {
+        Self::Rlp(error)
+    }
+}
+
+/// An archived [`EVMStateSketch`], read in place.
+///
+/// The headers are decoded and checked to chain up when the archive is opened. The state is read
+/// from the tries as it is accessed, against the state root of the header: every trie node and
+/// bytecode is checked against its hash the first time it is read.
+pub struct SketchArchive<'a> {
+    /// The block header.
+    pub header: Header,
+    /// The hashes of the block and its ancestors, by number.
+    block_hashes: HashMap<u64, B256>,
+    /// The archived sketch.
+    sketch: &'a ArchivedFlatSketch,
+    /// Whether each trie node was checked against its hash.
+    checked_nodes: RefCell<Vec<bool>>,
+    /// Whether each bytecode was checked against its hash.
+    checked_codes: RefCell<Vec<bool>>,
+    /// The storage roots of the accounts read.
+    storage_roots: RefCell<HashMap<Address, B256>>,
+}
+
+impl fmt::Debug for SketchArchive<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SketchArchive")
+            .field("header", &self.header)
+            .field("block_hashes", &self.block_hashes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> SketchArchive<'a> {
+    /// Open an archive written by [`EVMStateSketch::to_archive`].
+    ///
+    /// The layout of the archive is validated, but none of its content is copied.
+    pub fn new(bytes: &'a [u8]) -> eyre::Result<Self> {
+        let Some(body) = bytes.strip_prefix(&ARCHIVE_MAGIC) else {
+            bail!("not an archived state sketch");
+        };
+        let Some((version, body)) = body.split_first_chunk::<4>() else {
+            bail!("truncated state sketch archive");
+        };
+        let version = u32::from_le_bytes(*version);
+        if version != ARCHIVE_VERSION {
+            bail!(
+                "unsupported state sketch archive version {}, expected version {}",
+                version,
+                ARCHIVE_VERSION
+            );
+        }
+        let sketch = rkyv::access::<ArchivedFlatSketch, rancor::Error>(body)
+            .map_err(|error| eyre!("invalid state sketch archive: {}", error))?;
+
+        let header = Header::decode(&mut sketch.header.as_slice()).wrap_err("invalid header")?;
+        let mut block_hashes = HashMap::default();
+        block_hashes.insert(header.number, header.hash_slow());
+        let mut child = header.clone();
+        for ancestor in sketch.ancestor_headers.iter() {
+            let ancestor = Header::decode(&mut ancestor.as_slice()).wrap_err("invalid header")?;
+            let hash = ancestor.hash_slow();
+            if ancestor.number != child.number.wrapping_sub(1) || hash != child.parent_hash {
+                bail!("ancestor header {} is not the parent of header {}", hash, child.number);
+            }
+            block_hashes.insert(ancestor.number, hash);
+            child = ancestor;
+        }
+
+        Ok(Self {
+            header,
+            block_hashes,
+            checked_nodes: RefCell::new(vec![false; sketch.nodes.len()]),
+            checked_codes: RefCell::new(vec![false; sketch.bytecodes.len()]),
+            storage_roots: RefCell::default(),
+            sketch,
+        })
+    }
+
+    /// The trie node with hash `hash`.
+    fn node(&self, hash: B256) -> Result<&'a [u8], ArchiveError> {
+        let nodes = &self.sketch.nodes;
+        let index = nodes
+            .binary_search_by(|node| node.hash.as_slice().cmp(hash.as_slice()))
+            .map_err(|_| ArchiveError::MissingNode(hash))?;
+        let node = nodes[index].data.as_slice();
+
+        let mut checked = self.checked_nodes.borrow_mut();
+        if !checked[index] {
+            if keccak256(node) != hash {
+                return Err(ArchiveError::InvalidNode(hash));
+            }
+            checked[index] = true;
+        }
+        Ok(node)
+    }
+
+    /// The node referenced by `reference`, an item of the node with hash `parent`, along with the
+    /// hash of the last node fetched by hash to reach it, or `None` if it is empty.
+    fn child(
+        &self,
+        parent: B256,
+        reference: &'a [u8],
+    ) -> Result<Option<(&'a [u8], B256)>, ArchiveError> {
+        let mut payload = reference;
+        let header =
+            RlpHeader::decode(&mut payload).map_err(|_| ArchiveError::InvalidNode(parent))?;
+        match (header.list, header.payload_length) {
+            // Nodes shorter than a hash are embedded in their parent.
+            (true, _) => Ok(Some((reference, parent))),
+            (false, 0) => Ok(None),
+            (false, 32) => {
+                let hash = B256::from_slice(payload);
+                Ok(Some((self.node(hash)?, hash)))
+            }
+            (false, _) => Err(ArchiveError::InvalidNode(parent)),
+        }
+    }
+
+    /// The value at `key` in the trie with root `root`, or `None` if there is none.
+    ///
+    /// The value is returned as the RLP it is stored as.
+    fn get(&self, root: B256, key: B256) -> Result<Option<&'a [u8]>, ArchiveError> {
+        if root == EMPTY_ROOT_HASH {
+            return Ok(None);
+        }
+        let nibble = |index: usize| (key[index / 2] >> (4 * (1 - index % 2))) & 0x0f;
+
+        // The node being walked, the hash of the last node fetched by hash to reach it, and the
+        // number of nibbles of the key walked so far.
+        let (mut node, mut hash, mut depth) = (self.node(root)?, root, 0);
+        loop {
+            let invalid = move |_| ArchiveError::InvalidNode(hash);
+            let items = list_items(node).map_err(invalid)?;
+            match items.as_slice() {
+                [children @ .., _value] if children.len() == 16 => {
+                    if depth == 64 {
+                        return Err(ArchiveError::InvalidNode(hash));
+                    }
+                    match self.child(hash, children[nibble(depth) as usize])? {
+                        Some(child) => (node, hash) = child,
+                        None => return Ok(None),
+                    }
+                    depth += 1;
+                }
+                [path, value] => {
+                    let path = RlpHeader::decode_bytes(&mut &path[..], false).map_err(invalid)?;
+                    let (&flag, rest) =
+                        path.split_first().ok_or(ArchiveError::InvalidNode(hash))?;
+                    let odd = (flag & 0x10 != 0).then_some(flag & 0x0f);
+                    let path = odd.into_iter().chain(rest.iter().flat_map(|b| [b >> 4, b & 0x0f]));
+
+                    let mut length = 0;
+                    for path_nibble in path {
+                        if depth + length == 64 || nibble(depth + length) != path_nibble {
+                            return Ok(None);
+                        }
+                        length += 1;
+                    }
+                    depth += length;
+
+                    // Leaves hold the value, extensions the next node.
+                    if flag & 0x20 != 0 {
+                        if depth != 64 {
+                            return Ok(None);
+                        }
+                        let value =
+                            RlpHeader::decode_bytes(&mut &value[..], false).map_err(invalid)?;
+                        return Ok(Some(value));
+                    }
+                    match self.child(hash, value)? {
+                        Some(child) => (node, hash) = child,
+                        None => return Err(ArchiveError::InvalidNode(hash)),
+                    }
+                }
+                _ => return Err(ArchiveError::InvalidNode(hash)),
+            }
+        }
+    }
+}
+
+/// The items of the RLP list `list`, each with its RLP header.
+fn list_items(list: &[u8]) -> Result<Vec<&[u8]>, alloy_rlp::Error> {
+    let mut payload = list;
+    let header = RlpHeader::decode(&mut payload)?;
+    if !header.list {
+        return Err(alloy_rlp::Error::UnexpectedString);
+    }
+    let mut payload = &payload[..header.payload_length];
+    let mut items = Vec::with_capacity(17);
+    while !payload.is_empty() {
+        let mut rest = payload;
+        let header = RlpHeader::decode(&mut rest)?;
+        let length = payload.len() - rest.len() + header.payload_length;
+        if length > payload.len() {
+            return Err(alloy_rlp::Error::InputTooShort);
+        }
+        let (item, rest) = payload.split_at(length);
+        items.push(item);
+        payload = rest;
+    }
+    Ok(items)
+}
+
+impl DatabaseRef for SketchArchive<'_> {
+    type Error = ArchiveError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let Some(mut account) = self.get(self.header.state_root, keccak256(address))? else {
+            return Ok(None);
+        };
+        let account = TrieAccount::decode(&mut account)?;
+        self.storage_roots.borrow_mut().insert(address, account.storage_root);
+        Ok(Some(AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.code_hash,
+            code: None,
+        }))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::default());
+        }
+        let bytecodes = &self.sketch.bytecodes;
+        let index = bytecodes
+            .binary_search_by(|code| code.hash.as_slice().cmp(code_hash.as_slice()))
+            .map_err(|_| ArchiveError::MissingCode(code_hash))?;
+        let code = bytecodes[index].data.as_slice();
+
+        let mut checked = self.checked_codes.borrow_mut();
+        if !checked[index] {
+            if keccak256(code) != code_hash {
+                return Err(ArchiveError::InvalidCode(code_hash));
+            }
+            checked[index] = true;
+        }
+        Ok(Bytecode::new_raw(Bytes::copy_from_slice(code)))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let storage_root = self.storage_roots.borrow().get(&address).copied();
+        let storage_root = match storage_root {
+            Some(storage_root) => storage_root,
+            None => match self.basic_ref(address)? {
+                Some(_) => self.storage_roots.borrow()[&address],
+                None => return Ok(U256::ZERO),
+            },
+        };
+        match self.get(storage_root, keccak256(B256::from(index)))? {
+            Some(mut value) => Ok(U256::decode(&mut value)?),
+            None => Ok(U256::ZERO),
+        }
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.block_hashes.get(&number).copied().ok_or(ArchiveError::MissingBlockHash(number))
+    }
+}
@@ -1,12 +1,24 @@
 use std::iter::once;
 
+use bincode::Options;
+use eyre::{bail, WrapErr};
 use reth_primitives::{EthPrimitives, Header};
-use revm_primitives::{Address, Bytecode, HashMap, B256, U256};
+use revm_primitives::{keccak256, Address, Bytecode, Bytes, HashMap, B256, U256};
 use rsp_client_executor::io::WitnessInput;
-use rsp_mpt::EthereumState;
+use rsp_mpt::{mpt::MptNode, EthereumState};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
+/// The magic bytes an encoded [`EVMStateSketch`] starts with.
+pub const SKETCH_MAGIC: [u8; 8] = *b"sp1ccskt";
+
+/// The version of the encoding of [`EVMStateSketch`], written after [`SKETCH_MAGIC`].
+///
+/// It must be bumped whenever the serialized layout of any type in the sketch changes, such as the
+/// MPT nodes of rsp, so that sketches encoded by another version are rejected instead of being
+/// misread.
+pub const SKETCH_VERSION: u32 = 1;
+
 /// Information about how the contract executions accessed state, which is needed to execute the
 /// contract in SP1.
 ///
@@ -30,6 +42,168 @@ pub struct EVMStateSketch {
     pub bytecodes: Vec<Bytecode>,
 }
 
+impl EVMStateSketch {
+    /// Encode the sketch in its canonical binary form: [`SKETCH_MAGIC`] and [`SKETCH_VERSION`],
+    /// followed by the fields of the sketch, with every map and set sorted.
+    ///
+    /// Equal sketches always have the same encoding, whatever the order in which their state was
+    /// accessed.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = SKETCH_MAGIC.to_vec();
+        bytes.extend_from_slice(&SKETCH_VERSION.to_le_bytes());
+        bincode_options()
+            .serialize_into(&mut bytes, &self.canonical())
+            .expect("serializing into a vector does not fail");
+        bytes
+    }
+
+    /// The size of each component of the sketch in its canonical encoding.
+    pub fn encoded_sizes(&self) -> EncodedSizes {
+        let sketch = self.canonical();
+        EncodedSizes {
+            header: encoded_size(&sketch.header),
+            ancestor_headers: encoded_size(&sketch.ancestor_headers),
+            state_trie: encoded_size(&sketch.state_trie),
+            storage_tries: encoded_size(&sketch.storage_tries),
+            state_requests: encoded_size(&sketch.state_requests),
+            bytecodes: encoded_size(&sketch.bytecodes),
+        }
+    }
+
+    /// The fields of the sketch, with every map and set sorted.
+    fn canonical(&self) -> CanonicalSketchRef<'_> {
+        let mut storage_tries = self.state.storage_tries.iter().collect::<Vec<_>>();
+        storage_tries.sort_unstable_by_key(|(address_hash, _)| *address_hash);
+
+        let mut state_requests = self
+            .state_requests
+            .iter()
+            .map(|(address, slots)| {
+                let mut slots = slots.clone();
+                slots.sort_unstable();
+                slots.dedup();
+                (*address, slots)
+            })
+            .collect::<Vec<_>>();
+        state_requests.sort_unstable_by_key(|(address, _)| *address);
+
+        let mut bytecodes =
+            self.bytecodes.iter().map(|code| (code.hash_slow(), code)).collect::<Vec<_>>();
+        bytecodes.sort_unstable_by_key(|(hash, _)| *hash);
+        bytecodes.dedup_by_key(|(hash, _)| *hash);
+
+        CanonicalSketchRef {
+            header: (&self.header).into(),
+            ancestor_headers: self.ancestor_headers.iter().map(Into::into).collect(),
+            state_trie: &self.state.state_trie,
+            storage_tries,
+            state_requests,
+            bytecodes: bytecodes.into_iter().map(|(_, code)| code.original_bytes()).collect(),
+        }
+    }
+
+    /// Decode a sketch encoded by [`EVMStateSketch::encode`].
+    pub fn decode(bytes: &[u8]) -> eyre::Result<Self> {
+        let Some(body) = bytes.strip_prefix(&SKETCH_MAGIC) else {
+            bail!("not an encoded state sketch");
+        };
+        let Some((version, body)) = body.split_first_chunk::<4>() else {
+            bail!("truncated state sketch");
+        };
+        let version = u32::from_le_bytes(*version);
+        if version != SKETCH_VERSION {
+            bail!(
+                "unsupported state sketch encoding version {}, expected version {}",
+                version,
+                SKETCH_VERSION
+            );
+        }
+
+        let sketch: CanonicalSketch =
+            bincode_options().deserialize(body).wrap_err("invalid state sketch")?;
+        Ok(Self {
+            header: sketch.header,
+            ancestor_headers: sketch.ancestor_headers,
+            state: EthereumState {
+                state_trie: sketch.state_trie,
+                storage_tries: sketch.storage_tries.into_iter().collect(),
+            },
+            state_requests: sketch.state_requests.into_iter().collect(),
+            bytecodes: sketch.bytecodes.into_iter().map(Bytecode::new_raw).collect(),
+        })
+    }
+
+    /// The hash of the canonical encoding of the sketch, identifying it.
+    pub fn content_hash(&self) -> B256 {
+        keccak256(self.encode())
+    }
+}
+
+/// The size in bytes of each component of an encoded [`EVMStateSketch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct EncodedSizes {
+    /// The size of the header.
+    pub header: u64,
+    /// The size of the ancestor headers.
+    pub ancestor_headers: u64,
+    /// The size of the state trie.
+    pub state_trie: u64,
+    /// The size of the storage tries.
+    pub storage_tries: u64,
+    /// The size of the state requests.
+    pub state_requests: u64,
+    /// The size of the bytecodes.
+    pub bytecodes: u64,
+}
+
+impl EncodedSizes {
+    /// The size of the whole encoded sketch, including its magic and version.
+    pub fn total(&self) -> u64 {
+        (SKETCH_MAGIC.len() + size_of::<u32>()) as u64
+            + self.header
+            + self.ancestor_headers
+            + self.state_trie
+            + self.storage_tries
+            + self.state_requests
+            + self.bytecodes
+    }
+}
+
+/// The encoded fields of an [`EVMStateSketch`], in their canonical order.
+#[serde_as]
+#[derive(Deserialize)]
+struct CanonicalSketch {
+    #[serde_as(as = "alloy_consensus::serde_bincode_compat::Header")]
+    header: Header,
+    #[serde_as(as = "Vec<alloy_consensus::serde_bincode_compat::Header>")]
+    ancestor_headers: Vec<Header>,
+    state_trie: MptNode,
+    storage_tries: Vec<(B256, MptNode)>,
+    state_requests: Vec<(Address, Vec<U256>)>,
+    bytecodes: Vec<Bytes>,
+}
+
+/// A [`CanonicalSketch`] borrowing from the sketch being encoded.
+#[derive(Serialize)]
+struct CanonicalSketchRef<'a> {
+    header: alloy_consensus::serde_bincode_compat::Header<'a>,
+    ancestor_headers: Vec<alloy_consensus::serde_bincode_compat::Header<'a>>,
+    state_trie: &'a MptNode,
+    storage_tries: Vec<(&'a B256, &'a MptNode)>,
+    state_requests: Vec<(Address, Vec<U256>)>,
+    bytecodes: Vec<Bytes>,
+}
+
+/// The bincode options of the sketch encoding, rejecting trailing bytes.
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+/// The size of `value` in the sketch encoding.
+fn encoded_size<T: Serialize + ?Sized>(value: &T) -> u64 {
+    bincode_options().serialized_size(value).expect("the sketch fields are serializable")
+}
+
 impl WitnessInput<EthPrimitives> for EVMStateSketch {
     #[inline(always)]
     fn state(&self) -> &EthereumState {
@@ -56,3 +230,59 @@ impl WitnessInput<EthPrimitives> for EVMStateSketch {
         once(&self.header).chain(self.ancestor_headers.iter())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{counters, sketch};
+
+    #[test]
+    fn test_roundtrip() {
+        let sketch = sketch(&counters(5, 7));
+        let encoded = sketch.encode();
+        assert!(encoded.starts_with(&SKETCH_MAGIC));
+        assert_eq!(EVMStateSketch::decode(&encoded).unwrap(), sketch);
+        assert_eq!(sketch.encoded_sizes().total(), encoded.len() as u64);
+    }
+
+    #[test]
+    fn test_encoding_is_canonical() {
+        let sketch = sketch(&counters(5, 7));
+
+        // The same sketch, with its maps filled and its lists ordered the other way around.
+        let mut reordered = sketch.clone();
+        reordered.state.storage_tries =
+            sketch.state.storage_tries.iter().rev().map(|(k, v)| (*k, v.clone())).collect();
+        reordered.state_requests = sketch
+            .state_requests
+            .iter()
+            .rev()
+            .map(|(address, slots)| (*address, slots.iter().rev().copied().collect()))
+            .collect();
+        reordered.bytecodes.reverse();
+
+        assert_eq!(reordered.encode(), sketch.encode());
+        assert_eq!(reordered.content_hash(), sketch.content_hash());
+    }
+
+    #[test]
+    fn test_invalid_encodings() {
+        let encoded = sketch(&counters(5, 7)).encode();
+
+        let mut wrong_magic = encoded.clone();
+        wrong_magic[0] ^= 1;
+        assert!(EVMStateSketch::decode(&wrong_magic).is_err());
+
+        let mut wrong_version = encoded.clone();
+        wrong_version[SKETCH_MAGIC.len()..SKETCH_MAGIC.len() + 4]
+            .copy_from_slice(&(SKETCH_VERSION + 1).to_le_bytes());
+        assert!(EVMStateSketch::decode(&wrong_version).is_err());
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(EVMStateSketch::decode(&trailing).is_err());
+
+        assert!(EVMStateSketch::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(EVMStateSketch::decode(&encoded[..SKETCH_MAGIC.len() + 2]).is_err());
+    }
+}
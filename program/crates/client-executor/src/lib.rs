@@ -1,14 +1,29 @@
+pub mod archive;
 pub mod io;
+pub mod merge;
+pub mod overrides;
+pub mod validate;
+
+#[cfg(test)]
+mod test_utils;
+
 use std::sync::Arc;
 
 use alloy_sol_types::{sol, SolCall};
-use eyre::OptionExt;
+use archive::SketchArchive;
+use eyre::{bail, OptionExt};
 use io::EVMStateSketch;
+use overrides::{
+    apply_overrides, committed_overrides, AccountOverride, AccountStateOverride, StateOverrides,
+};
 use reth_evm::{ConfigureEvmEnv, EvmEnv};
 use reth_evm_ethereum::EthEvmConfig;
 use reth_primitives::Header;
-use revm::{db::CacheDB, Database, Evm, EvmBuilder, State};
-use revm_primitives::{Address, Bytes, CfgEnvWithHandlerCfg, SpecId, TxKind, B256, U256};
+use revm::{db::CacheDB, Database, DatabaseCommit, DatabaseRef, Evm, EvmBuilder, State};
+use revm_primitives::{
+    AccessListItem, Address, Bytes, CfgEnvWithHandlerCfg, ExecutionResult, Output, ResultAndState,
+    SpecId, TxEnv, TxKind, B256, U256,
+};
 use rsp_client_executor::io::{TrieDB, WitnessInput};
 
 /// Input to a contract call.
@@ -22,15 +37,54 @@ pub struct ContractInput {
     pub caller_address: Address,
     /// The calldata to pass to the contract.
     pub calldata: ContractCalldata,
+    /// The rest of the transaction environment of the call.
+    pub env: CallEnv,
+    /// The overrides of the state the call runs on.
+    pub overrides: StateOverrides,
+}
+
+/// The transaction environment of a call, beyond its caller, target and calldata.
+///
+/// By default, a call transfers no value, can use all the gas of the block, pays no gas and does
+/// not check the nonce of the caller.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallEnv {
+    /// The value transferred to the contract.
+    pub value: U256,
+    /// The gas limit of the call, or the gas limit of the block if unset.
+    pub gas_limit: Option<u64>,
+    /// The gas price, or the max fee per gas if a priority fee is set.
+    ///
+    /// A call with a zero gas price runs with a zero base fee, so that it does not pay for gas. A
+    /// call with a gas price runs with the base fee of the block, and the caller must be able to
+    /// pay for the gas.
+    pub gas_price: U256,
+    /// The max priority fee per gas, for an EIP-1559 call.
+    pub gas_priority_fee: Option<U256>,
+    /// The nonce of the caller, checked against its account if set.
+    pub nonce: Option<u64>,
+    /// The EIP-2930 access list.
+    pub access_list: Vec<AccessListItem>,
+    /// The versioned hashes of the blobs of an EIP-4844 call.
+    pub blob_hashes: Vec<B256>,
+    /// The max fee per blob gas, for an EIP-4844 call.
+    pub max_fee_per_blob_gas: U256,
 }
 
 /// The type of calldata to pass to a contract.
 ///
-/// This enum is used to distinguish between contract calls and contract creations.
+/// This enum is used to distinguish between contract calls, contract creations, and deployless
+/// calls, which call a contract that is only deployed in the state of the execution.
 #[derive(Debug, Clone)]
 pub enum ContractCalldata {
     Call(Bytes),
     Create(Bytes),
+    Deployless {
+        /// The init code of the contract, deployed from the caller before the call.
+        init_code: Bytes,
+        /// The calldata to pass to the deployed contract.
+        calldata: Bytes,
+    },
 }
 
 impl ContractCalldata {
@@ -39,6 +93,15 @@ impl ContractCalldata {
         match self {
             Self::Call(calldata) => calldata.clone(),
             Self::Create(calldata) => calldata.clone(),
+            Self::Deployless { calldata, .. } => calldata.clone(),
+        }
+    }
+
+    /// The init code deployed before a deployless call, or nothing for other calls.
+    pub fn init_code(&self) -> Bytes {
+        match self {
+            Self::Deployless { init_code, .. } => init_code.clone(),
+            Self::Call(_) | Self::Create(_) => Bytes::new(),
         }
     }
 }
@@ -54,6 +117,8 @@ impl ContractInput {
             contract_address,
             caller_address,
             calldata: ContractCalldata::Call(calldata.abi_encode().into()),
+            env: CallEnv::default(),
+            overrides: StateOverrides::new(),
         }
     }
 
@@ -66,11 +131,76 @@ impl ContractInput {
             contract_address: Address::ZERO,
             caller_address,
             calldata: ContractCalldata::Create(calldata),
+            env: CallEnv::default(),
+            overrides: StateOverrides::new(),
         }
     }
+
+    /// Creates a new deployless call input, calling `calldata` on the contract deployed by
+    /// `init_code`.
+    ///
+    /// The contract is deployed from the caller, in the state of the execution only: it does not
+    /// need to exist on chain. Its address is only known once deployed, so the contract address is
+    /// set to the zero address until then.
+    pub fn new_deployless_call<C: SolCall>(
+        caller_address: Address,
+        init_code: Bytes,
+        calldata: C,
+    ) -> Self {
+        Self {
+            contract_address: Address::ZERO,
+            caller_address,
+            calldata: ContractCalldata::Deployless {
+                init_code,
+                calldata: calldata.abi_encode().into(),
+            },
+            env: CallEnv::default(),
+            overrides: StateOverrides::new(),
+        }
+    }
+
+    /// Set the transaction environment of the call.
+    pub fn with_env(mut self, env: CallEnv) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Set the value transferred to the contract, for a payable function.
+    pub fn with_value(mut self, value: U256) -> Self {
+        self.env.value = value;
+        self
+    }
+
+    /// Override the state of the account at `address` for the call.
+    pub fn with_override(mut self, address: Address, account: AccountOverride) -> Self {
+        self.overrides.insert(address, account);
+        self
+    }
 }
 
 sol! {
+    /// An entry of the access list of a call.
+    struct AccessListEntry {
+        address account;
+        bytes32[] storageKeys;
+    }
+
+    /// The transaction environment a call was executed with, as [`CallEnv`] resolves it.
+    ///
+    /// The priority fee of a legacy call is its gas price, and the nonce is only meaningful if it
+    /// was checked.
+    struct CallEnvironment {
+        uint256 value;
+        uint64 gasLimit;
+        uint256 gasPrice;
+        uint256 gasPriorityFee;
+        bool checkNonce;
+        uint64 nonce;
+        AccessListEntry[] accessList;
+        bytes32[] blobHashes;
+        uint256 maxFeePerBlobGas;
+    }
+
     /// Public values of a contract call.
     ///
     /// These outputs can easily be abi-encoded, for use on-chain.
@@ -79,31 +209,170 @@ sol! {
         address callerAddress;
         address contractAddress;
         bytes contractCalldata;
+        bytes initCode;
+        CallEnvironment environment;
+        AccountStateOverride[] stateOverrides;
         bytes contractOutput;
     }
 }
 
+impl CallEnvironment {
+    /// The environment `call` is executed with in the block of `header`.
+    pub fn new(call: &ContractInput, header: &Header) -> Self {
+        let tx_env = tx_env(call, header);
+        Self {
+            value: tx_env.value,
+            gasLimit: tx_env.gas_limit,
+            gasPrice: tx_env.gas_price,
+            gasPriorityFee: tx_env.gas_priority_fee.unwrap_or(tx_env.gas_price),
+            checkNonce: tx_env.nonce.is_some(),
+            nonce: tx_env.nonce.unwrap_or_default(),
+            accessList: tx_env
+                .access_list
+                .into_iter()
+                .map(|item| AccessListEntry {
+                    account: item.address,
+                    storageKeys: item.storage_keys,
+                })
+                .collect(),
+            blobHashes: tx_env.blob_hashes,
+            maxFeePerBlobGas: tx_env.max_fee_per_blob_gas.unwrap_or_default(),
+        }
+    }
+}
+
 impl ContractPublicValues {
     /// Construct a new [`ContractPublicValues`]
     ///
-    /// By default, commit the contract input, the output, and the block hash to public values of
-    /// the proof. More can be committed if necessary.
-    pub fn new(call: ContractInput, output: Bytes, block_hash: B256) -> Self {
+    /// By default, commit the contract input with its environment and state overrides, the
+    /// output, and the hash of `header` to public values of the proof. More can be committed if
+    /// necessary.
+    ///
+    /// The contract address of a deployless call is the address the contract was deployed at, as
+    /// set by [`deploy`].
+    pub fn new(call: ContractInput, output: Bytes, header: &Header) -> Self {
         Self {
+            environment: CallEnvironment::new(&call, header),
+            stateOverrides: committed_overrides(&call.overrides),
             contractAddress: call.contract_address,
             callerAddress: call.caller_address,
             contractCalldata: call.calldata.to_bytes(),
+            initCode: call.calldata.init_code(),
             contractOutput: output,
-            blockHash: block_hash,
+            blockHash: header.hash_slow(),
         }
     }
 }
 
+/// How the calls of a batch see the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchMode {
+    /// Every call is executed on the state of the block, as if it were the only one.
+    #[default]
+    Independent,
+    /// Every call is executed on the state left by the previous ones, like the transactions of a
+    /// block.
+    Sequential,
+}
+
+sol! {
+    /// The outcome of a call of a batch.
+    struct CallOutput {
+        address callerAddress;
+        address contractAddress;
+        bytes contractCalldata;
+        bytes initCode;
+        CallEnvironment environment;
+        AccountStateOverride[] stateOverrides;
+        bool success;
+        bytes contractOutput;
+    }
+
+    /// Public values of a batch of contract calls.
+    ///
+    /// These outputs can easily be abi-encoded, for use on-chain.
+    struct BatchPublicValues {
+        bytes32 blockHash;
+        bool sequential;
+        CallOutput[] calls;
+    }
+}
+
+impl BatchPublicValues {
+    /// Construct a new [`BatchPublicValues`] from the result of each call of a batch.
+    ///
+    /// Calls that halted, such as by running out of gas, have no output.
+    pub fn new(
+        calls: Vec<ContractInput>,
+        results: Vec<ExecutionResult>,
+        mode: BatchMode,
+        header: &Header,
+    ) -> Self {
+        let calls = calls
+            .into_iter()
+            .zip(results)
+            .map(|(call, result)| CallOutput {
+                environment: CallEnvironment::new(&call, header),
+                stateOverrides: committed_overrides(&call.overrides),
+                callerAddress: call.caller_address,
+                contractAddress: call.contract_address,
+                contractCalldata: call.calldata.to_bytes(),
+                initCode: call.calldata.init_code(),
+                success: result.is_success(),
+                contractOutput: result.into_output().unwrap_or_default(),
+            })
+            .collect();
+        Self { blockHash: header.hash_slow(), sequential: mode == BatchMode::Sequential, calls }
+    }
+}
+
+/// Executes `calls` in `mode` on the state of `db` at the end of the block of `header`.
+///
+/// Returns the result of every call, in order. In [`BatchMode::Sequential`] mode, only the state
+/// changes of the calls that succeed are carried over, while the state overrides and the
+/// deployments of every call stay in place for the next ones. Deployless calls are pointed at the
+/// contract they deployed, as by [`deploy`].
+pub fn transact_batch<D>(
+    db: D,
+    header: &Header,
+    calls: &mut [ContractInput],
+    mode: BatchMode,
+) -> eyre::Result<Vec<ExecutionResult>>
+where
+    D: DatabaseRef,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut shared_db = CacheDB::new(&db);
+    let mut results = Vec::with_capacity(calls.len());
+    for call in calls {
+        let ResultAndState { result, state } = match mode {
+            BatchMode::Independent => {
+                let mut cache_db = call_db(&db, call)?;
+                deploy(&mut cache_db, header, call)?;
+                new_evm(cache_db, header, U256::ZERO, call).transact()?
+            }
+            BatchMode::Sequential => {
+                apply_overrides(&mut shared_db, &call.overrides)?;
+                deploy(&mut shared_db, header, call)?;
+                new_evm(&mut shared_db, header, U256::ZERO, call).transact()?
+            }
+        };
+        if mode == BatchMode::Sequential {
+            shared_db.commit(state);
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
 /// An executor that executes smart contract calls inside a zkVM.
+///
+/// The state is read from a [`TrieDB`] built from an [`EVMStateSketch`], or read in place from a
+/// [`SketchArchive`].
 #[derive(Debug)]
-pub struct ClientExecutor<'a> {
+pub struct ClientExecutor<'a, D = TrieDB<'a>> {
     /// The database that the executor uses to access state.
-    pub witness_db: TrieDB<'a>,
+    pub witness_db: D,
     /// The block header.
     pub header: &'a Header,
 }
@@ -114,17 +383,82 @@ impl<'a> ClientExecutor<'a> {
         // let header = state_sketch.header.clone();
         Ok(Self { witness_db: state_sketch.witness_db().unwrap(), header: &state_sketch.header })
     }
+}
 
+impl<'a> ClientExecutor<'a, &'a SketchArchive<'a>> {
+    /// Instantiates a new [`ClientExecutor`] reading the state in place from `archive`.
+    pub fn from_archive(archive: &'a SketchArchive<'a>) -> Self {
+        Self { witness_db: archive, header: &archive.header }
+    }
+}
+
+impl<D> ClientExecutor<'_, D>
+where
+    D: DatabaseRef,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
     /// Executes the smart contract call with the given [`ContractInput`] in SP1.
     ///
     /// Storage accesses are already validated against the `witness_db`'s state root.
-    pub fn execute(&self, call: ContractInput) -> eyre::Result<ContractPublicValues> {
-        let cache_db = CacheDB::new(&self.witness_db);
+    pub fn execute(&self, mut call: ContractInput) -> eyre::Result<ContractPublicValues> {
+        let mut cache_db = call_db(&self.witness_db, &call)?;
+        deploy(&mut cache_db, self.header, &mut call)?;
         let mut evm = new_evm(cache_db, self.header, U256::ZERO, &call);
         let tx_output = evm.transact()?;
         let tx_output_bytes = tx_output.result.output().ok_or_eyre("Error decoding result")?;
-        Ok(ContractPublicValues::new(call, tx_output_bytes.clone(), self.header.hash_slow()))
+        Ok(ContractPublicValues::new(call, tx_output_bytes.clone(), self.header))
     }
+
+    /// Executes the smart contract calls `calls` in SP1, in `mode`.
+    ///
+    /// Unlike [`ClientExecutor::execute`], a call that reverts or halts does not fail the batch:
+    /// its outcome is committed along with the others.
+    pub fn execute_batch(
+        &self,
+        mut calls: Vec<ContractInput>,
+        mode: BatchMode,
+    ) -> eyre::Result<BatchPublicValues> {
+        let results = transact_batch(&self.witness_db, self.header, &mut calls, mode)?;
+        Ok(BatchPublicValues::new(calls, results, mode, self.header))
+    }
+}
+
+/// A [`CacheDB`] over `db` to run `call` on, with the state overrides of the call applied.
+pub fn call_db<D: DatabaseRef>(db: D, call: &ContractInput) -> Result<CacheDB<D>, D::Error> {
+    let mut cache_db = CacheDB::new(db);
+    apply_overrides(&mut cache_db, &call.overrides)?;
+    Ok(cache_db)
+}
+
+/// Deploys the init code of a deployless `call` in `db`, and points the call at the deployed
+/// contract. Other calls are left as is.
+///
+/// The contract is deployed by a contract creation from the caller, with the environment of the
+/// call but without value, which goes to the call itself. If the nonce of the caller is checked,
+/// the call then runs with the next one.
+pub fn deploy<D>(db: &mut CacheDB<D>, header: &Header, call: &mut ContractInput) -> eyre::Result<()>
+where
+    D: DatabaseRef,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    let ContractCalldata::Deployless { init_code, .. } = &call.calldata else { return Ok(()) };
+    let creation = ContractInput {
+        contract_address: Address::ZERO,
+        caller_address: call.caller_address,
+        calldata: ContractCalldata::Create(init_code.clone()),
+        env: CallEnv { value: U256::ZERO, blob_hashes: Vec::new(), ..call.env.clone() },
+        overrides: StateOverrides::new(),
+    };
+    let ResultAndState { result, state } =
+        new_evm(&mut *db, header, U256::ZERO, &creation).transact()?;
+    let ExecutionResult::Success { output: Output::Create(_, Some(address)), .. } = result else {
+        bail!("the deployment of the init code of the call failed: {:?}", result);
+    };
+    db.commit(state);
+
+    call.contract_address = address;
+    call.env.nonce = call.env.nonce.map(|nonce| nonce + 1);
+    Ok(())
 }
 
 /// TODO Add support for other chains besides Ethereum Mainnet.
@@ -143,7 +477,9 @@ where
     let EvmEnv { cfg_env, mut block_env, .. } = EthEvmConfig::new(chain_spec).evm_env(header);
 
     // Set the base fee to 0 to enable 0 gas price transactions.
-    block_env.basefee = U256::from(0);
+    if call.env.gas_price.is_zero() {
+        block_env.basefee = U256::from(0);
+    }
     block_env.difficulty = total_difficulty;
 
     let state = State::builder().with_database(db).build();
@@ -157,15 +493,32 @@ where
         .modify_block_env(|evm_block_env| *evm_block_env = block_env)
         .build();
 
-    let tx_env = evm.tx_mut();
-    tx_env.caller = call.caller_address;
-    tx_env.data = call.calldata.to_bytes();
-    tx_env.gas_limit = header.gas_limit;
-    // Set the gas price to 0 to avoid lack of funds (0) error.
-    tx_env.gas_price = U256::from(0);
-    tx_env.transact_to = match call.calldata {
-        ContractCalldata::Create(_) => TxKind::Create,
-        ContractCalldata::Call(_) => TxKind::Call(call.contract_address),
-    };
+    *evm.tx_mut() = tx_env(call, header);
     evm
 }
+
+/// The transaction environment of `call`, in the block of `header`.
+fn tx_env(call: &ContractInput, header: &Header) -> TxEnv {
+    let env = &call.env;
+    TxEnv {
+        caller: call.caller_address,
+        gas_limit: env.gas_limit.unwrap_or(header.gas_limit),
+        // Without a gas price, the caller pays nothing, to avoid lack of funds (0) error.
+        gas_price: env.gas_price,
+        gas_priority_fee: env.gas_priority_fee,
+        transact_to: match call.calldata {
+            ContractCalldata::Create(_) => TxKind::Create,
+            ContractCalldata::Call(_) | ContractCalldata::Deployless { .. } => {
+                TxKind::Call(call.contract_address)
+            }
+        },
+        value: env.value,
+        data: call.calldata.to_bytes(),
+        nonce: env.nonce,
+        access_list: env.access_list.clone(),
+        blob_hashes: env.blob_hashes.clone(),
+        // Only EIP-4844 calls have a max fee per blob gas.
+        max_fee_per_blob_gas: (!env.blob_hashes.is_empty()).then_some(env.max_fee_per_blob_gas),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,265 @@
+//! Merging of [`EVMStateSketch`]es of the same block, fetched separately.
+
+use std::{collections::BTreeSet, fmt};
+
+use revm_primitives::B256;
+use rsp_mpt::{
+    mpt::{MptNode, MptNodeData},
+    EthereumState,
+};
+
+use crate::io::EVMStateSketch;
+
+/// An error merging two [`EVMStateSketch`]es.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// The sketches are not for the same block.
+    Header {
+        /// The hash of the header of the first sketch.
+        expected: B256,
+        /// The hash of the header of the second sketch.
+        found: B256,
+    },
+    /// The sketches do not agree on an ancestor header.
+    AncestorHeader {
+        /// The number of the ancestor header.
+        number: u64,
+    },
+    /// Two tries that should be the same trie are not.
+    Trie {
+        /// The hash of the address of the account, for a storage trie, or `None` for the state
+        /// trie.
+        hashed_address: Option<B256>,
+        /// The root of the trie of the first sketch.
+        expected: B256,
+        /// The root of the trie of the second sketch.
+        found: B256,
+    },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header { expected, found } => {
+                write!(f, "cannot merge the sketch of block {} into block {}", found, expected)
+            }
+            Self::AncestorHeader { number } => {
+                write!(f, "the sketches have different ancestor headers {}", number)
+            }
+            Self::Trie { hashed_address: None, expected, found } => {
+                write!(f, "the state tries have different roots {} and {}", expected, found)
+            }
+            Self::Trie { hashed_address: Some(hashed_address), expected, found } => write!(
+                f,
+                "the storage tries of account hash {} have different roots {} and {}",
+                hashed_address, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl EVMStateSketch {
+    /// Merges two sketches of the same block into a sketch holding the state of both.
+    ///
+    /// The tries, the state requests and the bytecodes are unioned, and the longest chain of
+    /// ancestor headers is kept. Executing a call on the merged sketch is the same as executing it
+    /// on either sketch, so the calls of both can be run on it.
+    pub fn merge(&self, other: &Self) -> Result<Self, MergeError> {
+        if self.header != other.header {
+            return Err(MergeError::Header {
+                expected: self.header.hash_slow(),
+                found: other.header.hash_slow(),
+            });
+        }
+
+        // Both chains hash up to the same header, so the shorter one must be a prefix of the other.
+        let (longer, shorter) = if self.ancestor_headers.len() >= other.ancestor_headers.len() {
+            (&self.ancestor_headers, &other.ancestor_headers)
+        } else {
+            (&other.ancestor_headers, &self.ancestor_headers)
+        };
+        if let Some((ancestor, _)) = shorter.iter().zip(longer).find(|(a, b)| a != b) {
+            return Err(MergeError::AncestorHeader { number: ancestor.number });
+        }
+
+        let state_trie =
+            merge_tries(&self.state.state_trie, &other.state.state_trie).ok_or_else(|| {
+                MergeError::Trie {
+                    hashed_address: None,
+                    expected: self.state.state_trie.hash(),
+                    found: other.state.state_trie.hash(),
+                }
+            })?;
+        let mut storage_tries = self.state.storage_tries.clone();
+        for (hashed_address, trie) in &other.state.storage_tries {
+            let merged = match storage_tries.get(hashed_address) {
+                Some(existing) => merge_tries(existing, trie).ok_or_else(|| MergeError::Trie {
+                    hashed_address: Some(*hashed_address),
+                    expected: existing.hash(),
+                    found: trie.hash(),
+                })?,
+                None => trie.clone(),
+            };
+            storage_tries.insert(*hashed_address, merged);
+        }
+
+        let mut state_requests = self.state_requests.clone();
+        for (address, slots) in &other.state_requests {
+            let merged = state_requests.entry(*address).or_default();
+            merged.extend(slots);
+            merged.sort_unstable();
+            merged.dedup();
+        }
+
+        let mut code_hashes = BTreeSet::new();
+        let bytecodes = self
+            .bytecodes
+            .iter()
+            .chain(&other.bytecodes)
+            .filter(|code| code_hashes.insert(code.hash_slow()))
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            header: self.header.clone(),
+            ancestor_headers: longer.clone(),
+            state: EthereumState { state_trie, storage_tries },
+            state_requests,
+            bytecodes,
+        })
+    }
+}
+
+/// Merges two partial views of the same trie, resolving every node resolved in either.
+///
+/// Returns `None` if the tries are not the same.
+fn merge_tries(a: &MptNode, b: &MptNode) -> Option<MptNode> {
+    match (a.as_data(), b.as_data()) {
+        (MptNodeData::Digest(_), _) | (_, MptNodeData::Digest(_)) => {
+            let (digest, other) =
+                if matches!(a.as_data(), MptNodeData::Digest(_)) { (a, b) } else { (b, a) };
+            (digest.hash() == other.hash()).then(|| other.clone())
+        }
+        (MptNodeData::Branch(a_children), MptNodeData::Branch(b_children)) => {
+            let mut children: [Option<Box<MptNode>>; 16] = Default::default();
+            for (child, (a_child, b_child)) in
+                children.iter_mut().zip(a_children.iter().zip(b_children))
+            {
+                *child = match (a_child, b_child) {
+                    (Some(a_child), Some(b_child)) => {
+                        Some(Box::new(merge_tries(a_child, b_child)?))
+                    }
+                    (None, None) => None,
+                    _ => return None,
+                };
+            }
+            Some(MptNodeData::Branch(children).into())
+        }
+        (MptNodeData::Extension(a_prefix, a_child), MptNodeData::Extension(b_prefix, b_child))
+            if a_prefix == b_prefix =>
+        {
+            let child = merge_tries(a_child, b_child)?;
+            Some(MptNodeData::Extension(a_prefix.clone(), Box::new(child)).into())
+        }
+        (MptNodeData::Null, MptNodeData::Null) => Some(a.clone()),
+        (MptNodeData::Leaf(..), MptNodeData::Leaf(..)) => {
+            (a.as_data() == b.as_data()).then(|| a.clone())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm_primitives::{keccak256, U256};
+
+    use super::*;
+    use crate::{
+        test_utils::{counters, increment, partial_sketch, sketch, COUNTER_A, COUNTER_B},
+        ClientExecutor,
+    };
+
+    #[test]
+    fn test_merge_disjoint_sketches() {
+        let accounts = counters(5, 7);
+        let a = partial_sketch(&accounts, &[COUNTER_A]);
+        let b = partial_sketch(&accounts, &[COUNTER_B]);
+
+        let merged = a.merge(&b).unwrap();
+        assert_eq!(merged.validate(), Ok(()));
+        assert_eq!(merged.content_hash(), sketch(&accounts).content_hash());
+        assert_eq!(b.merge(&a).unwrap().content_hash(), merged.content_hash());
+
+        // The calls of both sketches run on the merged one.
+        let executor = ClientExecutor::new(&merged).unwrap();
+        for (counter, expected) in [(COUNTER_A, 6), (COUNTER_B, 8)] {
+            let public_values = executor.execute(increment(counter)).unwrap();
+            assert_eq!(U256::from_be_slice(&public_values.contractOutput), U256::from(expected));
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_the_longest_ancestors() {
+        let accounts = counters(5, 7);
+        let a = partial_sketch(&accounts, &[COUNTER_A]);
+        let mut b = partial_sketch(&accounts, &[COUNTER_B]);
+        b.ancestor_headers.truncate(1);
+
+        assert_eq!(b.merge(&a).unwrap().ancestor_headers, a.ancestor_headers);
+    }
+
+    #[test]
+    fn test_merge_different_tries() {
+        // The sketches of two different states of the same block cannot be merged.
+        let a = partial_sketch(&counters(5, 7), &[COUNTER_A]);
+        let mut other_state = partial_sketch(&counters(6, 7), &[COUNTER_A]);
+        other_state.header = a.header.clone();
+        assert_eq!(
+            a.merge(&other_state),
+            Err(MergeError::Trie {
+                hashed_address: None,
+                expected: a.state.state_trie.hash(),
+                found: other_state.state.state_trie.hash(),
+            })
+        );
+
+        let hashed_address = keccak256(COUNTER_A);
+        let mut other_storage = a.clone();
+        other_storage.state.storage_tries.insert(hashed_address, MptNode::default());
+        assert_eq!(
+            a.merge(&other_storage),
+            Err(MergeError::Trie {
+                hashed_address: Some(hashed_address),
+                expected: a.state.storage_tries[&hashed_address].hash(),
+                found: other_storage.state.storage_tries[&hashed_address].hash(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_different_blocks() {
+        let a = sketch(&counters(5, 7));
+        let mut other_block = a.clone();
+        other_block.header.number += 1;
+        assert_eq!(
+            a.merge(&other_block),
+            Err(MergeError::Header {
+                expected: a.header.hash_slow(),
+                found: other_block.header.hash_slow(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_different_ancestors() {
+        let a = sketch(&counters(5, 7));
+        let mut other_ancestors = a.clone();
+        other_ancestors.ancestor_headers[1].gas_limit += 1;
+        assert_eq!(
+            a.merge(&other_ancestors),
+            Err(MergeError::AncestorHeader { number: 21_999_998 })
+        );
+    }
+}
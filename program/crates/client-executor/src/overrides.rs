@@ -0,0 +1,131 @@
+//! Overrides of the state a call runs on, like the state overrides of `eth_call`.
+//!
+//! The overrides are applied to the [`CacheDB`] of the call, on top of the state of the block: the
+//! sketch is not modified, and the accounts overridden are still read from it. The overrides are
+//! committed in the public values of the call, so that a verifier knows exactly which state it ran
+//! on.
+
+use std::collections::BTreeMap;
+
+use alloy_sol_types::sol;
+use revm::{
+    db::{AccountState, CacheDB},
+    DatabaseRef,
+};
+use revm_primitives::{Address, Bytecode, Bytes, B256, U256};
+
+/// The overrides of the state of an account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountOverride {
+    /// The balance of the account.
+    pub balance: Option<U256>,
+    /// The nonce of the account.
+    pub nonce: Option<u64>,
+    /// The code of the account.
+    pub code: Option<Bytes>,
+    /// The whole storage of the account: the slots not set are zero.
+    pub state: Option<BTreeMap<B256, B256>>,
+    /// Storage slots to set, on top of the storage of the account or of `state`.
+    pub state_diff: BTreeMap<B256, B256>,
+}
+
+/// The overrides of the state of every account, by address.
+pub type StateOverrides = BTreeMap<Address, AccountOverride>;
+
+sol! {
+    /// The value a storage slot is overridden with.
+    struct StorageOverride {
+        bytes32 slot;
+        bytes32 value;
+    }
+
+    /// The overrides of the state of an account, as committed in the public values of a call.
+    ///
+    /// A field is only overridden if its flag is set. With `replaceStorage`, the slots not in
+    /// `slots` are zero.
+    struct AccountStateOverride {
+        address account;
+        bool overrideBalance;
+        uint256 balance;
+        bool overrideNonce;
+        uint64 nonce;
+        bool overrideCode;
+        bytes code;
+        bool replaceStorage;
+        StorageOverride[] slots;
+    }
+}
+
+impl AccountOverride {
+    /// The storage slots set by this override, `state_diff` taking precedence over `state`.
+    pub fn storage(&self) -> BTreeMap<B256, B256> {
+        let mut storage = self.state.clone().unwrap_or_default();
+        storage.extend(&self.state_diff);
+        storage
+    }
+}
+
+/// The overrides `overrides`, as committed in the public values of a call.
+pub fn committed_overrides(overrides: &StateOverrides) -> Vec<AccountStateOverride> {
+    overrides
+        .iter()
+        .map(|(address, account)| AccountStateOverride {
+            account: *address,
+            overrideBalance: account.balance.is_some(),
+            balance: account.balance.unwrap_or_default(),
+            overrideNonce: account.nonce.is_some(),
+            nonce: account.nonce.unwrap_or_default(),
+            overrideCode: account.code.is_some(),
+            code: account.code.clone().unwrap_or_default(),
+            replaceStorage: account.state.is_some(),
+            slots: account
+                .storage()
+                .into_iter()
+                .map(|(slot, value)| StorageOverride { slot, value })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Applies `overrides` to the state of `db`.
+///
+/// The accounts overridden are loaded from the database underneath first, so that the fields
+/// which are not overridden keep their value.
+pub fn apply_overrides<D: DatabaseRef>(
+    db: &mut CacheDB<D>,
+    overrides: &StateOverrides,
+) -> Result<(), D::Error> {
+    for (address, account) in overrides {
+        let code = account.code.as_ref().map(|code| Bytecode::new_raw(code.clone()));
+        if let Some(code) = &code {
+            db.contracts.insert(code.hash_slow(), code.clone());
+        }
+
+        let db_account = db.load_account(*address)?;
+        // An account created by the overrides has no storage to read from the database.
+        if db_account.account_state == AccountState::NotExisting {
+            db_account.account_state = AccountState::StorageCleared;
+        }
+        if let Some(balance) = account.balance {
+            db_account.info.balance = balance;
+        }
+        if let Some(nonce) = account.nonce {
+            db_account.info.nonce = nonce;
+        }
+        if let Some(code) = code {
+            db_account.info.code_hash = code.hash_slow();
+            db_account.info.code = Some(code);
+        }
+        if account.state.is_some() {
+            db_account.storage.clear();
+            db_account.account_state = AccountState::StorageCleared;
+        }
+        db_account.storage.extend(
+            account
+                .storage()
+                .into_iter()
+                .map(|(slot, value)| (U256::from_be_bytes(slot.0), U256::from_be_bytes(value.0))),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Synthetic sketches for the unit tests, built from a full state without any RPC.
+
+use std::collections::BTreeMap;
+
+use alloy_trie::{TrieAccount, KECCAK_EMPTY};
+use reth_primitives::Header;
+use revm_primitives::{address, hex, keccak256, Address, Bytecode, Bytes, HashMap, B256, U256};
+use rsp_mpt::{
+    mpt::{MptNode, MptNodeData, MptNodeReference},
+    EthereumState,
+};
+
+use crate::{io::EVMStateSketch, CallEnv, ContractCalldata, ContractInput};
+
+/// A counter contract, whose hashed address starts with the nibble `5`.
+pub(crate) const COUNTER_A: Address = address!("00000000000000000000000000000000000000aa");
+/// A counter contract, whose hashed address starts with the nibble `d`.
+pub(crate) const COUNTER_B: Address = address!("00000000000000000000000000000000000000a3");
+
+/// Increments the value of slot 0, and returns it.
+const COUNTER_CODE: [u8; 18] = hex!("6000546001018060005560005260206000f3");
+
+/// An account of a synthetic state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TestAccount {
+    pub(crate) balance: U256,
+    pub(crate) code: Bytes,
+    pub(crate) storage: BTreeMap<U256, U256>,
+}
+
+impl TestAccount {
+    /// A counter contract whose slot 0 holds `value`.
+    pub(crate) fn counter(value: u64) -> Self {
+        Self {
+            balance: U256::ZERO,
+            code: Bytes::from_static(&COUNTER_CODE),
+            storage: BTreeMap::from([(U256::ZERO, U256::from(value))]),
+        }
+    }
+}
+
+/// The state of [`COUNTER_A`] holding `a` and [`COUNTER_B`] holding `b`.
+pub(crate) fn counters(a: u64, b: u64) -> BTreeMap<Address, TestAccount> {
+    BTreeMap::from([(COUNTER_A, TestAccount::counter(a)), (COUNTER_B, TestAccount::counter(b))])
+}
+
+/// A call incrementing the counter at `address`.
+pub(crate) fn increment(address: Address) -> ContractInput {
+    ContractInput {
+        contract_address: address,
+        caller_address: Address::default(),
+        calldata: ContractCalldata::Call(Bytes::new()),
+        env: CallEnv::default(),
+        overrides: Default::default(),
+    }
+}
+
+/// A sketch of the state `accounts` requesting every account and storage slot, on a block with two
+/// ancestor headers.
+pub(crate) fn sketch(accounts: &BTreeMap<Address, TestAccount>) -> EVMStateSketch {
+    partial_sketch(accounts, &accounts.keys().copied().collect::<Vec<_>>())
+}
+
+/// A sketch of the state `accounts` only requesting the accounts `requested`, and all their
+/// storage slots.
+///
+/// The branches of the root of the state trie leading to the other accounts are replaced by their
+/// hash, so the requested accounts must not share the first nibble of their hashed address with
+/// the other ones.
+pub(crate) fn partial_sketch(
+    accounts: &BTreeMap<Address, TestAccount>,
+    requested: &[Address],
+) -> EVMStateSketch {
+    let mut state_trie = MptNode::default();
+    let mut storage_tries = HashMap::default();
+    for (address, account) in accounts {
+        let mut storage_trie = MptNode::default();
+        for (slot, value) in account.storage.iter().filter(|(_, value)| !value.is_zero()) {
+            storage_trie.insert_rlp(keccak256(B256::from(*slot)).as_slice(), *value).unwrap();
+        }
+        let code_hash =
+            if account.code.is_empty() { KECCAK_EMPTY } else { keccak256(&account.code) };
+        let trie_account = TrieAccount {
+            nonce: 0,
+            balance: account.balance,
+            storage_root: storage_trie.hash(),
+            code_hash,
+        };
+        state_trie.insert_rlp(keccak256(address).as_slice(), trie_account).unwrap();
+        if requested.contains(address) {
+            storage_tries.insert(keccak256(address), storage_trie);
+        }
+    }
+
+    let state_trie = match state_trie.as_data() {
+        MptNodeData::Branch(children) => {
+            let mut children = children.clone();
+            for (nibble, child) in children.iter_mut().enumerate() {
+                let Some(node) = child else { continue };
+                if requested.iter().any(|address| usize::from(keccak256(address)[0] >> 4) == nibble)
+                {
+                    continue;
+                }
+                if let MptNodeReference::Digest(hash) = node.reference() {
+                    **node = MptNodeData::Digest(hash).into();
+                }
+            }
+            MptNodeData::Branch(children).into()
+        }
+        _ => state_trie.clone(),
+    };
+
+    let state_requests = requested
+        .iter()
+        .map(|address| (*address, accounts[address].storage.keys().copied().collect()))
+        .collect();
+    // Sorted by hash and deduplicated, as in a decoded sketch.
+    let bytecodes = requested
+        .iter()
+        .map(|address| &accounts[address].code)
+        .filter(|code| !code.is_empty())
+        .map(|code| (keccak256(code), Bytecode::new_raw(code.clone())))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect();
+
+    let (header, ancestor_headers) = headers(state_trie.hash());
+    EVMStateSketch {
+        header,
+        ancestor_headers,
+        state: EthereumState { state_trie, storage_tries },
+        state_requests,
+        bytecodes,
+    }
+}
+
+/// A header with state root `state_root`, and its two ancestor headers, newest first.
+fn headers(state_root: B256) -> (Header, Vec<Header>) {
+    let grandparent = Header { number: 21_999_998, ..Default::default() };
+    let parent =
+        Header { number: 21_999_999, parent_hash: grandparent.hash_slow(), ..Default::default() };
+    let header = Header {
+        number: 22_000_000,
+        parent_hash: parent.hash_slow(),
+        timestamp: 1_750_000_000,
+        gas_limit: 30_000_000,
+        base_fee_per_gas: Some(1_000_000_000),
+        excess_blob_gas: Some(0),
+        state_root,
+        ..Default::default()
+    };
+    (header, vec![parent, grandparent])
+}
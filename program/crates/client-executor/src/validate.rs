@@ -0,0 +1,364 @@
+//! Validation of an [`EVMStateSketch`] on its own, before it is handed to the client.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use alloy_trie::{TrieAccount, EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use revm_primitives::{keccak256, Address, B256, U256};
+
+use crate::io::EVMStateSketch;
+
+/// An inconsistency in an [`EVMStateSketch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SketchError {
+    /// An ancestor header does not have the number preceding its child's.
+    AncestorNumber {
+        /// The number of the ancestor header.
+        number: u64,
+        /// The number preceding its child's.
+        expected: u64,
+    },
+    /// An ancestor header does not hash to the parent hash of its child.
+    AncestorHash {
+        /// The number of the ancestor header.
+        number: u64,
+        /// The hash of the ancestor header.
+        hash: B256,
+        /// The parent hash of its child.
+        expected: B256,
+    },
+    /// The state trie does not have the state root of the header.
+    StateRoot {
+        /// The root of the state trie.
+        root: B256,
+        /// The state root of the header.
+        expected: B256,
+    },
+    /// The state trie does not resolve the path of a requested account.
+    UnprovableAccount {
+        /// The account.
+        address: Address,
+    },
+    /// The storage trie of a requested account with storage is missing.
+    MissingStorageTrie {
+        /// The account.
+        address: Address,
+    },
+    /// A storage trie does not have the storage root of its account.
+    StorageRoot {
+        /// The hash of the address of the account.
+        hashed_address: B256,
+        /// The root of the storage trie.
+        root: B256,
+        /// The storage root of the account.
+        expected: B256,
+    },
+    /// The storage trie of an account does not resolve the path of a requested slot.
+    UnprovableSlot {
+        /// The account.
+        address: Address,
+        /// The slot.
+        slot: U256,
+    },
+    /// The bytecode of a requested account is missing.
+    MissingBytecode {
+        /// The account.
+        address: Address,
+        /// The code hash of the account.
+        code_hash: B256,
+    },
+    /// A storage trie is not the storage of any requested account.
+    SuperfluousStorageTrie {
+        /// The hash of the address of the account.
+        hashed_address: B256,
+    },
+    /// A bytecode is not the code of any requested account, or is included twice.
+    SuperfluousBytecode {
+        /// The hash of the bytecode.
+        code_hash: B256,
+    },
+}
+
+impl fmt::Display for SketchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AncestorNumber { number, expected } => {
+                write!(f, "ancestor header {} should be header {}", number, expected)
+            }
+            Self::AncestorHash { number, hash, expected } => write!(
+                f,
+                "ancestor header {} has hash {}, but its child has parent hash {}",
+                number, hash, expected
+            ),
+            Self::StateRoot { root, expected } => {
+                write!(f, "the state trie has root {}, but the header has {}", root, expected)
+            }
+            Self::UnprovableAccount { address } => {
+                write!(f, "account {} cannot be proven from the state trie", address)
+            }
+            Self::MissingStorageTrie { address } => {
+                write!(f, "the storage trie of account {} is missing", address)
+            }
+            Self::StorageRoot { hashed_address, root, expected } => write!(
+                f,
+                "the storage trie of account hash {} has root {}, but the account has {}",
+                hashed_address, root, expected
+            ),
+            Self::UnprovableSlot { address, slot } => write!(
+                f,
+                "storage slot {} of account {} cannot be proven from its storage trie",
+                slot, address
+            ),
+            Self::MissingBytecode { address, code_hash } => {
+                write!(f, "the bytecode {} of account {} is missing", code_hash, address)
+            }
+            Self::SuperfluousStorageTrie { hashed_address } => write!(
+                f,
+                "the storage trie of account hash {} is not the storage of a requested account",
+                hashed_address
+            ),
+            Self::SuperfluousBytecode { code_hash } => {
+                write!(f, "bytecode {} is not the code of a requested account", code_hash)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SketchError {}
+
+impl EVMStateSketch {
+    /// Checks that the sketch is self-consistent, without executing anything.
+    ///
+    /// The ancestor headers must chain up to the header, and every requested account and storage
+    /// slot must be provable from the state, against the state root of the header. The bytecodes
+    /// must be exactly the code of the requested accounts, and the storage tries exactly the
+    /// storage of the requested accounts.
+    ///
+    /// Only the first inconsistency found is returned.
+    pub fn validate(&self) -> Result<(), SketchError> {
+        let mut child = &self.header;
+        for ancestor in &self.ancestor_headers {
+            let expected = child.number.wrapping_sub(1);
+            if ancestor.number != expected {
+                return Err(SketchError::AncestorNumber { number: ancestor.number, expected });
+            }
+            let hash = ancestor.hash_slow();
+            if hash != child.parent_hash {
+                return Err(SketchError::AncestorHash {
+                    number: ancestor.number,
+                    hash,
+                    expected: child.parent_hash,
+                });
+            }
+            child = ancestor;
+        }
+
+        let root = self.state.state_trie.hash();
+        if root != self.header.state_root {
+            return Err(SketchError::StateRoot { root, expected: self.header.state_root });
+        }
+
+        // Resolve the requested accounts, in order so that the first error is deterministic.
+        let state_requests = self.state_requests.iter().collect::<BTreeMap<_, _>>();
+        let mut accounts = BTreeMap::new();
+        for (address, slots) in &state_requests {
+            let hashed_address = keccak256(address);
+            let account = self
+                .state
+                .state_trie
+                .get_rlp::<TrieAccount>(hashed_address.as_slice())
+                .map_err(|_| SketchError::UnprovableAccount { address: **address })?;
+            let storage_root = account.map_or(EMPTY_ROOT_HASH, |account| account.storage_root);
+
+            match self.state.storage_tries.get(&hashed_address) {
+                Some(storage_trie) => {
+                    let root = storage_trie.hash();
+                    if root != storage_root {
+                        return Err(SketchError::StorageRoot {
+                            hashed_address,
+                            root,
+                            expected: storage_root,
+                        });
+                    }
+                    for slot in slots.iter() {
+                        let hashed_slot = keccak256(B256::from(*slot));
+                        storage_trie.get_rlp::<U256>(hashed_slot.as_slice()).map_err(|_| {
+                            SketchError::UnprovableSlot { address: **address, slot: *slot }
+                        })?;
+                    }
+                }
+                None if storage_root != EMPTY_ROOT_HASH && !slots.is_empty() => {
+                    return Err(SketchError::MissingStorageTrie { address: **address });
+                }
+                None => {}
+            }
+            accounts.insert(hashed_address, (**address, account));
+        }
+
+        let mut hashed_addresses = self.state.storage_tries.keys().collect::<Vec<_>>();
+        hashed_addresses.sort_unstable();
+        if let Some(hashed_address) = hashed_addresses
+            .into_iter()
+            .find(|hashed_address| !accounts.contains_key(*hashed_address))
+        {
+            return Err(SketchError::SuperfluousStorageTrie { hashed_address: *hashed_address });
+        }
+
+        // The empty bytecode is the code of every account without code: it is never superfluous.
+        let mut code_hashes = BTreeSet::new();
+        for code in &self.bytecodes {
+            let code_hash = code.hash_slow();
+            if code_hash != KECCAK_EMPTY && !code_hashes.insert(code_hash) {
+                return Err(SketchError::SuperfluousBytecode { code_hash });
+            }
+        }
+        let mut needed = BTreeSet::new();
+        for (address, account) in accounts.values() {
+            let Some(account) = account else { continue };
+            if account.code_hash == KECCAK_EMPTY {
+                continue;
+            }
+            if !code_hashes.contains(&account.code_hash) {
+                return Err(SketchError::MissingBytecode {
+                    address: *address,
+                    code_hash: account.code_hash,
+                });
+            }
+            needed.insert(account.code_hash);
+        }
+        if let Some(code_hash) = code_hashes.difference(&needed).next() {
+            return Err(SketchError::SuperfluousBytecode { code_hash: *code_hash });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm_primitives::{Bytecode, Bytes};
+    use rsp_mpt::mpt::{MptNode, MptNodeData};
+
+    use super::*;
+    use crate::test_utils::{counters, partial_sketch, sketch, COUNTER_A, COUNTER_B};
+
+    #[test]
+    fn test_valid_sketches() {
+        let accounts = counters(5, 7);
+        assert_eq!(sketch(&accounts).validate(), Ok(()));
+        assert_eq!(partial_sketch(&accounts, &[COUNTER_A]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_ancestor_number() {
+        let mut sketch = sketch(&counters(5, 7));
+        sketch.ancestor_headers[0].number += 1;
+        assert_eq!(
+            sketch.validate(),
+            Err(SketchError::AncestorNumber { number: 22_000_000, expected: 21_999_999 })
+        );
+    }
+
+    #[test]
+    fn test_ancestor_hash() {
+        let mut sketch = sketch(&counters(5, 7));
+        sketch.ancestor_headers[1].gas_limit += 1;
+        assert_eq!(
+            sketch.validate(),
+            Err(SketchError::AncestorHash {
+                number: 21_999_998,
+                hash: sketch.ancestor_headers[1].hash_slow(),
+                expected: sketch.ancestor_headers[0].parent_hash,
+            })
+        );
+    }
+
+    #[test]
+    fn test_state_root() {
+        let mut sketch = sketch(&counters(5, 7));
+        let root = sketch.header.state_root;
+        sketch.header.state_root = B256::ZERO;
+        assert_eq!(sketch.validate(), Err(SketchError::StateRoot { root, expected: B256::ZERO }));
+    }
+
+    #[test]
+    fn test_unprovable_account() {
+        // The branch of the state trie leading to the other counter is only a hash.
+        let mut sketch = partial_sketch(&counters(5, 7), &[COUNTER_A]);
+        sketch.state_requests.insert(COUNTER_B, Vec::new());
+        assert_eq!(sketch.validate(), Err(SketchError::UnprovableAccount { address: COUNTER_B }));
+    }
+
+    #[test]
+    fn test_missing_storage_trie() {
+        let mut sketch = sketch(&counters(5, 7));
+        sketch.state.storage_tries.remove(&keccak256(COUNTER_A));
+        assert_eq!(sketch.validate(), Err(SketchError::MissingStorageTrie { address: COUNTER_A }));
+    }
+
+    #[test]
+    fn test_storage_root() {
+        let mut sketch = sketch(&counters(5, 7));
+        let hashed_address = keccak256(COUNTER_A);
+        let expected = sketch.state.storage_tries[&hashed_address].hash();
+        sketch.state.storage_tries.insert(hashed_address, MptNode::default());
+        assert_eq!(
+            sketch.validate(),
+            Err(SketchError::StorageRoot { hashed_address, root: EMPTY_ROOT_HASH, expected })
+        );
+    }
+
+    #[test]
+    fn test_unprovable_slot() {
+        // A storage trie reduced to its root hash has the right root, but proves no slot.
+        let mut sketch = sketch(&counters(5, 7));
+        let hashed_address = keccak256(COUNTER_A);
+        let root = sketch.state.storage_tries[&hashed_address].hash();
+        sketch.state.storage_tries.insert(hashed_address, MptNodeData::Digest(root).into());
+        assert_eq!(
+            sketch.validate(),
+            Err(SketchError::UnprovableSlot { address: COUNTER_A, slot: U256::ZERO })
+        );
+    }
+
+    #[test]
+    fn test_missing_bytecode() {
+        let mut sketch = sketch(&counters(5, 7));
+        let code_hash = sketch.bytecodes[0].hash_slow();
+        sketch.bytecodes.clear();
+        assert_eq!(
+            sketch.validate(),
+            Err(SketchError::MissingBytecode { address: COUNTER_A, code_hash })
+        );
+    }
+
+    #[test]
+    fn test_superfluous_storage_trie() {
+        let mut sketch = partial_sketch(&counters(5, 7), &[COUNTER_A]);
+        let hashed_address = keccak256(COUNTER_B);
+        sketch.state.storage_tries.insert(hashed_address, MptNode::default());
+        assert_eq!(sketch.validate(), Err(SketchError::SuperfluousStorageTrie { hashed_address }));
+    }
+
+    #[test]
+    fn test_superfluous_bytecode() {
+        let mut with_other_code = sketch(&counters(5, 7));
+        let code = Bytecode::new_raw(Bytes::from_static(&[0x00]));
+        with_other_code.bytecodes.push(code.clone());
+        assert_eq!(
+            with_other_code.validate(),
+            Err(SketchError::SuperfluousBytecode { code_hash: code.hash_slow() })
+        );
+
+        // Including a bytecode twice is superfluous too.
+        let mut with_code_twice = sketch(&counters(5, 7));
+        let code = with_code_twice.bytecodes[0].clone();
+        with_code_twice.bytecodes.push(code.clone());
+        assert_eq!(
+            with_code_twice.validate(),
+            Err(SketchError::SuperfluousBytecode { code_hash: code.hash_slow() })
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! An on-disk cache of RPC responses.
+//!
+//! Responses are content-addressed by `(chain id, block hash, method, params)`, so that a cached
+//! response is only reused for a request pinned to the very same block. Requests pinned to a block
+//! number are resolved to a block hash through an index of the blocks seen so far, which is filled
+//! from the `eth_getBlockByNumber` and `eth_getBlockByHash` responses. Requests for a block tag such
+//! as `latest` are never served from the cache.
+//!
+//! A block number may be reorged to another block after it was indexed, so unless the cache is
+//! offline, `eth_getBlockByNumber` is never served from the cache: its response refreshes the
+//! index instead, and the requests resolved through the index after it use the canonical block.
+//!
+//! Since the [`HostExecutor`] fetches its block before anything else, running it against a block
+//! number once is enough to replay the run offline later.
+//!
+//! The cache is laid out as
+//!
+//! ```text
+//! <dir>/<chain id>/blocks/<block number>   the hash of the block
+//! <dir>/<chain id>/<key>.json              a response
+//! ```
+//!
+//! where `key = keccak256(block hash, method, params)`.
+//!
+//! [`HostExecutor`]: crate::HostExecutor
+
+use std::{
+    path::{Path, PathBuf},
+    task::{Context, Poll},
+};
+
+use alloy_json_rpc::{RequestPacket, Response, ResponsePacket, ResponsePayload, SerializedRequest};
+use alloy_primitives::{keccak256, B256};
+use alloy_provider::{
+    network::{AnyNetwork, Network},
+    Provider, RootProvider,
+};
+use alloy_rpc_client::ClientBuilder;
+use alloy_transport::{TransportError, TransportErrorKind, TransportFut};
+use serde::{Deserialize, Serialize};
+use serde_json::{value::RawValue, Value};
+use tower::{Layer, Service};
+use url::Url;
+
+use crate::RetryConfig;
+
+/// Methods whose result does not depend on a block.
+const BLOCK_INDEPENDENT_METHODS: &[&str] = &["eth_chainId"];
+
+/// How the RPC responses of a chain are cached on disk.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// The directory holding the cache.
+    pub dir: PathBuf,
+    /// The chain the requests are sent to.
+    pub chain_id: u64,
+    /// If set, requests missing from the cache fail instead of being sent.
+    pub offline: bool,
+}
+
+impl CacheConfig {
+    /// Create a [`CacheConfig`] caching the responses of `chain_id` in `dir`.
+    pub fn new(dir: impl Into<PathBuf>, chain_id: u64) -> Self {
+        Self { dir: dir.into(), chain_id, offline: false }
+    }
+
+    /// Only serve requests from the cache.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    /// Create a [`CacheLayer`] with this configuration.
+    pub fn layer(&self) -> CacheLayer {
+        CacheLayer {
+            store: CacheStore { dir: self.dir.join(self.chain_id.to_string()) },
+            offline: self.offline,
+        }
+    }
+
+    /// Create an HTTP provider whose responses are cached, and whose requests missing from the
+    /// cache are retried as configured by `retry`.
+    pub fn http_provider<N: Network>(&self, url: Url, retry: &RetryConfig) -> RootProvider<N> {
+        RootProvider::new(
+            ClientBuilder::default().layer(self.layer()).layer(retry.layer()).http(url),
+        )
+    }
+}
+
+/// A recorded RPC fixture for tests: the responses needed to run some calls at a pinned block.
+///
+/// The fixture directory holds the number of the pinned block in a `block` file, next to a
+/// response cache. Once recorded, it can be checked in and replayed without any RPC.
+#[derive(Debug, Clone)]
+pub struct RpcFixture {
+    /// The provider replaying or recording the fixture.
+    pub provider: RootProvider<AnyNetwork>,
+    /// The block the fixture is pinned to.
+    pub block_number: u64,
+}
+
+impl RpcFixture {
+    /// Replay the fixture in `dir`, or record it from `url` at the latest block if it does not
+    /// exist yet.
+    pub async fn replay_or_record(
+        dir: &Path,
+        chain_id: u64,
+        url: Option<Url>,
+    ) -> eyre::Result<Self> {
+        let cache = CacheConfig::new(dir, chain_id);
+        let block_path = dir.join("block");
+
+        if block_path.exists() {
+            let block_number = std::fs::read_to_string(&block_path)?.trim().parse()?;
+            let layer = cache.offline().layer();
+            let provider = RootProvider::new(
+                ClientBuilder::default().layer(layer).transport(OfflineTransport, true),
+            );
+            return Ok(Self { provider, block_number });
+        }
+
+        let url = url.ok_or_else(|| {
+            eyre::eyre!("no RPC fixture in {}, and no RPC to record it from", dir.display())
+        })?;
+        tracing::info!("recording an RPC fixture to {}", dir.display());
+        let provider = cache.http_provider(url, &RetryConfig::default());
+        let block_number = provider.get_block_number().await?;
+        write_atomic(&block_path, format!("{}\n", block_number).as_bytes())?;
+        Ok(Self { provider, block_number })
+    }
+}
+
+/// A transport failing every request, behind an offline [`CacheLayer`].
+#[derive(Debug, Clone, Copy)]
+struct OfflineTransport;
+
+impl Service<RequestPacket> for OfflineTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: RequestPacket) -> Self::Future {
+        Box::pin(async { Err(TransportErrorKind::custom_str("the RPC is offline")) })
+    }
+}
+
+/// A transport layer serving requests from an on-disk cache, and caching the responses of the
+/// requests it forwards.
+#[derive(Debug, Clone)]
+pub struct CacheLayer {
+    store: CacheStore,
+    offline: bool,
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = CacheService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService { inner, store: self.store.clone(), offline: self.offline }
+    }
+}
+
+/// The service created by a [`CacheLayer`].
+#[derive(Debug, Clone)]
+pub struct CacheService<S> {
+    inner: S,
+    store: CacheStore,
+    offline: bool,
+}
+
+impl<S> Service<RequestPacket> for CacheService<S>
+where
+    S: Service<RequestPacket, Future = TransportFut<'static>, Error = TransportError>
+        + Send
+        + Clone
+        + 'static,
+{
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let store = self.store.clone();
+        let offline = self.offline;
+
+        Box::pin(async move {
+            let requests = match &request {
+                RequestPacket::Single(request) => std::slice::from_ref(request),
+                RequestPacket::Batch(requests) => requests.as_slice(),
+            };
+
+            // Only serve the packet from the cache if every request in it is cached.
+            let cached = requests
+                .iter()
+                .map(|request| {
+                    if !offline && request.method() == "eth_getBlockByNumber" {
+                        return None;
+                    }
+                    store.get(request).map(|result| cached_response(request, result))
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(mut responses) = cached {
+                return Ok(match request {
+                    RequestPacket::Single(_) => ResponsePacket::Single(responses.remove(0)),
+                    RequestPacket::Batch(_) => ResponsePacket::Batch(responses),
+                });
+            }
+            if offline {
+                let missing = requests.iter().find(|request| store.get(request).is_none());
+                return Err(TransportErrorKind::custom_str(&format!(
+                    "{} is not in the RPC cache",
+                    missing.map(|request| request.method()).unwrap_or_default()
+                )));
+            }
+
+            let response = inner.call(request.clone()).await?;
+            let responses = match &response {
+                ResponsePacket::Single(response) => std::slice::from_ref(response),
+                ResponsePacket::Batch(responses) => responses.as_slice(),
+            };
+            for response in responses {
+                let ResponsePayload::Success(result) = &response.payload else { continue };
+                let Some(request) = requests.iter().find(|request| request.id() == &response.id)
+                else {
+                    continue;
+                };
+                // The cache is best effort: failing to write it must not fail the request.
+                if let Err(e) = store.put(request, result) {
+                    tracing::warn!("failed to cache the {} response: {}", request.method(), e);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// A cached response.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    method: String,
+    params: Option<Box<RawValue>>,
+    result: Box<RawValue>,
+}
+
+/// The on-disk cache of a single chain.
+#[derive(Debug, Clone)]
+struct CacheStore {
+    dir: PathBuf,
+}
+
+impl CacheStore {
+    /// The cached result of `request`, if any.
+    fn get(&self, request: &SerializedRequest) -> Option<Box<RawValue>> {
+        let path = self.entry_path(request)?;
+        let entry: CacheEntry = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+        Some(entry.result)
+    }
+
+    /// Cache the `result` of `request`, if it is pinned to a known block.
+    fn put(&self, request: &SerializedRequest, result: &RawValue) -> eyre::Result<()> {
+        // A missing block or account proof may still show up later, so nulls are never cached.
+        if result.get() == "null" {
+            return Ok(());
+        }
+        if matches!(request.method(), "eth_getBlockByNumber" | "eth_getBlockByHash") {
+            self.index_block(result)?;
+        }
+        let Some(path) = self.entry_path(request) else { return Ok(()) };
+        let entry = CacheEntry {
+            method: request.method().to_string(),
+            params: request.params().map(|params| params.to_owned()),
+            result: result.to_owned(),
+        };
+        write_atomic(&path, &serde_json::to_vec(&entry)?)
+    }
+
+    /// Record the hash of the block returned by an `eth_getBlockBy*` request.
+    fn index_block(&self, block: &RawValue) -> eyre::Result<()> {
+        #[derive(Deserialize)]
+        struct BlockId {
+            hash: B256,
+            number: alloy_primitives::U64,
+        }
+
+        // A `null` result means the block does not exist (yet).
+        let Ok(block) = serde_json::from_str::<BlockId>(block.get()) else { return Ok(()) };
+        write_atomic(&self.block_path(block.number.to()), block.hash.to_string().as_bytes())
+    }
+
+    /// The path of the cached response to `request`, if the request is pinned to a known block.
+    fn entry_path(&self, request: &SerializedRequest) -> Option<PathBuf> {
+        let block_hash = if BLOCK_INDEPENDENT_METHODS.contains(&request.method()) {
+            B256::ZERO
+        } else {
+            let params: Vec<Value> = serde_json::from_str(request.params()?.get()).ok()?;
+            let index = block_param_index(request.method())?;
+            self.resolve_block(params.get(index)?)?
+        };
+
+        let params = request.params().map(|params| params.get()).unwrap_or_default();
+        let key = keccak256(format!("{}/{}/{}", block_hash, request.method(), params));
+        Some(self.dir.join(format!("{}.json", key)))
+    }
+
+    /// Resolve a block parameter to a block hash. Block tags are never resolved.
+    fn resolve_block(&self, block: &Value) -> Option<B256> {
+        let block = match block {
+            Value::Object(block) => block.get("blockHash").or_else(|| block.get("blockNumber"))?,
+            block => block,
+        };
+        let block = block.as_str()?;
+        if let Ok(hash) = block.parse::<B256>() {
+            return Some(hash);
+        }
+        let number = u64::from_str_radix(block.strip_prefix("0x")?, 16).ok()?;
+        std::fs::read_to_string(self.block_path(number)).ok()?.trim().parse().ok()
+    }
+
+    fn block_path(&self, number: u64) -> PathBuf {
+        self.dir.join("blocks").join(number.to_string())
+    }
+}
+
+/// The position of the block parameter of a cacheable method.
+fn block_param_index(method: &str) -> Option<usize> {
+    match method {
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => Some(0),
+        "eth_getBalance"
+        | "eth_getTransactionCount"
+        | "eth_getCode"
+        | "eth_call"
+        | "eth_createAccessList" => Some(1),
+        "eth_getStorageAt" | "eth_getProof" => Some(2),
+        _ => None,
+    }
+}
+
+fn cached_response(request: &SerializedRequest, result: Box<RawValue>) -> Response {
+    Response { id: request.id().clone(), payload: ResponsePayload::Success(result) }
+}
+
+/// Write a file through a temporary file, so that concurrent readers never see it half-written.
+fn write_atomic(path: &Path, contents: &[u8]) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension(format!("tmp-{}", std::process::id()));
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use alloy_json_rpc::{Id, Request};
+
+    use super::*;
+
+    const BLOCK_HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+    const REORGED_BLOCK_HASH: &str =
+        "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+    /// A transport answering every request, counting the requests it receives.
+    #[derive(Debug, Clone)]
+    struct CountingTransport {
+        calls: Arc<AtomicU32>,
+        block_hash: &'static str,
+    }
+
+    impl Service<RequestPacket> for CountingTransport {
+        type Response = ResponsePacket;
+        type Error = TransportError;
+        type Future = TransportFut<'static>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: RequestPacket) -> Self::Future {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let RequestPacket::Single(request) = request else { unimplemented!() };
+            let result = match request.method() {
+                "eth_getBlockByNumber" => {
+                    format!(r#"{{"hash":"{}","number":"0x10"}}"#, self.block_hash)
+                }
+                _ => r#""0x2a""#.to_string(),
+            };
+            Box::pin(async move {
+                Ok(ResponsePacket::Single(cached_response(
+                    &request,
+                    RawValue::from_string(result).unwrap(),
+                )))
+            })
+        }
+    }
+
+    fn request(method: &'static str, params: Value) -> RequestPacket {
+        Request::new(method, Id::Number(1), params).serialize().unwrap().into()
+    }
+
+    fn result(response: ResponsePacket) -> String {
+        let ResponsePacket::Single(response) = response else { panic!("expected one response") };
+        let ResponsePayload::Success(result) = response.payload else { panic!("expected success") };
+        result.get().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_cache_and_replay() {
+        let dir = std::env::temp_dir().join(format!("sp1-cc-rpc-cache-{}", std::process::id()));
+        let config = CacheConfig::new(&dir, 1);
+        let calls = Arc::new(AtomicU32::new(0));
+        let transport = CountingTransport { calls: calls.clone(), block_hash: BLOCK_HASH };
+        let mut service = config.layer().layer(transport.clone());
+
+        let get_block = || request("eth_getBlockByNumber", serde_json::json!(["0x10", false]));
+        let get_storage = || {
+            request(
+                "eth_getStorageAt",
+                serde_json::json!(["0x0000000000000000000000000000000000000001", "0x0", "0x10"]),
+            )
+        };
+        let latest = || request("eth_getBlockByNumber", serde_json::json!(["latest", false]));
+
+        // The first requests are forwarded, and then served from the cache, except for the block
+        // requested by number.
+        service.call(get_block()).await.unwrap();
+        service.call(get_storage()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(result(service.call(get_block()).await.unwrap()).contains(BLOCK_HASH));
+        assert_eq!(result(service.call(get_storage()).await.unwrap()), r#""0x2a""#);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Block tags are never served from the cache.
+        service.call(latest()).await.unwrap();
+        service.call(latest()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        // An offline cache replays the cached requests without sending anything.
+        let mut offline = config.clone().offline().layer().layer(transport);
+        assert!(result(offline.call(get_block()).await.unwrap()).contains(BLOCK_HASH));
+        assert_eq!(result(offline.call(get_storage()).await.unwrap()), r#""0x2a""#);
+        assert!(offline.call(latest()).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        // Once the block is reorged, the requests by number resolve to the new block.
+        let reorged = CountingTransport { calls: calls.clone(), block_hash: REORGED_BLOCK_HASH };
+        let mut service = config.layer().layer(reorged.clone());
+        assert!(result(service.call(get_block()).await.unwrap()).contains(REORGED_BLOCK_HASH));
+        service.call(get_storage()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+        let mut offline = config.offline().layer().layer(reorged);
+        assert!(result(offline.call(get_block()).await.unwrap()).contains(REORGED_BLOCK_HASH));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
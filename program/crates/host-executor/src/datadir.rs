@@ -0,0 +1,141 @@
+//! Witness generation from the database of a local reth node.
+//!
+//! Fetching thousands of account proofs over JSON-RPC is wasteful when a reth node runs next to
+//! the host. The [`DatadirExecutor`] is a [`HostExecutor`] reading accounts, storage and code
+//! straight from the datadir of the node, and generating the account proofs from its trie tables.
+//! It produces the same [`EVMStateSketch`] as over JSON-RPC.
+//!
+//! The database is opened read-only, so the node can keep running while sketches are generated.
+//!
+//! [`EVMStateSketch`]: sp1_cc_client_executor::io::EVMStateSketch
+
+use std::{fmt, path::Path, sync::Arc};
+
+use eyre::{eyre, WrapErr};
+use reth_chainspec::ChainSpec;
+use reth_db::{mdbx::DatabaseArguments, models::ClientVersion, open_db_read_only, DatabaseEnv};
+use reth_node_ethereum::EthereumNode;
+use reth_node_types::NodeTypesWithDBAdapter;
+use reth_primitives::Header;
+use reth_provider::{
+    providers::StaticFileProvider, HeaderProvider, ProviderFactory, StateProofProvider,
+    StateProviderBox, StateProviderFactory,
+};
+use reth_revm::database::StateProviderDatabase;
+use reth_trie::{AccountProof, TrieInput};
+use revm::DatabaseRef;
+use revm_primitives::{AccountInfo, Address, Bytecode, B256, U256};
+
+use crate::{HostExecutor, ProofSource};
+
+/// The node types of an Ethereum reth node, over a read-only database.
+pub type DatadirNode = NodeTypesWithDBAdapter<EthereumNode, Arc<DatabaseEnv>>;
+
+/// Open the datadir of a reth node of `chain_spec` read-only.
+pub fn open_datadir(
+    datadir: &Path,
+    chain_spec: Arc<ChainSpec>,
+) -> eyre::Result<ProviderFactory<DatadirNode>> {
+    let db =
+        open_db_read_only(&datadir.join("db"), DatabaseArguments::new(ClientVersion::default()))
+            .wrap_err_with(|| format!("failed to open the database in {}", datadir.display()))?;
+    let static_files = StaticFileProvider::read_only(datadir.join("static_files"), false)
+        .wrap_err_with(|| format!("failed to open the static files in {}", datadir.display()))?;
+    Ok(ProviderFactory::new(Arc::new(db), chain_spec, static_files))
+}
+
+/// A state source reading the database of a reth node, at the end of a block.
+pub struct DatadirDb {
+    /// The provider of the node database.
+    pub factory: ProviderFactory<DatadirNode>,
+    /// The state at the end of the block.
+    state: StateProviderDatabase<StateProviderBox>,
+}
+
+impl fmt::Debug for DatadirDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatadirDb").finish_non_exhaustive()
+    }
+}
+
+impl DatabaseRef for DatadirDb {
+    type Error = <StateProviderDatabase<StateProviderBox> as DatabaseRef>::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.state.basic_ref(address)
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.state.code_by_hash_ref(code_hash)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.state.storage_ref(address, index)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.state.block_hash_ref(number)
+    }
+}
+
+impl ProofSource for DatadirDb {
+    async fn account_proofs(
+        &self,
+        _header: &Header,
+        requests: &[(Address, Vec<B256>)],
+    ) -> eyre::Result<Vec<AccountProof>> {
+        tracing::info!("generating {} account proofs", requests.len());
+        requests
+            .iter()
+            .map(|(address, keys)| {
+                self.state
+                    .0
+                    .proof(TrieInput::default(), *address, keys)
+                    .wrap_err_with(|| format!("failed to generate the proof of {}", address))
+            })
+            .collect()
+    }
+
+    async fn headers(&self, numbers: &[u64]) -> eyre::Result<Vec<Header>> {
+        numbers
+            .iter()
+            .map(|number| {
+                self.factory
+                    .header_by_number(*number)?
+                    .ok_or_else(|| eyre!("block {} is not in the database", number))
+            })
+            .collect()
+    }
+}
+
+/// An executor that reads the state from the datadir of a reth node.
+pub type DatadirExecutor = HostExecutor<DatadirDb>;
+
+impl DatadirExecutor {
+    /// Open the datadir of a reth node of `chain_spec` read-only, to execute calls at the end of
+    /// block `block_number`.
+    pub fn open(
+        datadir: &Path,
+        chain_spec: Arc<ChainSpec>,
+        block_number: u64,
+    ) -> eyre::Result<Self> {
+        Self::from_factory(open_datadir(datadir, chain_spec)?, block_number)
+    }
+
+    /// Create a new [`DatadirExecutor`] from an open node database, to execute calls at the end of
+    /// block `block_number`.
+    pub fn from_factory(
+        factory: ProviderFactory<DatadirNode>,
+        block_number: u64,
+    ) -> eyre::Result<Self> {
+        let header = factory
+            .header_by_number(block_number)?
+            .ok_or_else(|| eyre!("block {} is not in the database", block_number))?;
+        let state = factory
+            .history_by_block_number(block_number)
+            .wrap_err_with(|| format!("the state at block {} is not available", block_number))?;
+        let db = DatadirDb { factory, state: StateProviderDatabase::new(state) };
+
+        Ok(Self::from_db(db, header))
+    }
+}
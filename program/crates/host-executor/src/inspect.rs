@@ -0,0 +1,264 @@
+//! A report of the content of an [`EVMStateSketch`], to debug and audit it.
+//!
+//! The [`SketchReport`] lists what a sketch holds: the block, the ancestor headers, the accounts
+//! and storage slots requested along with their values, the bytecodes, the size of the tries and
+//! the encoded size of each component. It is displayed as a summary, and serializes to a
+//! human-readable JSON form holding the headers and bytecodes in full.
+
+use std::fmt;
+
+use alloy_primitives::{keccak256, Address};
+use alloy_trie::{TrieAccount, EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use reth_primitives::Header;
+use revm_primitives::{Bytes, B256, U256};
+use rsp_mpt::mpt::{MptNode, MptNodeData};
+use serde::Serialize;
+use sp1_cc_client_executor::io::{EVMStateSketch, EncodedSizes};
+
+/// A report of the content of an [`EVMStateSketch`].
+#[derive(Debug, Clone, Serialize)]
+pub struct SketchReport {
+    /// The hash of the canonical encoding of the sketch.
+    pub content_hash: B256,
+    /// The hash of the block.
+    pub block_hash: B256,
+    /// The header of the block.
+    pub header: Header,
+    /// The ancestor headers, newest first.
+    pub ancestor_headers: Vec<Header>,
+    /// The requested accounts, by address.
+    pub accounts: Vec<AccountReport>,
+    /// The bytecodes, by hash.
+    pub bytecodes: Vec<BytecodeReport>,
+    /// The nodes of the state trie.
+    pub state_trie_nodes: NodeCounts,
+    /// The nodes of all the storage tries.
+    pub storage_trie_nodes: NodeCounts,
+    /// The number of storage tries.
+    pub storage_tries: usize,
+    /// The encoded size of each component of the sketch.
+    pub encoded_sizes: EncodedSizes,
+    /// The first inconsistency of the sketch, if any.
+    pub validation_error: Option<String>,
+}
+
+/// A requested account of an [`EVMStateSketch`].
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountReport {
+    /// The address of the account.
+    pub address: Address,
+    /// The account, or `None` if it does not exist.
+    ///
+    /// An account that cannot be proven from the state trie is reported as missing.
+    pub account: Option<TrieAccount>,
+    /// Whether the account can be proven from the state trie.
+    pub provable: bool,
+    /// The requested storage slots.
+    pub slots: Vec<SlotReport>,
+}
+
+/// A requested storage slot of an [`EVMStateSketch`].
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotReport {
+    /// The slot.
+    pub slot: U256,
+    /// The value of the slot, or `None` if it cannot be proven from the storage trie.
+    pub value: Option<U256>,
+}
+
+/// A bytecode of an [`EVMStateSketch`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BytecodeReport {
+    /// The hash of the bytecode.
+    pub hash: B256,
+    /// The size of the bytecode.
+    pub size: usize,
+    /// The bytecode.
+    pub code: Bytes,
+}
+
+/// The number of nodes of a trie.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct NodeCounts {
+    /// The nodes held in full.
+    pub resolved: usize,
+    /// The nodes only held by their hash.
+    pub digests: usize,
+}
+
+impl NodeCounts {
+    /// Count the nodes of the trie rooted at `node`.
+    pub(crate) fn add(&mut self, node: &MptNode) {
+        match node.as_data() {
+            MptNodeData::Null => {}
+            MptNodeData::Branch(children) => {
+                self.resolved += 1;
+                children.iter().flatten().for_each(|child| self.add(child));
+            }
+            MptNodeData::Leaf(..) => self.resolved += 1,
+            MptNodeData::Extension(_, child) => {
+                self.resolved += 1;
+                self.add(child);
+            }
+            MptNodeData::Digest(_) => self.digests += 1,
+        }
+    }
+}
+
+impl SketchReport {
+    /// Report the content of `sketch`.
+    pub fn new(sketch: &EVMStateSketch) -> Self {
+        let mut requests = sketch.state_requests.iter().collect::<Vec<_>>();
+        requests.sort_unstable_by_key(|(address, _)| **address);
+        let accounts = requests
+            .into_iter()
+            .map(|(address, slots)| {
+                let hashed_address = keccak256(address);
+                let account =
+                    sketch.state.state_trie.get_rlp::<TrieAccount>(hashed_address.as_slice());
+                let storage_trie = sketch.state.storage_tries.get(&hashed_address);
+
+                let mut slots = slots.clone();
+                slots.sort_unstable();
+                slots.dedup();
+                let slots = slots
+                    .into_iter()
+                    .map(|slot| {
+                        let hashed_slot = keccak256(B256::from(slot));
+                        let value = match (&account, storage_trie) {
+                            (Ok(None), _) => Some(U256::ZERO),
+                            (Ok(Some(info)), None) if info.storage_root == EMPTY_ROOT_HASH => {
+                                Some(U256::ZERO)
+                            }
+                            (_, Some(trie)) => trie
+                                .get_rlp::<U256>(hashed_slot.as_slice())
+                                .ok()
+                                .map(Option::unwrap_or_default),
+                            (_, None) => None,
+                        };
+                        SlotReport { slot, value }
+                    })
+                    .collect();
+
+                AccountReport {
+                    address: *address,
+                    provable: account.is_ok(),
+                    account: account.ok().flatten(),
+                    slots,
+                }
+            })
+            .collect();
+
+        // The empty bytecode of accounts without code is not worth reporting.
+        let mut bytecodes = sketch
+            .bytecodes
+            .iter()
+            .filter(|code| !code.is_empty())
+            .map(|code| {
+                let code = code.original_bytes();
+                BytecodeReport { hash: keccak256(&code), size: code.len(), code }
+            })
+            .collect::<Vec<_>>();
+        bytecodes.sort_unstable_by_key(|code| code.hash);
+        bytecodes.dedup_by_key(|code| code.hash);
+
+        let mut state_trie_nodes = NodeCounts::default();
+        state_trie_nodes.add(&sketch.state.state_trie);
+        let mut storage_trie_nodes = NodeCounts::default();
+        sketch.state.storage_tries.values().for_each(|trie| storage_trie_nodes.add(trie));
+
+        Self {
+            content_hash: sketch.content_hash(),
+            block_hash: sketch.header.hash_slow(),
+            header: sketch.header.clone(),
+            ancestor_headers: sketch.ancestor_headers.clone(),
+            accounts,
+            bytecodes,
+            state_trie_nodes,
+            storage_trie_nodes,
+            storage_tries: sketch.state.storage_tries.len(),
+            encoded_sizes: sketch.encoded_sizes(),
+            validation_error: sketch.validate().err().map(|error| error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SketchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sketch {}", self.content_hash)?;
+        writeln!(
+            f,
+            "Block {} {}, state root {}",
+            self.header.number, self.block_hash, self.header.state_root
+        )?;
+        match (self.ancestor_headers.last(), self.ancestor_headers.first()) {
+            (Some(oldest), Some(newest)) => writeln!(
+                f,
+                "Ancestors: blocks {} to {} ({} headers)",
+                oldest.number,
+                newest.number,
+                self.ancestor_headers.len()
+            )?,
+            _ => writeln!(f, "Ancestors: none")?,
+        }
+
+        writeln!(f, "Accounts: {}", self.accounts.len())?;
+        for account in &self.accounts {
+            match (&account.account, account.provable) {
+                (_, false) => writeln!(f, "  {}: not provable", account.address)?,
+                (None, true) => writeln!(f, "  {}: does not exist", account.address)?,
+                (Some(info), true) => {
+                    let code = match self.bytecodes.iter().find(|code| code.hash == info.code_hash)
+                    {
+                        Some(code) => format!("{} bytes of code", code.size),
+                        None if info.code_hash == KECCAK_EMPTY => "no code".to_string(),
+                        None => format!("missing code {}", info.code_hash),
+                    };
+                    writeln!(
+                        f,
+                        "  {}: nonce {}, balance {}, {}, storage root {}",
+                        account.address, info.nonce, info.balance, code, info.storage_root
+                    )?;
+                }
+            }
+            for slot in &account.slots {
+                match slot.value {
+                    Some(value) => writeln!(f, "    slot {:#x} = {:#x}", slot.slot, value)?,
+                    None => writeln!(f, "    slot {:#x}: not provable", slot.slot)?,
+                }
+            }
+        }
+
+        let code_size = self.bytecodes.iter().map(|code| code.size).sum::<usize>();
+        writeln!(f, "Bytecodes: {}, {} bytes", self.bytecodes.len(), code_size)?;
+        writeln!(
+            f,
+            "Trie nodes: state trie {} resolved, {} digests; {} storage tries {} resolved, {} \
+             digests",
+            self.state_trie_nodes.resolved,
+            self.state_trie_nodes.digests,
+            self.storage_tries,
+            self.storage_trie_nodes.resolved,
+            self.storage_trie_nodes.digests
+        )?;
+
+        let sizes = &self.encoded_sizes;
+        writeln!(
+            f,
+            "Encoded size: {} bytes (header {}, ancestor headers {}, state trie {}, storage tries \
+             {}, state requests {}, bytecodes {})",
+            sizes.total(),
+            sizes.header,
+            sizes.ancestor_headers,
+            sizes.state_trie,
+            sizes.storage_tries,
+            sizes.state_requests,
+            sizes.bytecodes
+        )?;
+
+        match &self.validation_error {
+            Some(error) => write!(f, "Invalid: {}", error),
+            None => write!(f, "Valid"),
+        }
+    }
+}
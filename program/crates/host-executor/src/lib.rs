@@ -1,132 +1,249 @@
 #[cfg(test)]
 mod test;
 
-use std::collections::BTreeSet;
-
-use alloy_provider::{network::AnyNetwork, Provider};
-use alloy_rpc_types::{BlockId, BlockNumberOrTag, BlockTransactionsKind};
+pub mod cache;
+#[cfg(feature = "datadir")]
+pub mod datadir;
+pub mod inspect;
+pub mod provider;
+pub mod prune;
+pub mod quorum;
+pub mod retry;
+mod tracking;
+pub mod trie;
+mod verify;
+
+use alloy_primitives::Address;
+use alloy_provider::network::{AnyNetwork, Network};
 use eyre::eyre;
 use reth_primitives::Header;
-use revm::db::CacheDB;
-use revm_primitives::{Bytes, B256, U256};
+use reth_trie::AccountProof;
+use revm::DatabaseRef;
+use revm_primitives::{Bytes, ExecutionResult, B256, U256};
 use rsp_mpt::EthereumState;
-use rsp_primitives::account_proof::eip1186_proof_to_account_proof;
-use rsp_rpc_db::RpcDb;
 
-use sp1_cc_client_executor::{io::EVMStateSketch, new_evm, ContractInput};
+use sp1_cc_client_executor::{
+    call_db, deploy, io::EVMStateSketch, new_evm, transact_batch, BatchMode, BatchPublicValues,
+    ContractCalldata, ContractInput, ContractPublicValues,
+};
+use tracking::TrackingDb;
+
+pub use cache::{CacheConfig, CacheLayer, RpcFixture};
+#[cfg(feature = "datadir")]
+pub use datadir::{DatadirDb, DatadirExecutor};
+pub use inspect::SketchReport;
+pub use provider::{FetchOptions, NamedProvider, ProviderDb, RpcHostExecutor};
+pub use prune::{prune_sketch, PruneReport};
+pub use quorum::{QuorumConfig, QuorumTransport};
+pub use retry::{RetryConfig, RetryLayer};
+pub use trie::{StateFile, TrieDb, TrieExecutor, TrieState};
+
+/// The block type returned by [`AnyNetwork`] providers.
+type AnyBlock = <AnyNetwork as Network>::BlockResponse;
+
+/// An executor of calls at a block, that keeps track of the state being accessed, and eventually
+/// compresses it into an [`EVMStateSketch`].
+///
+/// Implemented by the [`HostExecutor`], whatever the source of the state.
+#[allow(async_fn_in_trait)]
+pub trait SketchExecutor {
+    /// The header of the block the calls are executed on.
+    fn header(&self) -> &Header;
+
+    /// Executes the smart contract call with the given [`ContractInput`].
+    async fn execute(&mut self, call: ContractInput) -> eyre::Result<Bytes>;
+
+    /// Returns the cumulative [`EVMStateSketch`] after executing some smart contracts.
+    async fn finalize(&self) -> eyre::Result<EVMStateSketch>;
+}
+
+/// A source of the state of a block, that a [`HostExecutor`] executes calls on.
+///
+/// The calls read the state through the [`DatabaseRef`], and the [`HostExecutor`] records what they
+/// access. The source then proves the accessed state against the state root of the block, and gives
+/// the headers of the blocks whose hashes were read with the BLOCKHASH opcode.
+///
+/// Implemented by the [`ProviderDb`], which fetches the state from a provider, by the [`TrieDb`],
+/// which reads it from a set of trie nodes, and by the `DatadirDb`, which reads it from the
+/// database of a reth node.
+#[allow(async_fn_in_trait)]
+pub trait ProofSource: DatabaseRef {
+    /// Returns the proof of each account of `requests` and of the storage slots requested along
+    /// with it, in order, at the end of the block of `header`.
+    async fn account_proofs(
+        &self,
+        header: &Header,
+        requests: &[(Address, Vec<B256>)],
+    ) -> eyre::Result<Vec<AccountProof>>;
+
+    /// Returns the headers of the blocks `numbers`, in order.
+    async fn headers(&self, numbers: &[u64]) -> eyre::Result<Vec<Header>>;
+
+    /// Checks that the source gives the same result as the local execution of `call` at the end of
+    /// the block of `header`.
+    ///
+    /// Only a source that executes calls itself can check them: the default fails.
+    async fn verify_call(
+        &self,
+        header: &Header,
+        call: &ContractInput,
+        _result: &ExecutionResult,
+    ) -> eyre::Result<()> {
+        Err(eyre!(
+            "the state source cannot check the execution of {} at block {}",
+            verify::describe_call(call),
+            header.number
+        ))
+    }
+}
 
-/// An executor that fetches data from a [`Provider`].
+/// An executor of calls at a block, reading the state from a [`ProofSource`].
 ///
 /// This executor keeps track of the state being accessed, and eventually compresses it into an
-/// [`EVMStateSketch`].
+/// [`EVMStateSketch`]. The state is fetched from a provider by the executor created with
+/// [`HostExecutor::new`], read from a set of trie nodes by the [`TrieExecutor`], and read from the
+/// database of a reth node by the `DatadirExecutor`: all of them execute calls and generate the
+/// witness the same way.
 #[derive(Debug, Clone)]
-pub struct HostExecutor<P: Provider<AnyNetwork> + Clone> {
+pub struct HostExecutor<D> {
     /// The header of the block to execute our view functions on.
     pub header: Header,
-    /// The [`RpcDb`] used to back the EVM.
-    pub rpc_db: RpcDb<P, AnyNetwork>,
-    /// The provider used to fetch data.
-    pub provider: P,
+    /// The state at the end of the block, recording the state accessed.
+    db: TrackingDb<D>,
+    /// Whether every call is also checked against the state source, to check that the local EVM
+    /// gives the same output.
+    pub verify_calls: bool,
 }
 
-impl<P: Provider<AnyNetwork> + Clone> HostExecutor<P> {
-    /// Create a new [`HostExecutor`] with a specific [`Provider`] and [`BlockNumberOrTag`].
-    pub async fn new(provider: P, block_number: BlockNumberOrTag) -> eyre::Result<Self> {
-        let block = provider
-            .get_block_by_number(block_number, BlockTransactionsKind::Full)
-            .await?
-            .ok_or(eyre!("couldn't fetch block: {}", block_number))?;
-
-        let rpc_db = RpcDb::new(provider.clone(), block.header.number);
-        let header = block
-            .inner
-            .header
-            .inner
-            .try_into_header()
-            .map_err(|_| eyre!("fail to convert header"))?;
-
-        Ok(Self { header, rpc_db, provider })
+impl<D> HostExecutor<D>
+where
+    D: ProofSource,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Create a new [`HostExecutor`] executing calls on `db`, at the end of the block of `header`.
+    pub fn from_db(db: D, header: Header) -> Self {
+        let db = TrackingDb::new(db, header.number);
+        Self { header, db, verify_calls: false }
     }
 
-    /// Create a new [`HostExecutor`] with a specific [`Provider`] and [`BlockId`].
-    pub async fn new_with_blockid(provider: P, block_identifier: BlockId) -> eyre::Result<Self> {
-        let block = provider
-            .get_block(block_identifier, BlockTransactionsKind::Full)
-            .await?
-            .ok_or(eyre!("couldn't fetch block: {}", block_identifier))?;
-
-        let rpc_db = RpcDb::new(provider.clone(), block.header.number);
-        let header = block
-            .inner
-            .header
-            .inner
-            .try_into_header()
-            .map_err(|_| eyre!("fail to convert header"))?;
-        Ok(Self { header, rpc_db, provider })
+    /// The source of the state.
+    pub fn db(&self) -> &D {
+        &self.db.inner
     }
 
     /// Executes the smart contract call with the given [`ContractInput`].
+    ///
+    /// If call verification is enabled, the output is checked against the state source.
     pub async fn execute(&mut self, call: ContractInput) -> eyre::Result<Bytes> {
-        let cache_db = CacheDB::new(&self.rpc_db);
+        Ok(self.execute_call(call).await?.contractOutput)
+    }
+
+    /// Executes the smart contract call with the given [`ContractInput`], and returns the public
+    /// values [`ClientExecutor::execute`] commits for it.
+    ///
+    /// For a deployless call, the contract address of the public values is the address the
+    /// contract was deployed at. Deployless calls are not checked against `eth_call`, which cannot
+    /// deploy a contract before the call.
+    ///
+    /// [`ClientExecutor::execute`]: sp1_cc_client_executor::ClientExecutor::execute
+    pub async fn execute_call(
+        &mut self,
+        mut call: ContractInput,
+    ) -> eyre::Result<ContractPublicValues> {
+        let mut cache_db = call_db(&self.db, &call)?;
+        deploy(&mut cache_db, &self.header, &mut call)?;
         let mut evm = new_evm(cache_db, &self.header, U256::ZERO, &call);
-        let output = evm.transact()?;
-        let output_bytes = output.result.output().ok_or(eyre!("Error getting result"))?;
+        let result = evm.transact()?.result;
+        drop(evm);
+
+        if self.verify_calls && !matches!(call.calldata, ContractCalldata::Deployless { .. }) {
+            self.db.inner.verify_call(&self.header, &call, &result).await?;
+        }
 
-        Ok(output_bytes.clone())
+        let output_bytes = result.output().ok_or(eyre!("Error getting result"))?;
+        Ok(ContractPublicValues::new(call, output_bytes.clone(), &self.header))
     }
 
-    /// Returns the cumulative [`EVMStateSketch`] after executing some smart contracts.
-    pub async fn finalize(&self) -> eyre::Result<EVMStateSketch> {
-        let block_number = self.header.number;
-
-        // For every account touched, fetch the storage proofs for all the slots touched.
-        let state_requests = self.rpc_db.get_state_requests();
-        tracing::info!("fetching storage proofs");
-        let mut storage_proofs = Vec::new();
-
-        for (address, used_keys) in state_requests.iter() {
-            let keys = used_keys
-                .iter()
-                .map(|key| B256::from(*key))
-                .collect::<BTreeSet<_>>()
-                .into_iter()
-                .collect::<Vec<_>>();
-
-            let storage_proof =
-                self.provider.get_proof(*address, keys).block_id(block_number.into()).await?;
-            storage_proofs.push(eip1186_proof_to_account_proof(storage_proof));
+    /// Executes the smart contract calls `calls` in `mode`, as [`ClientExecutor::execute_batch`]
+    /// does, and returns the public values it commits.
+    ///
+    /// With call verification, only the calls executed on the state of the block are checked:
+    /// every call in [`BatchMode::Independent`] mode, and the first one in
+    /// [`BatchMode::Sequential`] mode. Deployless calls are never checked.
+    ///
+    /// [`ClientExecutor::execute_batch`]: sp1_cc_client_executor::ClientExecutor::execute_batch
+    pub async fn execute_batch(
+        &mut self,
+        mut calls: Vec<ContractInput>,
+        mode: BatchMode,
+    ) -> eyre::Result<BatchPublicValues> {
+        let results = transact_batch(&self.db, &self.header, &mut calls, mode)?;
+
+        if self.verify_calls {
+            let verified = match mode {
+                BatchMode::Independent => calls.len(),
+                BatchMode::Sequential => 1,
+            };
+            for (call, result) in calls.iter().zip(&results).take(verified) {
+                if !matches!(call.calldata, ContractCalldata::Deployless { .. }) {
+                    self.db.inner.verify_call(&self.header, call, result).await?;
+                }
+            }
         }
 
+        Ok(BatchPublicValues::new(calls, results, mode, &self.header))
+    }
+
+    /// Returns the cumulative [`EVMStateSketch`] after executing some smart contracts.
+    pub async fn finalize(&self) -> eyre::Result<EVMStateSketch> {
+        // For every account touched, prove all the slots touched.
+        let state_requests = self.db.state_requests();
+        let proof_requests = state_requests
+            .iter()
+            .map(|(address, slots)| {
+                (*address, slots.iter().map(|slot| B256::from(*slot)).collect())
+            })
+            .collect::<Vec<_>>();
+        tracing::info!("proving {} accounts", proof_requests.len());
+        let proofs = self.db.inner.account_proofs(&self.header, &proof_requests).await?;
         let storage_proofs_by_address =
-            storage_proofs.iter().map(|item| (item.address, item.clone())).collect();
+            proof_requests.iter().map(|(address, _)| *address).zip(proofs).collect();
         let state = EthereumState::from_proofs(self.header.state_root, &storage_proofs_by_address)?;
 
-        // Fetch the parent headers needed to constrain the BLOCKHASH opcode.
-        let oldest_ancestor = *self.rpc_db.oldest_ancestor.borrow();
-        let mut ancestor_headers = vec![];
-        tracing::info!("fetching {} ancestor headers", block_number - oldest_ancestor);
-        for height in (oldest_ancestor..=(block_number - 1)).rev() {
-            let block = self
-                .provider
-                .get_block_by_number(height.into(), BlockTransactionsKind::Full)
-                .await?
-                .unwrap();
-            ancestor_headers.push(
-                block
-                    .inner
-                    .header
-                    .inner
-                    .try_into_header()
-                    .map_err(|_| eyre!("fail to convert header"))?,
-            );
-        }
+        // Read the parent headers needed to constrain the BLOCKHASH opcode.
+        let numbers = (self.db.oldest_ancestor()..self.header.number).rev().collect::<Vec<_>>();
+        tracing::info!("reading {} ancestor headers", numbers.len());
+        let ancestor_headers = self.db.inner.headers(&numbers).await?;
 
         Ok(EVMStateSketch {
             header: self.header.clone(),
             ancestor_headers,
             state,
             state_requests,
-            bytecodes: self.rpc_db.get_bytecodes(),
+            bytecodes: self.db.bytecodes(),
         })
     }
 }
+
+impl<D> SketchExecutor for HostExecutor<D>
+where
+    D: ProofSource,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn header(&self) -> &Header {
+        &self.header
+    }
+
+    async fn execute(&mut self, call: ContractInput) -> eyre::Result<Bytes> {
+        HostExecutor::execute(self, call).await
+    }
+
+    async fn finalize(&self) -> eyre::Result<EVMStateSketch> {
+        HostExecutor::finalize(self).await
+    }
+}
+
+/// Converts an RPC block into a consensus [`Header`].
+fn block_to_header(block: AnyBlock) -> eyre::Result<Header> {
+    block.inner.header.inner.try_into_header().map_err(|_| eyre!("fail to convert header"))
+}
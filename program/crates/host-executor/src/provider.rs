@@ -0,0 +1,399 @@
+//! Witness generation over JSON-RPC.
+//!
+//! The [`ProviderDb`] reads the state of a block from a [`Provider`], and fetches the proofs of the
+//! state accessed with `eth_getProof`, checking them against the state root of the block. This is
+//! the state source of the [`HostExecutor`] created with [`HostExecutor::new`].
+
+use alloy_primitives::Address;
+use alloy_provider::{network::AnyNetwork, Provider};
+use alloy_rpc_client::BatchRequest;
+use alloy_rpc_types::{
+    BlockId, BlockNumberOrTag, BlockTransactionsKind, EIP1186AccountProofResponse,
+};
+use eyre::{eyre, WrapErr};
+use futures::{stream, StreamExt, TryStreamExt};
+use reth_primitives::Header;
+use reth_trie::AccountProof;
+use revm::DatabaseRef;
+use revm_primitives::{AccountInfo, Bytecode, ExecutionResult, B256, U256};
+use rsp_primitives::account_proof::eip1186_proof_to_account_proof;
+use rsp_rpc_db::RpcDb;
+use sp1_cc_client_executor::ContractInput;
+
+use crate::{
+    block_to_header,
+    verify::{self, CallOutcome},
+    AnyBlock, HostExecutor, ProofSource,
+};
+
+/// Options controlling how [`HostExecutor::finalize`] fetches the state witness.
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// The maximum number of requests in flight at once. When batching, this is the maximum
+    /// number of batches in flight.
+    pub concurrency: usize,
+    /// If set, requests are grouped into JSON-RPC batches of this many calls.
+    ///
+    /// Disabled by default, since many public RPCs reject or throttle batches.
+    pub batch_size: Option<usize>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self { concurrency: 16, batch_size: None }
+    }
+}
+
+/// A provider, with a name used in error messages, such as its URL.
+#[derive(Debug, Clone)]
+pub struct NamedProvider<P> {
+    /// The name of the provider.
+    pub name: String,
+    /// The provider.
+    pub provider: P,
+}
+
+/// A state source that fetches data from a [`Provider`].
+///
+/// The account and storage proofs are checked against the state root of the block. Invalid proofs
+/// are fetched again from the fallback provider, if any.
+#[derive(Debug, Clone)]
+pub struct ProviderDb<P: Provider<AnyNetwork> + Clone> {
+    /// The [`RpcDb`] reading the state from `provider`.
+    pub rpc_db: RpcDb<P, AnyNetwork>,
+    /// The provider used to fetch data.
+    pub provider: P,
+    /// How the state witness is fetched from the provider.
+    pub fetch_options: FetchOptions,
+    /// The name of `provider` in error messages, such as its URL.
+    pub provider_name: String,
+    /// The provider used to fetch the proofs that `provider` served invalid.
+    pub fallback: Option<NamedProvider<P>>,
+}
+
+/// An executor that fetches the state from a [`Provider`].
+///
+/// This was `HostExecutor<P>` before the executor became generic over its state source.
+pub type RpcHostExecutor<P> = HostExecutor<ProviderDb<P>>;
+
+impl<P: Provider<AnyNetwork> + Clone> RpcHostExecutor<P> {
+    /// Create a new [`HostExecutor`] with a specific [`Provider`] and [`BlockNumberOrTag`].
+    pub async fn new(provider: P, block_number: BlockNumberOrTag) -> eyre::Result<Self> {
+        let block = provider
+            .get_block_by_number(block_number, BlockTransactionsKind::Hashes)
+            .await
+            .wrap_err_with(|| format!("eth_getBlockByNumber({}) failed", block_number))?
+            .ok_or(eyre!("couldn't fetch block: {}", block_number))?;
+
+        let header = block_to_header(block)?;
+        Ok(Self::from_db(ProviderDb::new(provider, header.number), header))
+    }
+
+    /// Create a new [`HostExecutor`] with a specific [`Provider`] and [`BlockId`].
+    pub async fn new_with_blockid(provider: P, block_identifier: BlockId) -> eyre::Result<Self> {
+        let block = provider
+            .get_block(block_identifier, BlockTransactionsKind::Hashes)
+            .await
+            .wrap_err_with(|| format!("eth_getBlock({}) failed", block_identifier))?
+            .ok_or(eyre!("couldn't fetch block: {}", block_identifier))?;
+
+        let header = block_to_header(block)?;
+        Ok(Self::from_db(ProviderDb::new(provider, header.number), header))
+    }
+
+    /// The provider used to fetch data.
+    pub fn provider(&self) -> &P {
+        &self.db().provider
+    }
+
+    /// The [`RpcDb`] reading the state from the provider.
+    pub fn rpc_db(&self) -> &RpcDb<P, AnyNetwork> {
+        &self.db().rpc_db
+    }
+
+    /// Set the [`FetchOptions`] used by [`HostExecutor::finalize`].
+    pub fn with_fetch_options(mut self, fetch_options: FetchOptions) -> Self {
+        self.db.inner.fetch_options = fetch_options;
+        self
+    }
+
+    /// Set the name of the provider in error messages, such as its URL.
+    pub fn with_provider_name(mut self, name: impl Into<String>) -> Self {
+        self.db.inner.provider_name = name.into();
+        self
+    }
+
+    /// Set a provider to fetch the account proofs that the provider served invalid from.
+    pub fn with_fallback_provider(mut self, name: impl Into<String>, provider: P) -> Self {
+        self.db.inner.fallback = Some(NamedProvider { name: name.into(), provider });
+        self
+    }
+
+    /// Enable or disable the verification of every call against the provider's `eth_call`.
+    ///
+    /// This doubles the number of calls made to the provider, but catches a divergence of the
+    /// local EVM, for example because of a different chain spec, before a proof is generated.
+    pub fn with_call_verification(mut self, verify_calls: bool) -> Self {
+        self.verify_calls = verify_calls;
+        self
+    }
+}
+
+impl<P: Provider<AnyNetwork> + Clone> ProviderDb<P> {
+    /// Read the state at the end of block `block_number` from `provider`.
+    pub fn new(provider: P, block_number: u64) -> Self {
+        Self {
+            rpc_db: RpcDb::new(provider.clone(), block_number),
+            provider,
+            fetch_options: FetchOptions::default(),
+            provider_name: "the provider".to_string(),
+            fallback: None,
+        }
+    }
+
+    /// Fetches the `eth_getProof` response of each `(address, keys)` request, in order, and checks
+    /// them against the state root `state_root`.
+    ///
+    /// Invalid proofs are fetched again from the fallback provider, if any.
+    async fn fetch_verified_proofs(
+        &self,
+        requests: &[(Address, Vec<B256>)],
+        block_id: BlockId,
+        state_root: B256,
+    ) -> eyre::Result<Vec<EIP1186AccountProofResponse>> {
+        let mut proofs = self.fetch_proofs(&self.provider, requests, block_id).await?;
+
+        let invalid = requests
+            .iter()
+            .zip(&proofs)
+            .enumerate()
+            .filter_map(|(index, ((address, keys), proof))| {
+                let result = verify::verify_account_proof(proof, *address, keys, state_root);
+                result.err().map(|error| (index, error))
+            })
+            .collect::<Vec<_>>();
+        let Some((_, first_error)) = invalid.first() else {
+            return Ok(proofs);
+        };
+        let Some(fallback) = &self.fallback else {
+            return Err(eyre!(
+                "{} served {} invalid proofs at block {}, the first one: {}",
+                self.provider_name,
+                invalid.len(),
+                block_id,
+                first_error
+            ));
+        };
+
+        tracing::warn!(
+            "{} served {} invalid proofs at block {}, fetching them from {}. The first one: {}",
+            self.provider_name,
+            invalid.len(),
+            block_id,
+            fallback.name,
+            first_error
+        );
+        let retried = invalid.iter().map(|(index, _)| requests[*index].clone()).collect::<Vec<_>>();
+        let fallback_proofs = self.fetch_proofs(&fallback.provider, &retried, block_id).await?;
+        for ((index, error), ((address, keys), proof)) in
+            invalid.into_iter().zip(retried.iter().zip(fallback_proofs))
+        {
+            verify::verify_account_proof(&proof, *address, keys, state_root).map_err(
+                |fallback_error| {
+                    eyre!(
+                        "{} and {} both served invalid proofs at block {}: {}; {}",
+                        self.provider_name,
+                        fallback.name,
+                        block_id,
+                        error,
+                        fallback_error
+                    )
+                },
+            )?;
+            proofs[index] = proof;
+        }
+
+        Ok(proofs)
+    }
+
+    /// Fetches the `eth_getProof` response of each `(address, keys)` request from `provider`, in
+    /// order.
+    async fn fetch_proofs(
+        &self,
+        provider: &P,
+        requests: &[(Address, Vec<B256>)],
+        block_id: BlockId,
+    ) -> eyre::Result<Vec<EIP1186AccountProofResponse>> {
+        let concurrency = self.fetch_options.concurrency.max(1);
+        match self.fetch_options.batch_size {
+            None => {
+                stream::iter(requests)
+                    .map(|(address, keys)| async move {
+                        let proof = provider.get_proof(*address, keys.clone()).block_id(block_id);
+                        proof.await.wrap_err_with(|| {
+                            format!("eth_getProof({}) at block {} failed", address, block_id)
+                        })
+                    })
+                    .buffered(concurrency)
+                    .try_collect()
+                    .await
+            }
+            Some(batch_size) => {
+                let batches = stream::iter(requests.chunks(batch_size.max(1)))
+                    .map(|chunk| async move {
+                        let mut batch = BatchRequest::new(provider.client());
+                        let waiters = chunk
+                            .iter()
+                            .map(|(address, keys)| {
+                                batch.add_call::<_, EIP1186AccountProofResponse>(
+                                    "eth_getProof",
+                                    &(*address, keys.clone(), block_id),
+                                )
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        batch.send().await.wrap_err_with(|| {
+                            format!("batch of {} eth_getProof requests failed", chunk.len())
+                        })?;
+
+                        let mut proofs = Vec::with_capacity(waiters.len());
+                        for ((address, _), waiter) in chunk.iter().zip(waiters) {
+                            proofs.push(waiter.await.wrap_err_with(|| {
+                                format!("eth_getProof({}) at block {} failed", address, block_id)
+                            })?);
+                        }
+                        Ok::<_, eyre::Report>(proofs)
+                    })
+                    .buffered(concurrency)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                Ok(batches.into_iter().flatten().collect())
+            }
+        }
+    }
+
+    /// Fetches the headers of the blocks at `heights`, in order.
+    ///
+    /// Only headers are requested, since the transactions of ancestor blocks are never used.
+    async fn fetch_headers(&self, heights: &[u64]) -> eyre::Result<Vec<Header>> {
+        let concurrency = self.fetch_options.concurrency.max(1);
+        let blocks: Vec<Option<AnyBlock>> = match self.fetch_options.batch_size {
+            None => {
+                stream::iter(heights)
+                    .map(|height| async move {
+                        let block = self
+                            .provider
+                            .get_block_by_number((*height).into(), BlockTransactionsKind::Hashes)
+                            .await
+                            .wrap_err_with(|| format!("eth_getBlockByNumber({}) failed", height))?;
+                        Ok::<_, eyre::Report>(block)
+                    })
+                    .buffered(concurrency)
+                    .try_collect()
+                    .await?
+            }
+            Some(batch_size) => {
+                let batches = stream::iter(heights.chunks(batch_size.max(1)))
+                    .map(|chunk| async move {
+                        let mut batch = BatchRequest::new(self.provider.client());
+                        let waiters = chunk
+                            .iter()
+                            .map(|height| {
+                                batch.add_call::<_, Option<AnyBlock>>(
+                                    "eth_getBlockByNumber",
+                                    &(BlockNumberOrTag::Number(*height), false),
+                                )
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        batch.send().await.wrap_err_with(|| {
+                            format!("batch of {} eth_getBlockByNumber requests failed", chunk.len())
+                        })?;
+
+                        let mut blocks = Vec::with_capacity(waiters.len());
+                        for (height, waiter) in chunk.iter().zip(waiters) {
+                            blocks.push(waiter.await.wrap_err_with(|| {
+                                format!("eth_getBlockByNumber({}) failed", height)
+                            })?);
+                        }
+                        Ok::<_, eyre::Report>(blocks)
+                    })
+                    .buffered(concurrency)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                batches.into_iter().flatten().collect()
+            }
+        };
+
+        heights
+            .iter()
+            .zip(blocks)
+            .map(|(height, block)| {
+                block_to_header(block.ok_or(eyre!("couldn't fetch block: {}", height))?)
+            })
+            .collect()
+    }
+}
+
+impl<P: Provider<AnyNetwork> + Clone> DatabaseRef for ProviderDb<P> {
+    type Error = <RpcDb<P, AnyNetwork> as DatabaseRef>::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.rpc_db.basic_ref(address)
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.rpc_db.code_by_hash_ref(code_hash)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.rpc_db.storage_ref(address, index)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.rpc_db.block_hash_ref(number)
+    }
+}
+
+impl<P: Provider<AnyNetwork> + Clone> ProofSource for ProviderDb<P> {
+    async fn account_proofs(
+        &self,
+        header: &Header,
+        requests: &[(Address, Vec<B256>)],
+    ) -> eyre::Result<Vec<AccountProof>> {
+        tracing::info!("fetching {} account proofs", requests.len());
+        let proofs =
+            self.fetch_verified_proofs(requests, header.number.into(), header.state_root).await?;
+        Ok(proofs.into_iter().map(eip1186_proof_to_account_proof).collect())
+    }
+
+    async fn headers(&self, numbers: &[u64]) -> eyre::Result<Vec<Header>> {
+        self.fetch_headers(numbers).await
+    }
+
+    /// Checks the local execution against the provider's `eth_call`.
+    async fn verify_call(
+        &self,
+        header: &Header,
+        call: &ContractInput,
+        result: &ExecutionResult,
+    ) -> eyre::Result<()> {
+        let block_id = BlockId::from(header.number);
+        let request = verify::call_request(call, header);
+        let state_override = verify::state_override(call);
+        let mut eth_call = self.provider.call(&request).block(block_id);
+        if !state_override.is_empty() {
+            eth_call = eth_call.overrides(&state_override);
+        }
+        let node = CallOutcome::from_eth_call(eth_call.await)
+            .wrap_err_with(|| format!("eth_call at block {} failed", block_id))?;
+
+        match verify::call_diff(&CallOutcome::from_result(result), &node) {
+            Some(diff) => Err(eyre!(
+                "the local execution of {} diverges from eth_call at block {}: {}",
+                verify::describe_call(call),
+                block_id,
+                diff
+            )),
+            None => Ok(()),
+        }
+    }
+}
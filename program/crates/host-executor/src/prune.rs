@@ -0,0 +1,221 @@
+//! Pruning of an [`EVMStateSketch`] down to the state a set of calls uses.
+//!
+//! A sketch holds the state accessed by every call the executor ran, and all the ancestor headers
+//! down to the oldest block hash read. When the client only runs some of those calls, the rest is
+//! dead weight that the zkVM still deserializes and hashes. [`prune_sketch`] runs the client calls
+//! on the sketch itself, and keeps only what they access: the other accounts, storage slots,
+//! bytecodes and ancestor headers are dropped, and the trie branches leading to them are replaced
+//! by their hash.
+
+use std::{collections::BTreeSet, fmt};
+
+use alloy_primitives::keccak256;
+use eyre::{eyre, WrapErr};
+use revm_primitives::{HashMap, B256, KECCAK_EMPTY, U256};
+use rsp_client_executor::io::WitnessInput;
+use rsp_mpt::{
+    mpt::{MptNode, MptNodeData, MptNodeReference},
+    EthereumState,
+};
+use sp1_cc_client_executor::{
+    call_db, deploy,
+    io::{EVMStateSketch, EncodedSizes},
+    new_evm, ContractInput,
+};
+
+use crate::{inspect::NodeCounts, tracking::TrackingDb};
+
+/// What [`prune_sketch`] removed from a sketch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    /// The encoded sizes of the sketch before pruning.
+    pub before: EncodedSizes,
+    /// The encoded sizes of the sketch after pruning.
+    pub after: EncodedSizes,
+    /// The number of accounts removed.
+    pub accounts_removed: usize,
+    /// The number of storage slots removed.
+    pub slots_removed: usize,
+    /// The number of bytecodes removed.
+    pub bytecodes_removed: usize,
+    /// The number of ancestor headers removed.
+    pub ancestor_headers_removed: usize,
+    /// The number of trie nodes replaced by their hash, which the client no longer hashes.
+    pub trie_nodes_removed: usize,
+}
+
+impl PruneReport {
+    /// The number of bytes removed from the encoded sketch.
+    pub fn bytes_saved(&self) -> u64 {
+        self.before.total().saturating_sub(self.after.total())
+    }
+}
+
+impl fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pruned {} of {} bytes: {} accounts, {} storage slots, {} bytecodes, {} ancestor \
+             headers and {} trie nodes",
+            self.bytes_saved(),
+            self.before.total(),
+            self.accounts_removed,
+            self.slots_removed,
+            self.bytecodes_removed,
+            self.ancestor_headers_removed,
+            self.trie_nodes_removed
+        )
+    }
+}
+
+/// Prune `sketch` down to the state accessed by `calls`, as the client executes them.
+///
+/// The client must then only execute `calls`, or a subset of them: any other call may access
+/// state that was pruned.
+pub fn prune_sketch(
+    sketch: &EVMStateSketch,
+    calls: &[ContractInput],
+) -> eyre::Result<(EVMStateSketch, PruneReport)> {
+    sketch.validate().wrap_err("cannot prune an invalid sketch")?;
+
+    let witness_db = sketch.witness_db().map_err(|error| eyre!("{}", error))?;
+    let db = TrackingDb::new(&witness_db, sketch.header.number);
+    for call in calls {
+        let mut call = call.clone();
+        let mut cache_db =
+            call_db(&db, &call).wrap_err("failed to apply the state overrides of a call")?;
+        deploy(&mut cache_db, &sketch.header, &mut call)?;
+        let mut evm = new_evm(cache_db, &sketch.header, U256::ZERO, &call);
+        evm.transact().wrap_err("failed to execute a call on the sketch")?;
+    }
+
+    let state_requests = db.state_requests();
+    let code_hashes = db.bytecodes().iter().map(|code| code.hash_slow()).collect::<BTreeSet<_>>();
+    let depth = (sketch.header.number - db.oldest_ancestor()) as usize;
+
+    let account_keys = state_requests.keys().map(keccak256).collect::<Vec<_>>();
+    let state_trie = prune_trie(&sketch.state.state_trie, &nibbles(&account_keys));
+    let storage_tries = state_requests
+        .iter()
+        .filter(|(_, slots)| !slots.is_empty())
+        .filter_map(|(address, slots)| {
+            let hashed_address = keccak256(address);
+            let storage_trie = sketch.state.storage_tries.get(&hashed_address)?;
+            let keys = slots.iter().map(|slot| keccak256(B256::from(*slot))).collect::<Vec<_>>();
+            Some((hashed_address, prune_trie(storage_trie, &nibbles(&keys))))
+        })
+        .collect::<HashMap<_, _>>();
+
+    // The empty bytecode is kept: it is the code of every account without code.
+    let mut kept_codes = BTreeSet::new();
+    let bytecodes = sketch
+        .bytecodes
+        .iter()
+        .filter(|code| {
+            let code_hash = code.hash_slow();
+            (code_hash == KECCAK_EMPTY || code_hashes.contains(&code_hash))
+                && kept_codes.insert(code_hash)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let pruned = EVMStateSketch {
+        header: sketch.header.clone(),
+        ancestor_headers: sketch.ancestor_headers.iter().take(depth).cloned().collect(),
+        state: EthereumState { state_trie, storage_tries },
+        state_requests,
+        bytecodes,
+    };
+
+    let slots = |sketch: &EVMStateSketch| {
+        let slots = sketch
+            .state_requests
+            .iter()
+            .flat_map(|(address, slots)| slots.iter().map(move |slot| (*address, *slot)));
+        slots.collect::<BTreeSet<_>>().len()
+    };
+    let resolved_nodes = |sketch: &EVMStateSketch| {
+        let mut counts = NodeCounts::default();
+        counts.add(&sketch.state.state_trie);
+        sketch.state.storage_tries.values().for_each(|trie| counts.add(trie));
+        counts.resolved
+    };
+    let report = PruneReport {
+        before: sketch.encoded_sizes(),
+        after: pruned.encoded_sizes(),
+        accounts_removed: sketch.state_requests.len() - pruned.state_requests.len(),
+        slots_removed: slots(sketch) - slots(&pruned),
+        bytecodes_removed: sketch.bytecodes.len() - pruned.bytecodes.len(),
+        ancestor_headers_removed: sketch.ancestor_headers.len() - pruned.ancestor_headers.len(),
+        trie_nodes_removed: resolved_nodes(sketch) - resolved_nodes(&pruned),
+    };
+
+    Ok((pruned, report))
+}
+
+/// The nibbles of the trie keys `keys`.
+fn nibbles(keys: &[B256]) -> Vec<Vec<u8>> {
+    keys.iter().map(|key| key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()).collect()
+}
+
+/// The nibbles of the path of a leaf or extension node, from its hex-prefix encoding.
+fn path_nibbles(prefix: &[u8]) -> Vec<u8> {
+    let Some((first, rest)) = prefix.split_first() else { return Vec::new() };
+    let odd = (first & 0x10 != 0).then_some(first & 0x0f);
+    odd.into_iter().chain(rest.iter().flat_map(|byte| [byte >> 4, byte & 0x0f])).collect()
+}
+
+/// Replaces the branches of the trie rooted at `node` that no path of `keys` goes through by their
+/// hash.
+///
+/// Nodes shorter than a hash are embedded in their parent rather than referenced by hash: they are
+/// kept as is, since replacing them would change the hash of their parent.
+fn prune_trie(node: &MptNode, keys: &[Vec<u8>]) -> MptNode {
+    if keys.is_empty() {
+        return match node.reference() {
+            MptNodeReference::Digest(hash) => MptNodeData::Digest(hash).into(),
+            MptNodeReference::Bytes(_) => node.clone(),
+        };
+    }
+
+    match node.as_data() {
+        MptNodeData::Null | MptNodeData::Leaf(..) | MptNodeData::Digest(_) => node.clone(),
+        MptNodeData::Branch(children) => {
+            let mut pruned: [Option<Box<MptNode>>; 16] = Default::default();
+            for (nibble, child) in children.iter().enumerate() {
+                let Some(child) = child else { continue };
+                let keys = keys
+                    .iter()
+                    .filter(|key| key.first() == Some(&(nibble as u8)))
+                    .map(|key| key[1..].to_vec())
+                    .collect::<Vec<_>>();
+                pruned[nibble] = Some(Box::new(prune_trie(child, &keys)));
+            }
+            MptNodeData::Branch(pruned).into()
+        }
+        MptNodeData::Extension(prefix, child) => {
+            let path = path_nibbles(prefix);
+            let keys = keys
+                .iter()
+                .filter(|key| key.starts_with(&path))
+                .map(|key| key[path.len()..].to_vec())
+                .collect::<Vec<_>>();
+            MptNodeData::Extension(prefix.clone(), Box::new(prune_trie(child, &keys))).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_nibbles() {
+        // Even paths have a zero padding nibble after the flag, odd ones start in the flag byte.
+        assert_eq!(path_nibbles(&[0x20, 0x12, 0x34]), vec![1, 2, 3, 4]);
+        assert_eq!(path_nibbles(&[0x31, 0x23]), vec![1, 2, 3]);
+        assert_eq!(path_nibbles(&[0x00, 0xab]), vec![0xa, 0xb]);
+        assert_eq!(path_nibbles(&[0x1f]), vec![0xf]);
+        assert_eq!(nibbles(&[B256::with_last_byte(0xab)])[0][62..], [0xa, 0xb]);
+    }
+}
@@ -0,0 +1,508 @@
+//! Witness generation from several RPCs, requiring a quorum of them to agree.
+//!
+//! A single RPC could serve a fake block or fake state, and the proof would then be anchored to
+//! them. The [`QuorumTransport`] sends the requests the witness is anchored to, block headers and
+//! account proofs, to every endpoint, and only accepts a result served by a quorum of them. The
+//! endpoints disagreeing with the quorum are reported. Other requests, whose results end up being
+//! checked against the account proofs anyway, are sent to the endpoints in turn until one answers.
+//!
+//! Since endpoints may not be at the same head, a block requested by tag, such as `latest`, is
+//! pinned to the number of the block returned by the first endpoint to answer, before the quorum
+//! is asked for it.
+//!
+//! The transport can back any provider, so a [`HostExecutor`] uses it like any other RPC:
+//!
+//! ```no_run
+//! # async fn run() -> eyre::Result<()> {
+//! use alloy_provider::{network::AnyNetwork, RootProvider};
+//! use alloy_rpc_types::BlockNumberOrTag;
+//! use sp1_cc_host_executor::{HostExecutor, QuorumConfig, RetryConfig};
+//!
+//! let urls = vec!["https://a.example".parse()?, "https://b.example".parse()?];
+//! let provider: RootProvider<AnyNetwork> =
+//!     QuorumConfig::majority(urls).http_provider(&RetryConfig::default())?;
+//! let host_executor = HostExecutor::new(provider, BlockNumberOrTag::Latest).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`HostExecutor`]: crate::HostExecutor
+
+use std::{
+    fmt,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use alloy_json_rpc::{
+    Request, RequestPacket, Response, ResponsePacket, ResponsePayload, SerializedRequest,
+};
+use alloy_primitives::{B256, U64};
+use alloy_provider::{network::Network, RootProvider};
+use alloy_rpc_client::RpcClient;
+use alloy_rpc_types::EIP1186AccountProofResponse;
+use alloy_transport::{BoxTransport, TransportError, TransportErrorKind, TransportFut};
+use alloy_transport_http::Http;
+use futures::future::join_all;
+use serde::Deserialize;
+use serde_json::Value;
+use tower::{Layer, Service};
+use url::Url;
+
+use crate::{retry::describe, RetryConfig};
+
+/// Methods whose results must be agreed on by a quorum of endpoints.
+const QUORUM_METHODS: &[&str] =
+    &["eth_chainId", "eth_getBlockByNumber", "eth_getBlockByHash", "eth_getProof"];
+
+/// Which RPC endpoints are queried, and how many of them must agree.
+#[derive(Debug, Clone)]
+pub struct QuorumConfig {
+    /// The endpoints queried.
+    pub urls: Vec<Url>,
+    /// The number of endpoints that must serve the same result for it to be accepted.
+    pub quorum: usize,
+}
+
+impl QuorumConfig {
+    /// Require `quorum` of `urls` to agree.
+    ///
+    /// Fails unless `quorum` is a majority of `urls`, so that two different results cannot both
+    /// reach it.
+    pub fn new(urls: Vec<Url>, quorum: usize) -> eyre::Result<Self> {
+        check_quorum(quorum, urls.len())?;
+        Ok(Self { urls, quorum })
+    }
+
+    /// Require a majority of `urls` to agree.
+    pub fn majority(urls: Vec<Url>) -> Self {
+        let quorum = urls.len() / 2 + 1;
+        Self { urls, quorum }
+    }
+
+    /// Create a [`QuorumTransport`] over HTTP transports, whose requests are retried as configured
+    /// by `retry`.
+    pub fn transport(&self, retry: &RetryConfig) -> eyre::Result<QuorumTransport> {
+        let endpoints = self
+            .urls
+            .iter()
+            .map(|url| {
+                let transport = retry.layer().layer(Http::new(url.clone()));
+                (url.to_string(), BoxTransport::new(transport))
+            })
+            .collect();
+        QuorumTransport::new(endpoints, self.quorum)
+    }
+
+    /// Create a provider whose requests go through a [`QuorumTransport`].
+    pub fn http_provider<N: Network>(&self, retry: &RetryConfig) -> eyre::Result<RootProvider<N>> {
+        Ok(RootProvider::new(RpcClient::new(self.transport(retry)?, false)))
+    }
+}
+
+/// An RPC endpoint of a [`QuorumTransport`].
+#[derive(Clone)]
+struct Endpoint {
+    name: String,
+    transport: BoxTransport,
+}
+
+impl fmt::Debug for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Endpoint").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+/// A transport sending requests to several endpoints, and requiring a quorum of them to agree on
+/// the block headers and account proofs.
+#[derive(Debug, Clone)]
+pub struct QuorumTransport {
+    endpoints: Arc<Vec<Endpoint>>,
+    quorum: usize,
+}
+
+impl QuorumTransport {
+    /// Create a [`QuorumTransport`] over the named `endpoints`, such as their URLs, requiring
+    /// `quorum` of them to agree.
+    ///
+    /// Fails unless `quorum` is a majority of the endpoints.
+    pub fn new(endpoints: Vec<(String, BoxTransport)>, quorum: usize) -> eyre::Result<Self> {
+        check_quorum(quorum, endpoints.len())?;
+        let endpoints =
+            endpoints.into_iter().map(|(name, transport)| Endpoint { name, transport }).collect();
+        Ok(Self { endpoints: Arc::new(endpoints), quorum })
+    }
+}
+
+/// Check that `quorum` is a majority of `endpoints`: with less, two different results could both
+/// reach the quorum.
+fn check_quorum(quorum: usize, endpoints: usize) -> eyre::Result<()> {
+    if quorum <= endpoints / 2 || quorum > endpoints {
+        eyre::bail!("the quorum must be a majority of the {endpoints} endpoints, got {quorum}");
+    }
+    Ok(())
+}
+
+impl Service<RequestPacket> for QuorumTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The endpoints are boxed transports, which are always ready.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move {
+            let requests = match &request {
+                RequestPacket::Single(request) => std::slice::from_ref(request),
+                RequestPacket::Batch(requests) => requests.as_slice(),
+            };
+            if !requests.iter().any(|request| QUORUM_METHODS.contains(&request.method())) {
+                return this.call_any(request).await;
+            }
+
+            let request = this.pin_block_tag(request).await?;
+            let packets = join_all(
+                this.endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.transport.clone().call(request.clone())),
+            )
+            .await;
+
+            let requests = match &request {
+                RequestPacket::Single(request) => std::slice::from_ref(request),
+                RequestPacket::Batch(requests) => requests.as_slice(),
+            };
+            let responses = requests
+                .iter()
+                .map(|request| this.vote(request, &packets))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(match request {
+                RequestPacket::Single(_) => ResponsePacket::Single(responses[0].clone()),
+                RequestPacket::Batch(_) => ResponsePacket::Batch(responses),
+            })
+        })
+    }
+}
+
+impl QuorumTransport {
+    /// Send `request` to the endpoints in turn, until one of them answers.
+    async fn call_any(&self, request: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let mut errors = Vec::new();
+        for endpoint in self.endpoints.iter() {
+            match endpoint.transport.clone().call(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(error) => {
+                    tracing::warn!("{} failed on {}: {}", endpoint.name, describe(&request), error);
+                    errors.push(format!("{}: {}", endpoint.name, error));
+                }
+            }
+        }
+        Err(TransportErrorKind::custom_str(&format!(
+            "every endpoint failed on {}: {}",
+            describe(&request),
+            errors.join("; ")
+        )))
+    }
+
+    /// Pin a single `eth_getBlockByNumber` request for a block tag to the number of the block
+    /// returned by the first endpoint to answer.
+    async fn pin_block_tag(&self, request: RequestPacket) -> Result<RequestPacket, TransportError> {
+        let RequestPacket::Single(single) = &request else { return Ok(request) };
+        if single.method() != "eth_getBlockByNumber" {
+            return Ok(request);
+        }
+        let Some(mut params) = single
+            .params()
+            .and_then(|params| serde_json::from_str::<Vec<Value>>(params.get()).ok())
+        else {
+            return Ok(request);
+        };
+        let block = params.first().and_then(Value::as_str);
+        if block.is_none_or(|block| block.starts_with("0x")) {
+            return Ok(request);
+        }
+
+        #[derive(Deserialize)]
+        struct BlockNumber {
+            number: U64,
+        }
+
+        let ResponsePacket::Single(response) = self.call_any(request.clone()).await? else {
+            return Ok(request);
+        };
+        let ResponsePayload::Success(result) = &response.payload else { return Ok(request) };
+        let Ok(block) = serde_json::from_str::<BlockNumber>(result.get()) else {
+            return Ok(request);
+        };
+
+        params[0] = Value::String(format!("0x{:x}", block.number));
+        let pinned = Request::new(single.method().to_string(), single.id().clone(), params);
+        Ok(pinned.serialize().map_err(TransportError::ser_err)?.into())
+    }
+
+    /// Select the response to `request` served by a quorum of endpoints, reporting the endpoints
+    /// disagreeing with it.
+    fn vote(
+        &self,
+        request: &SerializedRequest,
+        packets: &[Result<ResponsePacket, TransportError>],
+    ) -> Result<Response, TransportError> {
+        // The answer of each endpoint: its response and the part of it that must be agreed on, or
+        // why it has none.
+        let answers = self
+            .endpoints
+            .iter()
+            .zip(packets)
+            .map(|(endpoint, packet)| {
+                let response = match packet {
+                    Ok(ResponsePacket::Single(response)) => Some(response),
+                    Ok(ResponsePacket::Batch(responses)) => {
+                        responses.iter().find(|response| &response.id == request.id())
+                    }
+                    Err(error) => return (endpoint, Err(error.to_string())),
+                };
+                match response {
+                    Some(response) => (endpoint, Ok((fingerprint(request, response), response))),
+                    None => (endpoint, Err("no response".to_string())),
+                }
+            })
+            .collect::<Vec<_>>();
+        let description = describe(&RequestPacket::Single(request.clone()));
+
+        if !QUORUM_METHODS.contains(&request.method()) {
+            // Any answer will do.
+            return answers
+                .iter()
+                .find_map(|(_, answer)| {
+                    answer.as_ref().ok().map(|(_, response)| (*response).clone())
+                })
+                .ok_or_else(|| {
+                    TransportErrorKind::custom_str(&format!(
+                        "every endpoint failed on {}",
+                        description
+                    ))
+                });
+        }
+
+        let mut votes: Vec<(&str, usize, &Response)> = Vec::new();
+        for (_, answer) in &answers {
+            let Ok((fingerprint, response)) = answer else { continue };
+            match votes.iter_mut().find(|(other, _, _)| other == fingerprint) {
+                Some((_, count, _)) => *count += 1,
+                None => votes.push((fingerprint, 1, response)),
+            }
+        }
+
+        let reached =
+            votes.iter().filter(|(_, count, _)| *count >= self.quorum).collect::<Vec<_>>();
+        let [(agreed, count, response)] = reached.as_slice() else {
+            let answers = answers
+                .iter()
+                .map(|(endpoint, answer)| match answer {
+                    Ok((fingerprint, _)) => format!("{} served {}", endpoint.name, fingerprint),
+                    Err(error) => format!("{} failed: {}", endpoint.name, error),
+                })
+                .collect::<Vec<_>>();
+            // Several results can only reach a quorum that is not a majority.
+            let reason = if reached.is_empty() { "no quorum" } else { "conflicting quorums" };
+            return Err(TransportErrorKind::custom_str(&format!(
+                "{} of {} endpoints on {}: {}",
+                reason,
+                self.quorum,
+                description,
+                answers.join("; ")
+            )));
+        };
+
+        for (endpoint, answer) in &answers {
+            match answer {
+                Ok((fingerprint, _)) if fingerprint == agreed => {}
+                Ok((fingerprint, _)) => tracing::warn!(
+                    "{} disagrees with the {} endpoints of the quorum on {}: it served {} instead of {}",
+                    endpoint.name,
+                    count,
+                    description,
+                    fingerprint,
+                    agreed
+                ),
+                Err(error) => tracing::warn!("{} failed on {}: {}", endpoint.name, description, error),
+            }
+        }
+        Ok((*response).clone())
+    }
+}
+
+/// The part of `response` that endpoints must agree on.
+///
+/// Nodes format some fields differently, so results are compared on what they commit to: the hash
+/// of a block, and the decoded content of an account proof.
+fn fingerprint(request: &SerializedRequest, response: &Response) -> String {
+    #[derive(Deserialize)]
+    struct BlockHash {
+        hash: B256,
+    }
+
+    let result = match &response.payload {
+        ResponsePayload::Success(result) => result.get(),
+        ResponsePayload::Failure(error) => {
+            return format!("error {}: {}", error.code, error.message)
+        }
+    };
+    let fingerprint = match request.method() {
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => {
+            serde_json::from_str::<Option<BlockHash>>(result).ok().map(|block| match block {
+                Some(block) => format!("block {}", block.hash),
+                None => "no block".to_string(),
+            })
+        }
+        "eth_getProof" => serde_json::from_str::<EIP1186AccountProofResponse>(result)
+            .ok()
+            .and_then(|proof| serde_json::to_string(&proof).ok())
+            .map(|proof| format!("proof {}", proof)),
+        _ => serde_json::from_str::<Value>(result).ok().map(|result| result.to_string()),
+    };
+    fingerprint.unwrap_or_else(|| result.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_json_rpc::Id;
+    use serde_json::value::RawValue;
+
+    use super::*;
+
+    /// A transport answering every request with the same result.
+    #[derive(Debug, Clone)]
+    struct StaticTransport {
+        result: Option<String>,
+    }
+
+    impl Service<RequestPacket> for StaticTransport {
+        type Response = ResponsePacket;
+        type Error = TransportError;
+        type Future = TransportFut<'static>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: RequestPacket) -> Self::Future {
+            let result = self.result.clone();
+            Box::pin(async move {
+                let RequestPacket::Single(request) = request else { unimplemented!() };
+                let result = result.ok_or_else(|| TransportErrorKind::custom_str("offline"))?;
+                Ok(ResponsePacket::Single(Response {
+                    id: request.id().clone(),
+                    payload: ResponsePayload::Success(RawValue::from_string(result).unwrap()),
+                }))
+            })
+        }
+    }
+
+    fn transport(results: Vec<Option<String>>, quorum: usize) -> QuorumTransport {
+        let endpoints = results
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| {
+                let transport = BoxTransport::new(StaticTransport { result });
+                (format!("endpoint {}", index), transport)
+            })
+            .collect();
+        QuorumTransport::new(endpoints, quorum).unwrap()
+    }
+
+    fn block(hash_byte: u8, extra: &str) -> Option<String> {
+        let hash = B256::repeat_byte(hash_byte);
+        Some(format!(r#"{{"hash":"{}","number":"0x10"{}}}"#, hash, extra))
+    }
+
+    fn get_block() -> RequestPacket {
+        Request::new("eth_getBlockByNumber", Id::Number(1), ("0x10", false))
+            .serialize()
+            .unwrap()
+            .into()
+    }
+
+    fn result(response: ResponsePacket) -> String {
+        let ResponsePacket::Single(response) = response else { panic!("expected one response") };
+        let ResponsePayload::Success(result) = response.payload else { panic!("expected success") };
+        result.get().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_quorum() {
+        // Endpoints formatting the same block differently agree, and a failed endpoint is ignored.
+        let response = transport(vec![block(1, ""), block(1, r#","size":"0x1""#), None], 2)
+            .call(get_block())
+            .await
+            .unwrap();
+        assert!(result(response).contains(&B256::repeat_byte(1).to_string()));
+
+        // The endpoint disagreeing with the quorum is outvoted.
+        let response = transport(vec![block(2, ""), block(1, ""), block(1, "")], 2)
+            .call(get_block())
+            .await
+            .unwrap();
+        assert!(result(response).contains(&B256::repeat_byte(1).to_string()));
+
+        // Without a quorum, the request fails, naming the answer of every endpoint.
+        let error = transport(vec![block(1, ""), block(2, ""), None], 2)
+            .call(get_block())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no quorum of 2 endpoints on eth_getBlockByNumber"));
+        assert!(error.contains(&format!("endpoint 1 served block {}", B256::repeat_byte(2))));
+        assert!(error.contains("endpoint 2 failed"));
+    }
+
+    #[test]
+    fn test_quorum_must_be_a_majority() {
+        let endpoints = |count: usize| {
+            (0..count)
+                .map(|index| {
+                    let transport = BoxTransport::new(StaticTransport { result: None });
+                    (format!("endpoint {}", index), transport)
+                })
+                .collect::<Vec<_>>()
+        };
+        for (count, quorum) in [(1, 1), (2, 2), (3, 2), (3, 3), (4, 3), (5, 3)] {
+            assert!(QuorumTransport::new(endpoints(count), quorum).is_ok());
+        }
+        for (count, quorum) in [(0, 0), (0, 1), (1, 0), (2, 1), (3, 1), (3, 4), (4, 2)] {
+            assert!(QuorumTransport::new(endpoints(count), quorum).is_err());
+        }
+
+        let urls = vec!["http://a.example".parse().unwrap(), "http://b.example".parse().unwrap()];
+        assert!(QuorumConfig::new(urls.clone(), 1).is_err());
+        assert!(QuorumConfig::new(urls.clone(), 2).is_ok());
+        assert!(QuorumConfig::majority(Vec::new()).transport(&RetryConfig::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_quorums() {
+        // `new` rejects such a quorum, so the transport is built directly: two different blocks
+        // reaching the quorum must not be resolved by picking one.
+        let mut transport = transport(vec![block(1, ""), block(2, "")], 2);
+        transport.quorum = 1;
+        let error = transport.call(get_block()).await.unwrap_err().to_string();
+        assert!(error.contains("conflicting quorums of 1 endpoints on eth_getBlockByNumber"));
+    }
+
+    #[tokio::test]
+    async fn test_other_methods_use_any_endpoint() {
+        let request = Request::new("eth_getCode", Id::Number(1), ("0x00", "0x10"))
+            .serialize()
+            .unwrap()
+            .into();
+        let response = transport(vec![None, Some(r#""0x01""#.into()), Some(r#""0x02""#.into())], 3)
+            .call(request)
+            .await
+            .unwrap();
+        assert_eq!(result(response), r#""0x01""#);
+    }
+}
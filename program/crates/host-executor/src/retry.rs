@@ -0,0 +1,346 @@
+//! Retries, timeouts and rate limiting for RPC requests.
+//!
+//! Public RPCs regularly answer with rate limit errors or time out under load. Instead of wrapping
+//! every call site, the [`RetryLayer`] sits in the transport stack of the provider, so that every
+//! request made through it, including the ones made by the [`RpcDb`] while executing a call, is
+//! rate limited and retried.
+//!
+//! [`RpcDb`]: rsp_rpc_db::RpcDb
+
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use alloy_json_rpc::{RequestPacket, ResponsePacket};
+use alloy_provider::{network::Network, RootProvider};
+use alloy_rpc_client::ClientBuilder;
+use alloy_transport::{
+    layers::{RateLimitRetryPolicy, RetryPolicy},
+    RpcError, TransportError, TransportErrorKind, TransportFut,
+};
+use rand::Rng;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+use tower::{Layer, Service};
+use url::Url;
+
+/// Maximum length of the request parameters quoted in error messages.
+const MAX_PARAMS_LEN: usize = 256;
+
+/// How RPC requests are retried and rate limited.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// The maximum number of attempts of a request, including the first one.
+    pub max_attempts: u32,
+    /// The backoff before the first retry. It doubles with every retry, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    /// The maximum backoff between two attempts.
+    pub max_backoff: Duration,
+    /// The timeout of a single attempt.
+    pub request_timeout: Duration,
+    /// The maximum number of requests sent per second, if any. A JSON-RPC batch counts as one
+    /// request per call it contains.
+    pub requests_per_second: Option<u32>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(30),
+            requests_per_second: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Create a [`RetryLayer`] with this configuration.
+    ///
+    /// The rate limit is shared by every transport the layer is applied to.
+    pub fn layer(&self) -> RetryLayer {
+        RetryLayer {
+            config: self.clone(),
+            limiter: self.requests_per_second.map(|rate| Arc::new(RateLimiter::new(rate))),
+        }
+    }
+
+    /// Create an HTTP provider whose requests go through a [`RetryLayer`].
+    pub fn http_provider<N: Network>(&self, url: Url) -> RootProvider<N> {
+        RootProvider::new(ClientBuilder::default().layer(self.layer()).http(url))
+    }
+
+    /// The backoff before the retry following the `attempt`th attempt, with jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        // Wait between half and all of the backoff, so that concurrent requests failing together
+        // do not retry together.
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// A transport layer retrying failed requests with exponential backoff, and rate limiting them.
+///
+/// Rate limit errors, server errors, network errors and timeouts are retried. Other errors, such
+/// as reverted calls, are returned as is.
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    config: RetryConfig,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService { inner, config: self.config.clone(), limiter: self.limiter.clone() }
+    }
+}
+
+/// The service created by a [`RetryLayer`].
+#[derive(Debug, Clone)]
+pub struct RetryService<S> {
+    inner: S,
+    config: RetryConfig,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S> Service<RequestPacket> for RetryService<S>
+where
+    S: Service<RequestPacket, Future = TransportFut<'static>, Error = TransportError>
+        + Send
+        + Clone
+        + 'static,
+{
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        // Take the service that was driven to readiness, and leave a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let mut attempt = 1;
+            loop {
+                if let Some(limiter) = &limiter {
+                    limiter.acquire(request.len().max(1) as u32).await;
+                }
+
+                let error = match timeout(config.request_timeout, inner.call(request.clone())).await
+                {
+                    Ok(Ok(response)) => match response.as_error() {
+                        Some(error) => {
+                            let error = TransportError::ErrorResp(error.clone());
+                            if !is_retryable(&error) {
+                                return Ok(response);
+                            }
+                            error
+                        }
+                        None => return Ok(response),
+                    },
+                    Ok(Err(error)) => {
+                        if !is_retryable(&error) {
+                            return Err(error);
+                        }
+                        error
+                    }
+                    Err(_) => TransportErrorKind::custom_str(&format!(
+                        "timed out after {:?}",
+                        config.request_timeout
+                    )),
+                };
+
+                if attempt >= config.max_attempts {
+                    return Err(TransportErrorKind::custom_str(&format!(
+                        "{} failed after {} attempts: {}",
+                        describe(&request),
+                        attempt,
+                        error
+                    )));
+                }
+
+                let backoff = RateLimitRetryPolicy::default()
+                    .backoff_hint(&error)
+                    .unwrap_or_else(|| config.backoff(attempt));
+                tracing::warn!(
+                    "{} failed (attempt {}/{}), retrying in {:?}: {}",
+                    describe(&request),
+                    attempt,
+                    config.max_attempts,
+                    backoff,
+                    error
+                );
+                sleep(backoff).await;
+                attempt += 1;
+            }
+        })
+    }
+}
+
+/// Whether a request that failed with `error` may succeed if retried.
+fn is_retryable(error: &TransportError) -> bool {
+    if RateLimitRetryPolicy::default().should_retry(error) {
+        return true;
+    }
+    match error {
+        RpcError::Transport(TransportErrorKind::HttpError(error)) => {
+            error.status == 408 || error.status >= 500
+        }
+        // The HTTP transport reports connection failures as custom errors.
+        RpcError::Transport(TransportErrorKind::Custom(_)) => true,
+        _ => false,
+    }
+}
+
+/// A human-readable description of a request, for error messages.
+pub(crate) fn describe(request: &RequestPacket) -> String {
+    match request {
+        RequestPacket::Single(request) => match request.params() {
+            Some(params) => {
+                let mut params = params.get().to_string();
+                if params.len() > MAX_PARAMS_LEN {
+                    let mut end = MAX_PARAMS_LEN;
+                    while !params.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    params.truncate(end);
+                    params.push_str("...");
+                }
+                format!("{} {}", request.method(), params)
+            }
+            None => request.method().to_string(),
+        },
+        RequestPacket::Batch(requests) => {
+            let mut methods = requests.iter().map(|request| request.method()).collect::<Vec<_>>();
+            methods.sort_unstable();
+            methods.dedup();
+            format!("batch of {} requests ({})", requests.len(), methods.join(", "))
+        }
+    }
+}
+
+/// A client-side rate limiter, spacing requests evenly.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `requests` requests may be sent.
+    async fn acquire(&self, requests: u32) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval * requests;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use alloy_json_rpc::{Id, Request, Response, ResponsePayload};
+    use serde_json::value::RawValue;
+
+    use super::*;
+
+    /// A transport failing with HTTP 429 `failures` times before answering.
+    #[derive(Debug, Clone)]
+    struct FlakyTransport {
+        failures: u32,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl Service<RequestPacket> for FlakyTransport {
+        type Response = ResponsePacket;
+        type Error = TransportError;
+        type Future = TransportFut<'static>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: RequestPacket) -> Self::Future {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let failures = self.failures;
+            Box::pin(async move {
+                if call < failures {
+                    return Err(TransportErrorKind::http_error(429, String::new()));
+                }
+                Ok(ResponsePacket::Single(Response {
+                    id: Id::Number(0),
+                    payload: ResponsePayload::Success(
+                        RawValue::from_string("\"0x1\"".into()).unwrap(),
+                    ),
+                }))
+            })
+        }
+    }
+
+    fn request() -> RequestPacket {
+        Request::new("eth_blockNumber", Id::Number(0), ()).serialize().unwrap().into()
+    }
+
+    fn config(max_attempts: u32) -> RetryConfig {
+        RetryConfig {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limited_requests() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let transport = FlakyTransport { failures: 2, calls: calls.clone() };
+        let response = config(3).layer().layer(transport).call(request()).await.unwrap();
+        assert!(response.is_success());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let transport = FlakyTransport { failures: 5, calls: calls.clone() };
+        let error = config(2).layer().layer(transport).call(request()).await.unwrap_err();
+        assert!(error.to_string().contains("eth_blockNumber failed after 2 attempts"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = RetryConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..Default::default()
+        };
+        let backoff = config.backoff(3);
+        assert!(backoff >= Duration::from_millis(200) && backoff <= Duration::from_millis(400));
+        assert!(config.backoff(30) <= Duration::from_millis(1000));
+    }
+}
@@ -1,15 +1,25 @@
+use std::{collections::BTreeMap, path::Path};
+
+use alloy_genesis::GenesisAccount;
 use alloy_primitives::{address, Address};
-use alloy_provider::RootProvider;
 use alloy_rpc_types::BlockNumberOrTag;
 use alloy_sol_macro::sol;
-use alloy_sol_types::SolCall;
-use revm_primitives::{hex, Bytes};
-use sp1_cc_client_executor::{ClientExecutor, ContractInput, ContractPublicValues};
+use alloy_sol_types::{SolCall, SolValue};
+use revm_primitives::{hex, Bytes, B256, U256};
+use sp1_cc_client_executor::{
+    archive::SketchArchive,
+    io::EVMStateSketch,
+    overrides::{AccountOverride, StateOverrides},
+    BatchMode, BatchPublicValues, CallEnv, ClientExecutor, ContractCalldata, ContractInput,
+    ContractPublicValues,
+};
 use url::Url;
 use ERC20Basic::nameCall;
 use IOracleHelper::getRatesCall;
 
-use crate::HostExecutor;
+use crate::{
+    prune_sketch, FetchOptions, HostExecutor, RpcFixture, SketchReport, TrieExecutor, TrieState,
+};
 
 sol! {
     /// Simplified interface of the ERC20Basic interface.
@@ -50,6 +60,12 @@ const COLLATERALS: [Address; 12] = [
     address!("Cd5fE23C85820F7B72D0926FC9b05b43E359b7ee"),
 ];
 
+/// A counter contract.
+const COUNTER: Address = address!("00000000000000000000000000000000000000aa");
+
+/// Increments the value of slot 0, and returns it.
+const COUNTER_CODE: [u8; 18] = hex!("6000546001018060005560005260206000f3");
+
 sol! {
     /// Part of the SimpleStaking interface
     interface SimpleStaking {
@@ -69,7 +85,8 @@ async fn test_multiplexer() -> eyre::Result<()> {
         get_rates_call,
     );
 
-    let public_values = test_e2e(contract_input).await?;
+    let fixture = fixture("multiplexer", 1, "ETH_RPC_URL").await?;
+    let public_values = test_e2e(fixture, contract_input).await?;
 
     let rates = getRatesCall::abi_decode_returns(&public_values.contractOutput, true)?._0;
 
@@ -88,7 +105,8 @@ async fn test_uniswap() -> eyre::Result<()> {
         slot0_call,
     );
 
-    let public_values = test_e2e(contract_input).await?;
+    let fixture = fixture("uniswap", 1, "ETH_RPC_URL").await?;
+    let public_values = test_e2e(fixture, contract_input).await?;
 
     let _price_x96_bytes =
         IUniswapV3PoolState::slot0Call::abi_decode_returns(&public_values.contractOutput, true)?
@@ -108,7 +126,8 @@ async fn test_wrapped_eth() -> eyre::Result<()> {
         name_call,
     );
 
-    let public_values = test_e2e(contract_input).await?;
+    let fixture = fixture("wrapped_eth", 1, "ETH_RPC_URL").await?;
+    let public_values = test_e2e(fixture, contract_input).await?;
 
     let name = nameCall::abi_decode_returns(&public_values.contractOutput, true)?._0;
     assert_eq!(name, String::from("Wrapped Ether"));
@@ -119,55 +138,514 @@ async fn test_wrapped_eth() -> eyre::Result<()> {
 /// This tests contract creation transactions.
 #[tokio::test(flavor = "multi_thread")]
 async fn test_contract_creation() -> eyre::Result<()> {
-    // Load environment variables.
-    dotenv::dotenv().ok();
-
+    // Returns the coinbase of the block.
     let bytecode = "0x6080604052348015600e575f5ffd5b50415f5260205ff3fe";
 
-    // Get a recent blob to get the hash from.
-    let block_number = BlockNumberOrTag::Safe;
-
     // Use `ETH_SEPOLIA_RPC_URL` to get all of the necessary state for the smart contract call.
-    let rpc_url = std::env::var("ETH_SEPOLIA_RPC_URL")
-        .unwrap_or_else(|_| panic!("Missing ETH_SEPOLIA_RPC_URL in env"));
-    let provider = RootProvider::new_http(Url::parse(&rpc_url)?);
-    let mut host_executor = HostExecutor::new(provider.clone(), block_number).await?;
+    let fixture = fixture("contract_creation", 11155111, "ETH_SEPOLIA_RPC_URL").await?;
 
-    // Keep track of the block hash. Later, validate the client's execution against this.
     let bytes = hex::decode(bytecode).expect("Decoding failed");
     println!("Checking coinbase");
     let contract_input = ContractInput::new_create(Address::default(), Bytes::from(bytes));
-    let _check_coinbase = host_executor.execute(contract_input).await?;
+    let public_values = test_e2e(fixture, contract_input).await?;
+    assert_eq!(public_values.contractOutput.len(), 32);
     Ok(())
 }
 
-/// Emulates the entire workflow of executing a smart contract call, without using SP1.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_batched_fetch() -> eyre::Result<()> {
+    let fixture = fixture("batched_fetch", 1, "ETH_RPC_URL").await?;
+
+    let get_rates_call = getRatesCall { collaterals: COLLATERALS.to_vec() };
+    let contract_input = ContractInput::new_call(
+        address!("0A8c00EcFA0816F4f09289ac52Fcb88eA5337526"),
+        Address::default(),
+        get_rates_call,
+    );
+
+    let mut host_executor =
+        HostExecutor::new(fixture.provider, fixture.block_number.into()).await?;
+    host_executor.execute(contract_input.clone()).await?;
+    let sequential = host_executor.finalize().await?;
+
+    // The same state, fetched with JSON-RPC batches, must give the same sketch.
+    let host_executor =
+        host_executor.with_fetch_options(FetchOptions { concurrency: 4, batch_size: Some(5) });
+    let batched = host_executor.finalize().await?;
+    assert_eq!(sequential.ancestor_headers, batched.ancestor_headers);
+    assert_eq!(sequential.state.state_root(), batched.state.state_root());
+    assert_eq!(batched.state.state_root(), batched.header.state_root);
+
+    let public_values = ClientExecutor::new(&batched)?.execute(contract_input)?;
+    let rates = getRatesCall::abi_decode_returns(&public_values.contractOutput, true)?._0;
+    assert_eq!(rates.len(), COLLATERALS.len());
+
+    Ok(())
+}
+
+/// Checks that the calls of a batch see the state changes of the previous ones in sequential
+/// mode only.
+#[test]
+fn test_execute_batch() -> eyre::Result<()> {
+    let increment = counter_call(COUNTER);
+    let sketch = devnet_sketch(&counter(5), &[increment.clone()])?;
+
+    let outputs = |public_values: BatchPublicValues| {
+        public_values
+            .calls
+            .into_iter()
+            .map(|call| {
+                assert!(call.success);
+                U256::from_be_slice(&call.contractOutput)
+            })
+            .collect::<Vec<_>>()
+    };
+    let calls = vec![increment.clone(), increment];
+    let client_executor = ClientExecutor::new(&sketch)?;
+    let sequential = client_executor.execute_batch(calls.clone(), BatchMode::Sequential)?;
+    assert!(sequential.sequential);
+    assert_eq!(sequential.blockHash, sketch.header.hash_slow());
+    assert_eq!(outputs(sequential), [U256::from(6), U256::from(7)]);
+    let independent = client_executor.execute_batch(calls.clone(), BatchMode::Independent)?;
+    assert_eq!(outputs(independent), [U256::from(6), U256::from(6)]);
+
+    let archive_bytes = sketch.to_archive();
+    let archive = SketchArchive::new(&archive_bytes)?;
+    let archive_executor = ClientExecutor::from_archive(&archive);
+    let sequential = archive_executor.execute_batch(calls, BatchMode::Sequential)?;
+    assert_eq!(outputs(sequential), [U256::from(6), U256::from(7)]);
+
+    Ok(())
+}
+
+/// Checks that a call runs with the transaction environment it is given, and commits it.
+#[test]
+fn test_call_env() -> eyre::Result<()> {
+    // Returns the call value and the gas price.
+    let contract = address!("00000000000000000000000000000000000000aa");
+    let code = hex::decode("346000523a60205260406000f3")?;
+    let caller = address!("00000000000000000000000000000000000000bb");
+    let accounts = BTreeMap::from([
+        (contract, GenesisAccount::default().with_code(Some(code.into()))),
+        (caller, GenesisAccount::default().with_balance(U256::from(10u128.pow(19)))),
+    ]);
+
+    let env = CallEnv {
+        value: U256::from(10u128.pow(18)),
+        gas_limit: Some(100_000),
+        gas_price: U256::from(3_000_000_000u64),
+        gas_priority_fee: Some(U256::from(1_000_000_000)),
+        nonce: Some(0),
+        ..Default::default()
+    };
+    let call = ContractInput {
+        contract_address: contract,
+        caller_address: caller,
+        calldata: ContractCalldata::Call(Bytes::new()),
+        env: env.clone(),
+        overrides: StateOverrides::new(),
+    };
+    let sketch = devnet_sketch(&accounts, &[call.clone()])?;
+    let client_executor = ClientExecutor::new(&sketch)?;
+
+    // The effective gas price is the base fee of the block plus the priority fee.
+    let public_values = client_executor.execute(call.clone())?;
+    assert_eq!(U256::from_be_slice(&public_values.contractOutput[..32]), env.value);
+    assert_eq!(U256::from_be_slice(&public_values.contractOutput[32..]), U256::from(2_000_000_000));
+    let environment = &public_values.environment;
+    assert_eq!(environment.value, env.value);
+    assert_eq!(environment.gasLimit, 100_000);
+    assert_eq!(environment.gasPrice, env.gas_price);
+    assert_eq!(environment.gasPriorityFee, U256::from(1_000_000_000));
+    assert!(environment.checkNonce);
+
+    // The defaults pay no gas and use the gas limit of the block.
+    let default_env = ClientExecutor::new(&sketch)?
+        .execute(call.clone().with_env(CallEnv::default()))?
+        .environment;
+    assert_eq!(default_env.gasLimit, sketch.header.gas_limit);
+    assert_eq!(default_env.gasPrice, U256::ZERO);
+    assert!(!default_env.checkNonce);
+
+    let wrong_nonce = CallEnv { nonce: Some(1), ..env };
+    assert!(client_executor.execute(call.with_env(wrong_nonce)).is_err());
+
+    Ok(())
+}
+
+/// Checks that calls run on top of their state overrides, and commit them.
+#[test]
+fn test_state_overrides() -> eyre::Result<()> {
+    let increment = counter_call(COUNTER);
+    let patched = increment.clone().with_override(
+        COUNTER,
+        AccountOverride {
+            state_diff: BTreeMap::from([(B256::ZERO, B256::from(U256::from(41)))]),
+            ..Default::default()
+        },
+    );
+    // Replaces the code of the counter by one returning the balance of the account in slot 0,
+    // which does not exist in the state.
+    let holder = address!("00000000000000000000000000000000000000cc");
+    let balance = increment
+        .clone()
+        .with_override(
+            COUNTER,
+            AccountOverride {
+                code: Some(hex::decode("6000543160005260206000f3")?.into()),
+                state: Some(BTreeMap::from([(B256::ZERO, holder.into_word())])),
+                ..Default::default()
+            },
+        )
+        .with_override(
+            holder,
+            AccountOverride { balance: Some(U256::from(77)), ..Default::default() },
+        );
+    let calls = [increment.clone(), patched.clone(), balance.clone()];
+    let sketch = devnet_sketch(&counter(5), &calls)?;
+    let client_executor = ClientExecutor::new(&sketch)?;
+
+    let output =
+        |public_values: &ContractPublicValues| U256::from_be_slice(&public_values.contractOutput);
+    assert_eq!(output(&client_executor.execute(increment)?), U256::from(6));
+    let public_values = client_executor.execute(patched)?;
+    assert_eq!(output(&public_values), U256::from(42));
+    let committed = &public_values.stateOverrides;
+    assert_eq!(committed.len(), 1);
+    assert_eq!(committed[0].account, COUNTER);
+    assert!(!committed[0].replaceStorage && !committed[0].overrideCode);
+    assert_eq!(committed[0].slots[0].value, B256::from(U256::from(41)));
+
+    let public_values = client_executor.execute(balance.clone())?;
+    assert_eq!(output(&public_values), U256::from(77));
+    assert!(public_values.stateOverrides[0].overrideCode);
+    assert!(public_values.stateOverrides[0].replaceStorage);
+
+    // Pruning keeps the state the overridden call reads.
+    let (pruned, _) = prune_sketch(&sketch, &[balance.clone()])?;
+    assert_eq!(output(&ClientExecutor::new(&pruned)?.execute(balance)?), U256::from(77));
+
+    Ok(())
+}
+
+/// Checks that a deployless call deploys its contract before calling it.
+#[test]
+fn test_deployless_call() -> eyre::Result<()> {
+    // Sets slot 0 to 5, and deploys a contract incrementing it and returning it.
+    let init_code = Bytes::from(hex::decode(
+        "60056000556012601160003960126000f36000546001018060005560005260206000f3",
+    )?);
+    let call =
+        ContractInput::new_deployless_call(Address::default(), init_code.clone(), nameCall {});
+    let sketch = devnet_sketch(&BTreeMap::new(), &[call.clone()])?;
+    let client_executor = ClientExecutor::new(&sketch)?;
+
+    let deployed = Address::default().create(0);
+    let public_values = client_executor.execute(call.clone())?;
+    assert_eq!(U256::from_be_slice(&public_values.contractOutput), U256::from(6));
+    assert_eq!(public_values.contractAddress, deployed);
+    assert_eq!(public_values.initCode, init_code);
+    assert_eq!(public_values.contractCalldata, Bytes::from(nameCall {}.abi_encode()));
+
+    // In sequential mode, the contract stays deployed for the next calls.
+    let increment = counter_call(deployed);
+    let batch = client_executor.execute_batch(vec![call, increment], BatchMode::Sequential)?;
+    assert_eq!(batch.calls[0].contractAddress, deployed);
+    let outputs = batch
+        .calls
+        .iter()
+        .map(|call| U256::from_be_slice(&call.contractOutput))
+        .collect::<Vec<_>>();
+    assert_eq!(outputs, [U256::from(6), U256::from(7)]);
+
+    Ok(())
+}
+
+/// Checks that the sketch generated by the host is valid, also after going through its canonical
+/// encoding.
+#[test]
+fn test_validate_sketch() -> eyre::Result<()> {
+    let sketch = devnet_sketch(&counter(5), &[counter_call(COUNTER)])?;
+    sketch.validate()?;
+    EVMStateSketch::decode(&sketch.encode())?.validate()?;
+
+    // The state must match the state root of the block.
+    let mut invalid = sketch;
+    invalid.header.state_root = B256::ZERO;
+    assert!(invalid.validate().is_err());
+
+    Ok(())
+}
+
+/// Checks that the report of a sketch describes its content.
+#[test]
+fn test_sketch_report() -> eyre::Result<()> {
+    let sketch = devnet_sketch(&counter(5), &[counter_call(COUNTER)])?;
+    let report = SketchReport::new(&sketch);
+    assert_eq!(report.validation_error, None);
+    assert_eq!(report.content_hash, sketch.content_hash());
+    assert_eq!(report.block_hash, sketch.header.hash_slow());
+    assert_eq!(report.encoded_sizes.total(), sketch.encode().len() as u64);
+    assert_eq!(report.accounts.len(), sketch.state_requests.len());
+
+    let account = report.accounts.iter().find(|account| account.address == COUNTER).unwrap();
+    assert!(account.provable);
+    assert_eq!(account.slots.len(), 1);
+    assert_eq!(account.slots[0].value, Some(U256::from(5)));
+    assert!(report.bytecodes.iter().any(|bytecode| bytecode.code[..] == COUNTER_CODE));
+
+    let mut invalid = sketch;
+    invalid.header.state_root = B256::ZERO;
+    assert!(SketchReport::new(&invalid).validation_error.is_some());
+
+    Ok(())
+}
+
+/// Checks that pruning a sketch down to some of its calls drops the state of the other ones, and
+/// keeps the sketch valid for the calls kept.
+#[test]
+fn test_prune_sketch() -> eyre::Result<()> {
+    let other = address!("00000000000000000000000000000000000000a3");
+    let mut accounts = counter(5);
+    accounts.insert(other, accounts[&COUNTER].clone());
+    let calls = [counter_call(COUNTER), counter_call(other)];
+    let sketch = devnet_sketch(&accounts, &calls)?;
+
+    let (pruned, report) = prune_sketch(&sketch, &calls[..1])?;
+    pruned.validate()?;
+    assert_eq!(report.before, sketch.encoded_sizes());
+    assert_eq!(report.after, pruned.encoded_sizes());
+    assert!(report.bytes_saved() > 0);
+    assert_eq!(report.accounts_removed, 1);
+    assert!(!pruned.state_requests.contains_key(&other));
+
+    let output = |sketch: &EVMStateSketch| -> eyre::Result<Bytes> {
+        Ok(ClientExecutor::new(sketch)?.execute(calls[0].clone())?.contractOutput)
+    };
+    assert_eq!(output(&pruned)?, output(&sketch)?);
+
+    // Pruning a sketch down to all of its calls removes no account.
+    let (_, report) = prune_sketch(&sketch, &calls)?;
+    assert_eq!(report.accounts_removed, 0);
+
+    Ok(())
+}
+
+/// Checks that reading a sketch in place from its archive gives the same public values as
+/// decoding it.
+#[test]
+fn test_archive() -> eyre::Result<()> {
+    let call = counter_call(COUNTER);
+    let sketch = devnet_sketch(&counter(5), &[call.clone()])?;
+    let public_values = ClientExecutor::new(&sketch)?.execute(call.clone())?;
+
+    let archive_bytes = sketch.to_archive();
+    let archive = SketchArchive::new(&archive_bytes)?;
+    let archive_public_values = ClientExecutor::from_archive(&archive).execute(call)?;
+    assert_eq!(archive_public_values.abi_encode(), public_values.abi_encode());
+
+    Ok(())
+}
+
+/// The state of a devnet holding [`COUNTER`], with `value` in slot 0.
+fn counter(value: u64) -> BTreeMap<Address, GenesisAccount> {
+    let storage = BTreeMap::from([(B256::ZERO, B256::from(U256::from(value)))]);
+    let account = GenesisAccount::default()
+        .with_code(Some(Bytes::from_static(&COUNTER_CODE)))
+        .with_storage(Some(storage));
+    BTreeMap::from([(COUNTER, account)])
+}
+
+/// A call incrementing the counter at `address`.
+fn counter_call(address: Address) -> ContractInput {
+    ContractInput {
+        contract_address: address,
+        caller_address: Address::default(),
+        calldata: ContractCalldata::Call(Bytes::new()),
+        env: CallEnv::default(),
+        overrides: StateOverrides::new(),
+    }
+}
+
+/// Executes `calls` on a devnet holding `accounts`, and returns the sketch of their state.
+fn devnet_sketch(
+    accounts: &BTreeMap<Address, GenesisAccount>,
+    calls: &[ContractInput],
+) -> eyre::Result<EVMStateSketch> {
+    let mut executor = TrieExecutor::devnet(accounts);
+    futures::executor::block_on(async {
+        for call in calls {
+            executor.execute(call.clone()).await?;
+        }
+        executor.finalize().await
+    })
+}
+
+/// Checks that a sketch generated from the datadir of a reth node is the same as the one generated
+/// from the trie nodes of the same state, on a datadir holding the genesis of a devnet.
+#[cfg(feature = "datadir")]
+#[test]
+fn test_datadir_matches_trie() -> eyre::Result<()> {
+    use std::sync::Arc;
+
+    use alloy_genesis::Genesis;
+    use reth_chainspec::{Chain, ChainSpec};
+    use reth_db::{init_db, mdbx::DatabaseArguments, models::ClientVersion};
+    use reth_db_common::init::init_genesis;
+    use reth_provider::{providers::StaticFileProvider, ProviderFactory};
+
+    use crate::{datadir::DatadirNode, DatadirExecutor};
+
+    let accounts = counter(5);
+    let increment = counter_call(COUNTER);
+
+    // Write the genesis of a devnet holding the counter to a new datadir.
+    let genesis = Genesis {
+        timestamp: 1_750_000_000,
+        gas_limit: 30_000_000,
+        base_fee_per_gas: Some(1_000_000_000),
+        alloc: accounts.clone(),
+        ..Default::default()
+    };
+    let chain_spec =
+        ChainSpec::builder().chain(Chain::dev()).genesis(genesis).cancun_activated().build();
+    let datadir = tempfile::tempdir()?;
+    let db = init_db(datadir.path().join("db"), DatabaseArguments::new(ClientVersion::default()))?;
+    let static_files = StaticFileProvider::read_write(datadir.path().join("static_files"))?;
+    let factory =
+        ProviderFactory::<DatadirNode>::new(Arc::new(db), Arc::new(chain_spec), static_files);
+    init_genesis(&factory)?;
+
+    let mut datadir_executor = DatadirExecutor::from_factory(factory, 0)?;
+    let header = datadir_executor.header.clone();
+    let mut trie_executor =
+        TrieExecutor::from_state(TrieState::from_accounts(&accounts), header, Vec::new())?;
+
+    let (datadir_output, datadir_sketch, trie_output, trie_sketch) =
+        futures::executor::block_on(async {
+            let datadir_output = datadir_executor.execute(increment.clone()).await?;
+            let trie_output = trie_executor.execute(increment.clone()).await?;
+            eyre::Ok((
+                datadir_output,
+                datadir_executor.finalize().await?,
+                trie_output,
+                trie_executor.finalize().await?,
+            ))
+        })?;
+    assert_eq!(U256::from_be_slice(&datadir_output), U256::from(6));
+    assert_eq!(datadir_output, trie_output);
+    assert_same_sketch(&datadir_sketch, &trie_sketch);
+
+    let public_values = ClientExecutor::new(&datadir_sketch)?.execute(increment)?;
+    assert_eq!(public_values.contractOutput, datadir_output);
+
+    Ok(())
+}
+
+/// Checks that a sketch generated from the datadir of a reth node is the same as the one generated
+/// over JSON-RPC, from a node of the same chain.
+///
+/// Needs a synced mainnet node: run with `RETH_DATADIR` and `ETH_RPC_URL` set, and
+/// `--features datadir -- --ignored`.
+#[cfg(feature = "datadir")]
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn test_datadir_matches_rpc() -> eyre::Result<()> {
+    use reth_provider::BlockNumReader;
+
+    use crate::{datadir::open_datadir, DatadirExecutor, RetryConfig};
+
+    dotenv::dotenv().ok();
+    let datadir = std::env::var("RETH_DATADIR")?;
+    let rpc_url = Url::parse(&std::env::var("ETH_RPC_URL")?)?;
+
+    let get_rates_call = getRatesCall { collaterals: COLLATERALS.to_vec() };
+    let contract_input = ContractInput::new_call(
+        address!("0A8c00EcFA0816F4f09289ac52Fcb88eA5337526"),
+        Address::default(),
+        get_rates_call,
+    );
+
+    let factory = open_datadir(Path::new(&datadir), reth_chainspec::MAINNET.clone())?;
+    let block_number = factory.last_block_number()?;
+    let mut datadir_executor = DatadirExecutor::from_factory(factory, block_number)?;
+    let datadir_output = datadir_executor.execute(contract_input.clone()).await?;
+    let datadir_sketch = datadir_executor.finalize().await?;
+
+    let provider = RetryConfig::default().http_provider(rpc_url);
+    let mut host_executor = HostExecutor::new(provider, block_number.into()).await?;
+    let rpc_output = host_executor.execute(contract_input.clone()).await?;
+    let rpc_sketch = host_executor.finalize().await?;
+
+    assert_eq!(datadir_output, rpc_output);
+    assert_same_sketch(&datadir_sketch, &rpc_sketch);
+
+    let public_values = ClientExecutor::new(&datadir_sketch)?.execute(contract_input)?;
+    assert_eq!(public_values.contractOutput, rpc_output);
+
+    Ok(())
+}
+
+/// Asserts that two sketches of the same block hold the same state, in whatever order their state
+/// requests and bytecodes are.
+#[cfg(feature = "datadir")]
+fn assert_same_sketch(sketch: &EVMStateSketch, expected: &EVMStateSketch) {
+    use std::collections::BTreeSet;
+
+    assert_eq!(sketch.header, expected.header);
+    assert_eq!(sketch.ancestor_headers, expected.ancestor_headers);
+    assert_eq!(sketch.state.state_root(), expected.state.state_root());
+
+    let state_requests = |sketch: &EVMStateSketch| {
+        sketch
+            .state_requests
+            .iter()
+            .map(|(address, slots)| (*address, slots.iter().copied().collect::<BTreeSet<_>>()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(state_requests(sketch), state_requests(expected));
+    let bytecodes = |sketch: &EVMStateSketch| {
+        sketch.bytecodes.iter().map(|bytecode| bytecode.hash_slow()).collect::<BTreeSet<_>>()
+    };
+    assert_eq!(bytecodes(sketch), bytecodes(expected));
+}
+
+/// Replays the RPC fixture recorded in `fixtures/<name>`.
 ///
-/// First, executes the smart contract call with the given [`ContractInput`] in the host executor.
-/// After getting the [`EVMStateSketch`] from the host executor, executes the same smart contract   
-/// call in the client executor.
-async fn test_e2e(contract_input: ContractInput) -> eyre::Result<ContractPublicValues> {
+/// If the fixture was not recorded yet, it is recorded from the RPC in the `rpc_url_var`
+/// environment variable.
+async fn fixture(name: &str, chain_id: u64, rpc_url_var: &str) -> eyre::Result<RpcFixture> {
     // Load environment variables.
     dotenv::dotenv().ok();
 
-    // Which block transactions are executed on.
-    let block_number = BlockNumberOrTag::Latest;
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name);
+    let rpc_url = std::env::var(rpc_url_var).ok().map(|url| Url::parse(&url)).transpose()?;
+    RpcFixture::replay_or_record(&dir, chain_id, rpc_url).await
+}
 
-    // Prepare the host executor.
-    //
-    // Use `RPC_URL` to get all of the necessary state for the smart contract call.
-    let rpc_url = std::env::var("ETH_RPC_URL").unwrap_or_else(|_| panic!("Missing RPC_URL"));
-    let provider = RootProvider::new_http(Url::parse(&rpc_url)?);
-    let mut host_executor = HostExecutor::new(provider.clone(), block_number).await?;
+/// Emulates the entire workflow of executing a smart contract call, without using SP1.
+///
+/// First, executes the smart contract call with the given [`ContractInput`] in the host executor,
+/// checking it against the node's `eth_call`.
+/// After getting the [`EVMStateSketch`] from the host executor, executes the same smart contract
+/// call in the client executor, on the sketch decoded from its canonical encoding.
+async fn test_e2e(
+    fixture: RpcFixture,
+    contract_input: ContractInput,
+) -> eyre::Result<ContractPublicValues> {
+    let block_number = BlockNumberOrTag::Number(fixture.block_number);
+    let mut host_executor =
+        HostExecutor::new(fixture.provider, block_number).await?.with_call_verification(true);
 
     let _contract_output = host_executor.execute(contract_input.clone()).await?;
 
     // Now that we've executed all of the calls, get the `EVMStateSketch` from the host executor.
     let state_sketch = host_executor.finalize().await?;
+    let state_sketch = EVMStateSketch::decode(&state_sketch.encode())?;
 
     let client_executor = ClientExecutor::new(&state_sketch)?;
-
     let public_values = client_executor.execute(contract_input)?;
+    assert_eq!(public_values.blockHash, host_executor.header.hash_slow());
 
     Ok(public_values)
 }
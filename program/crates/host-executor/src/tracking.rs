@@ -0,0 +1,137 @@
+//! A database recording the state accessed through it.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use revm::DatabaseRef;
+use revm_primitives::{AccountInfo, Address, Bytecode, HashMap, B256, KECCAK_EMPTY, U256};
+
+/// A [`DatabaseRef`] recording the accounts, storage slots, bytecodes and block hashes accessed
+/// through it, so that the witness of the accessed state can be generated afterwards.
+///
+/// Every [`HostExecutor`] records the state accessed by its calls through it, whatever the source
+/// of the state.
+///
+/// [`HostExecutor`]: crate::HostExecutor
+#[derive(Debug, Clone)]
+pub(crate) struct TrackingDb<D> {
+    /// The database the state is read from.
+    pub(crate) inner: D,
+    /// The storage slots accessed, by account.
+    accounts: RefCell<BTreeMap<Address, BTreeSet<U256>>>,
+    /// The bytecodes of the accounts accessed, by code hash.
+    bytecodes: RefCell<BTreeMap<B256, Bytecode>>,
+    /// The oldest block whose hash was accessed.
+    oldest_ancestor: RefCell<u64>,
+}
+
+impl<D> TrackingDb<D> {
+    /// Track the state accessed in `inner` at the end of block `block_number`.
+    pub(crate) fn new(inner: D, block_number: u64) -> Self {
+        Self {
+            inner,
+            accounts: RefCell::default(),
+            bytecodes: RefCell::default(),
+            oldest_ancestor: RefCell::new(block_number),
+        }
+    }
+
+    /// The storage slots accessed, by account.
+    pub(crate) fn state_requests(&self) -> HashMap<Address, Vec<U256>> {
+        self.accounts
+            .borrow()
+            .iter()
+            .map(|(address, slots)| (*address, slots.iter().copied().collect()))
+            .collect()
+    }
+
+    /// The bytecodes of the accounts accessed.
+    pub(crate) fn bytecodes(&self) -> Vec<Bytecode> {
+        self.bytecodes.borrow().values().cloned().collect()
+    }
+
+    /// The oldest block whose hash was accessed, or the block itself if none was.
+    pub(crate) fn oldest_ancestor(&self) -> u64 {
+        *self.oldest_ancestor.borrow()
+    }
+}
+
+impl<D: DatabaseRef> DatabaseRef for TrackingDb<D> {
+    type Error = D::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.accounts.borrow_mut().entry(address).or_default();
+        let Some(mut info) = self.inner.basic_ref(address)? else { return Ok(None) };
+
+        // The witness needs the code of every account accessed, even if it is never run.
+        if info.code_hash != KECCAK_EMPTY {
+            let code = match info.code.take() {
+                Some(code) => code,
+                None => self.inner.code_by_hash_ref(info.code_hash)?,
+            };
+            self.bytecodes.borrow_mut().insert(info.code_hash, code.clone());
+            info.code = Some(code);
+        }
+        Ok(Some(info))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self.inner.code_by_hash_ref(code_hash)?;
+        self.bytecodes.borrow_mut().insert(code_hash, code.clone());
+        Ok(code)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.accounts.borrow_mut().entry(address).or_default().insert(index);
+        self.inner.storage_ref(address, index)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        let mut oldest_ancestor = self.oldest_ancestor.borrow_mut();
+        *oldest_ancestor = (*oldest_ancestor).min(number);
+        drop(oldest_ancestor);
+        self.inner.block_hash_ref(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::db::InMemoryDB;
+    use revm_primitives::{address, Bytes};
+
+    use super::*;
+
+    #[test]
+    fn test_tracking() {
+        let contract = address!("0000000000000000000000000000000000000001");
+        let caller = address!("0000000000000000000000000000000000000002");
+        let code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x00]));
+
+        let mut state = InMemoryDB::default();
+        state.insert_account_info(
+            contract,
+            AccountInfo {
+                code_hash: code.hash_slow(),
+                code: Some(code.clone()),
+                ..Default::default()
+            },
+        );
+        state.insert_account_storage(contract, U256::from(1), U256::from(42)).unwrap();
+
+        let db = TrackingDb::new(state, 100);
+        assert!(db.basic_ref(caller).unwrap().is_none());
+        assert_eq!(db.basic_ref(contract).unwrap().unwrap().code, Some(code.clone()));
+        assert_eq!(db.storage_ref(contract, U256::from(1)).unwrap(), U256::from(42));
+        assert_eq!(db.storage_ref(contract, U256::from(0)).unwrap(), U256::ZERO);
+        db.block_hash_ref(97).unwrap();
+        db.block_hash_ref(99).unwrap();
+
+        let state_requests = db.state_requests();
+        assert_eq!(state_requests[&caller], Vec::<U256>::new());
+        assert_eq!(state_requests[&contract], vec![U256::from(0), U256::from(1)]);
+        assert_eq!(db.bytecodes(), vec![code]);
+        assert_eq!(db.oldest_ancestor(), 97);
+    }
+}
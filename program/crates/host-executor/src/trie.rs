@@ -0,0 +1,713 @@
+//! Witness generation from a set of trie nodes held in memory.
+//!
+//! A node can serve the whole pre-state of a block at once with `debug_executionWitness`, and a
+//! devnet can dump its entire state to a file. In both cases, the host has everything it needs
+//! without calling `eth_getProof` for every account: the [`TrieState`] walks the state trie itself
+//! to read accounts and storage, and generates the account proofs from the same nodes.
+//!
+//! The [`TrieExecutor`] is a [`HostExecutor`] reading a [`TrieState`], and produces the same
+//! [`EVMStateSketch`] as over JSON-RPC.
+//!
+//! [`EVMStateSketch`]: sp1_cc_client_executor::io::EVMStateSketch
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::Path,
+};
+
+use alloy_genesis::GenesisAccount;
+use alloy_primitives::{keccak256, Address};
+use alloy_provider::{network::AnyNetwork, Provider};
+use alloy_rlp::Decodable;
+use alloy_rpc_types::{
+    BlockNumberOrTag, BlockTransactionsKind, EIP1186AccountProofResponse, EIP1186StorageProof,
+};
+use alloy_rpc_types_debug::ExecutionWitness;
+use alloy_trie::{
+    nodes::TrieNode, proof::ProofRetainer, HashBuilder, Nibbles, TrieAccount, EMPTY_ROOT_HASH,
+    KECCAK_EMPTY,
+};
+use eyre::{bail, eyre, WrapErr};
+use reth_primitives::Header;
+use reth_trie::AccountProof;
+use revm::DatabaseRef;
+use revm_primitives::{AccountInfo, Bytecode, Bytes, B256, U256};
+use rsp_primitives::account_proof::eip1186_proof_to_account_proof;
+use serde::Deserialize;
+
+use crate::{block_to_header, HostExecutor, ProofSource};
+
+/// An error reading a [`TrieState`].
+#[derive(Debug)]
+pub enum TrieStateError {
+    /// A trie node needed to read the state is missing.
+    MissingNode(B256),
+    /// The bytecode of an account is missing.
+    MissingCode(B256),
+    /// The hash of a block is not known.
+    MissingBlockHash(u64),
+    /// A trie node or bytecode does not hash to the hash it is given with.
+    InvalidHash(B256),
+    /// The trie nodes on the path of a key go deeper than the key.
+    InvalidPath(B256),
+    /// A trie node or value could not be decoded.
+    Rlp(alloy_rlp::Error),
+}
+
+impl fmt::Display for TrieStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingNode(hash) => write!(f, "trie node {} is missing from the state", hash),
+            Self::MissingCode(hash) => write!(f, "bytecode {} is missing from the state", hash),
+            Self::MissingBlockHash(number) => write!(f, "the hash of block {} is unknown", number),
+            Self::InvalidHash(hash) => write!(f, "the preimage of {} has another hash", hash),
+            Self::InvalidPath(key) => {
+                write!(f, "the trie nodes on the path of {} are invalid", key)
+            }
+            Self::Rlp(error) => write!(f, "invalid trie node: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TrieStateError {}
+
+impl From<alloy_rlp::Error> for TrieStateError {
+    fn from(error: alloy_rlp::Error) -> Self {
+        Self::Rlp(error)
+    }
+}
+
+/// The state of a block, as the nodes of its account and storage tries.
+///
+/// Only the parts of the tries whose nodes are present can be read: reading anything else fails
+/// with [`TrieStateError::MissingNode`].
+#[derive(Debug, Clone, Default)]
+pub struct TrieState {
+    /// The root of the account trie.
+    pub state_root: B256,
+    /// The trie nodes, by hash.
+    pub nodes: HashMap<B256, Bytes>,
+    /// The bytecodes, by hash.
+    pub codes: HashMap<B256, Bytes>,
+}
+
+impl TrieState {
+    /// Create the state rooted at `state_root` from a `debug_executionWitness` response.
+    ///
+    /// The witness of a block holds the state its transactions access, at the end of its parent
+    /// block: only the calls touching this state can be executed.
+    ///
+    /// Fails with [`TrieStateError::InvalidHash`] if a trie node or bytecode of the witness does
+    /// not hash to its key.
+    pub fn from_execution_witness(
+        state_root: B256,
+        witness: ExecutionWitness,
+    ) -> Result<Self, TrieStateError> {
+        let check = |(hash, preimage): (B256, Bytes)| {
+            if keccak256(&preimage) != hash {
+                return Err(TrieStateError::InvalidHash(hash));
+            }
+            Ok((hash, preimage))
+        };
+        Ok(Self {
+            state_root,
+            nodes: witness.state.into_iter().map(check).collect::<Result<_, _>>()?,
+            codes: witness.codes.into_iter().map(check).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Build the tries of a full state, such as the `alloc` of a `genesis.json`.
+    pub fn from_accounts(accounts: &BTreeMap<Address, GenesisAccount>) -> Self {
+        let mut nodes = HashMap::new();
+        let mut codes = HashMap::new();
+
+        let leaves = accounts.iter().map(|(address, account)| {
+            let storage = account.storage.iter().flatten().filter(|(_, value)| !value.is_zero());
+            let storage_root = build_trie(
+                storage.map(|(slot, value)| {
+                    (keccak256(slot), alloy_rlp::encode(U256::from_be_bytes(value.0)))
+                }),
+                &mut nodes,
+            );
+            let code_hash = match account.code.as_ref().filter(|code| !code.is_empty()) {
+                Some(code) => {
+                    let code_hash = keccak256(code);
+                    codes.insert(code_hash, code.clone());
+                    code_hash
+                }
+                None => KECCAK_EMPTY,
+            };
+            let account = TrieAccount {
+                nonce: account.nonce.unwrap_or_default(),
+                balance: account.balance,
+                storage_root,
+                code_hash,
+            };
+            (keccak256(address), alloy_rlp::encode(account))
+        });
+        let leaves = leaves.collect::<Vec<_>>();
+        let state_root = build_trie(leaves, &mut nodes);
+
+        Self { state_root, nodes, codes }
+    }
+
+    /// Reads an account.
+    pub fn account(&self, address: Address) -> Result<Option<TrieAccount>, TrieStateError> {
+        self.account_with_proof(address, &mut Vec::new())
+    }
+
+    /// Reads a storage slot of an account.
+    pub fn storage(&self, address: Address, slot: B256) -> Result<U256, TrieStateError> {
+        let Some(account) = self.account(address)? else { return Ok(U256::ZERO) };
+        self.storage_with_proof(account.storage_root, slot, &mut Vec::new())
+    }
+
+    /// Generates the proof of an account and of some of its storage slots, as served by
+    /// `eth_getProof`.
+    pub fn proof(
+        &self,
+        address: Address,
+        keys: &[B256],
+    ) -> Result<EIP1186AccountProofResponse, TrieStateError> {
+        let mut account_proof = Vec::new();
+        let account = self.account_with_proof(address, &mut account_proof)?.unwrap_or_default();
+
+        let storage_proof = keys
+            .iter()
+            .map(|key| {
+                let mut proof = Vec::new();
+                let value = self.storage_with_proof(account.storage_root, *key, &mut proof)?;
+                Ok(EIP1186StorageProof { key: (*key).into(), value, proof })
+            })
+            .collect::<Result<_, TrieStateError>>()?;
+
+        Ok(EIP1186AccountProofResponse {
+            address,
+            balance: account.balance,
+            code_hash: account.code_hash,
+            nonce: account.nonce,
+            storage_hash: account.storage_root,
+            account_proof,
+            storage_proof,
+        })
+    }
+
+    /// Reads an account, pushing the nodes on its path to `proof`.
+    fn account_with_proof(
+        &self,
+        address: Address,
+        proof: &mut Vec<Bytes>,
+    ) -> Result<Option<TrieAccount>, TrieStateError> {
+        match self.get(self.state_root, keccak256(address), proof)? {
+            Some(value) => Ok(Some(TrieAccount::decode(&mut value.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a storage slot from the storage trie rooted at `storage_root`, pushing the nodes on
+    /// its path to `proof`.
+    fn storage_with_proof(
+        &self,
+        storage_root: B256,
+        slot: B256,
+        proof: &mut Vec<Bytes>,
+    ) -> Result<U256, TrieStateError> {
+        match self.get(storage_root, keccak256(slot), proof)? {
+            Some(value) => Ok(U256::decode(&mut value.as_slice())?),
+            None => Ok(U256::ZERO),
+        }
+    }
+
+    /// Walks the trie rooted at `root` down to the leaf of `key`, pushing the nodes on the path to
+    /// `proof`, and returns the value of the leaf if there is one.
+    fn get(
+        &self,
+        root: B256,
+        key: B256,
+        proof: &mut Vec<Bytes>,
+    ) -> Result<Option<Vec<u8>>, TrieStateError> {
+        if root == EMPTY_ROOT_HASH {
+            return Ok(None);
+        }
+
+        let path = Nibbles::unpack(key);
+        let mut depth = 0;
+        let mut node = self.node(root)?;
+        proof.push(node.clone());
+        loop {
+            // The nodes of an invalid trie can go deeper than the path.
+            let rest = path.get(depth..).ok_or(TrieStateError::InvalidPath(key))?;
+            let child = match TrieNode::decode(&mut &node[..])? {
+                TrieNode::EmptyRoot => return Ok(None),
+                TrieNode::Leaf(leaf) => return Ok((rest == &leaf.key[..]).then_some(leaf.value)),
+                TrieNode::Extension(extension) => {
+                    if !rest.starts_with(&extension.key) {
+                        return Ok(None);
+                    }
+                    depth += extension.key.len();
+                    extension.child
+                }
+                TrieNode::Branch(branch) => {
+                    let nibble = *rest.first().ok_or(TrieStateError::InvalidPath(key))?;
+                    if !branch.state_mask.is_bit_set(nibble) {
+                        return Ok(None);
+                    }
+                    // The stack only holds the children that are set.
+                    let index = (0..nibble).filter(|i| branch.state_mask.is_bit_set(*i)).count();
+                    depth += 1;
+                    branch.stack.get(index).ok_or(TrieStateError::InvalidPath(key))?.clone()
+                }
+            };
+
+            // Nodes shorter than a hash are inlined in their parent, and are not part of proofs.
+            node = match child.as_hash() {
+                Some(hash) => {
+                    let node = self.node(hash)?;
+                    proof.push(node.clone());
+                    node
+                }
+                None => Bytes::copy_from_slice(child.as_slice()),
+            };
+        }
+    }
+
+    /// Returns the trie node with the given hash.
+    fn node(&self, hash: B256) -> Result<Bytes, TrieStateError> {
+        self.nodes.get(&hash).cloned().ok_or(TrieStateError::MissingNode(hash))
+    }
+}
+
+/// Builds the trie of `leaves`, by key hash, adding all its nodes to `nodes`, and returns its root.
+fn build_trie(
+    leaves: impl IntoIterator<Item = (B256, Vec<u8>)>,
+    nodes: &mut HashMap<B256, Bytes>,
+) -> B256 {
+    let mut leaves =
+        leaves.into_iter().map(|(key, value)| (Nibbles::unpack(key), value)).collect::<Vec<_>>();
+    leaves.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    // Retaining the proofs of all the leaves retains every node of the trie.
+    let targets = leaves.iter().map(|(key, _)| key.clone()).collect();
+    let mut builder = HashBuilder::default().with_proof_retainer(ProofRetainer::new(targets));
+    for (key, value) in &leaves {
+        builder.add_leaf(key.clone(), value);
+    }
+    let root = builder.root();
+    for (_, node) in builder.take_proof_nodes().into_inner() {
+        nodes.insert(keccak256(&node), node);
+    }
+    root
+}
+
+/// A state source reading a [`TrieState`], along with the headers of the blocks before it.
+#[derive(Debug, Clone)]
+pub struct TrieDb {
+    /// The state at the end of the block.
+    pub state: TrieState,
+    /// The headers of the blocks before it, newest first, whose hashes can be read with the
+    /// BLOCKHASH opcode.
+    pub ancestor_headers: Vec<Header>,
+    /// The hashes of the ancestor headers, by number.
+    block_hashes: HashMap<u64, B256>,
+}
+
+impl DatabaseRef for TrieDb {
+    type Error = TrieStateError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.state.account(address)?.map(|account| AccountInfo {
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.code_hash,
+            code: None,
+        }))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::default());
+        }
+        let code =
+            self.state.codes.get(&code_hash).ok_or(TrieStateError::MissingCode(code_hash))?;
+        Ok(Bytecode::new_raw(code.clone()))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.state.storage(address, B256::from(index))
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.block_hashes.get(&number).copied().ok_or(TrieStateError::MissingBlockHash(number))
+    }
+}
+
+impl ProofSource for TrieDb {
+    async fn account_proofs(
+        &self,
+        _header: &Header,
+        requests: &[(Address, Vec<B256>)],
+    ) -> eyre::Result<Vec<AccountProof>> {
+        requests
+            .iter()
+            .map(|(address, keys)| {
+                let proof = self
+                    .state
+                    .proof(*address, keys)
+                    .wrap_err_with(|| format!("failed to generate the proof of {}", address))?;
+                Ok(eip1186_proof_to_account_proof(proof))
+            })
+            .collect()
+    }
+
+    async fn headers(&self, numbers: &[u64]) -> eyre::Result<Vec<Header>> {
+        numbers
+            .iter()
+            .map(|number| {
+                let header = self.ancestor_headers.iter().find(|header| header.number == *number);
+                header.cloned().ok_or_else(|| eyre!("the header of block {} is unknown", number))
+            })
+            .collect()
+    }
+}
+
+/// A state dump: the `alloc` of a `genesis.json`, or an anvil `--dump-state` file.
+#[derive(Debug, Clone, Default)]
+pub struct StateFile {
+    /// The accounts of the state.
+    pub accounts: BTreeMap<Address, GenesisAccount>,
+    /// The headers of the blocks in the dump, oldest first. A `genesis.json` has none.
+    pub headers: Vec<Header>,
+}
+
+/// An account of an anvil state dump.
+#[derive(Debug, Deserialize)]
+struct AnvilAccount {
+    nonce: u64,
+    balance: U256,
+    code: Bytes,
+    storage: BTreeMap<U256, U256>,
+}
+
+/// A block of an anvil state dump.
+#[derive(Debug, Deserialize)]
+struct AnvilBlock {
+    header: Header,
+}
+
+impl StateFile {
+    /// Read a state dump from a file.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        Self::from_json(&json).wrap_err_with(|| format!("invalid state file {}", path.display()))
+    }
+
+    /// Parse a `genesis.json` or an anvil state dump.
+    pub fn from_json(json: &str) -> eyre::Result<Self> {
+        let mut value = serde_json::from_str::<serde_json::Value>(json)?;
+        if let Some(alloc) = value.get_mut("alloc") {
+            let accounts = serde_json::from_value(alloc.take()).wrap_err("invalid alloc")?;
+            return Ok(Self { accounts, headers: Vec::new() });
+        }
+
+        let Some(accounts) = value.get_mut("accounts") else {
+            bail!("neither a genesis.json with an alloc nor an anvil state dump with accounts");
+        };
+        let accounts = serde_json::from_value::<BTreeMap<Address, AnvilAccount>>(accounts.take())
+            .wrap_err("invalid accounts")?
+            .into_iter()
+            .map(|(address, account)| {
+                let storage = account
+                    .storage
+                    .into_iter()
+                    .map(|(slot, value)| (B256::from(slot), B256::from(value)))
+                    .collect();
+                let account = GenesisAccount {
+                    nonce: Some(account.nonce),
+                    balance: account.balance,
+                    code: Some(account.code),
+                    storage: Some(storage),
+                    private_key: None,
+                };
+                (address, account)
+            })
+            .collect();
+
+        let blocks = match value.get_mut("blocks") {
+            Some(blocks) => serde_json::from_value::<Vec<AnvilBlock>>(blocks.take())
+                .wrap_err("invalid blocks")?,
+            None => Vec::new(),
+        };
+        let mut headers = blocks.into_iter().map(|block| block.header).collect::<Vec<_>>();
+        headers.sort_by_key(|header| header.number);
+
+        Ok(Self { accounts, headers })
+    }
+}
+
+/// An executor that reads the state from a [`TrieState`].
+pub type TrieExecutor = HostExecutor<TrieDb>;
+
+impl TrieExecutor {
+    /// Create a new [`TrieExecutor`] executing calls on `state`, at the end of the block of
+    /// `header`.
+    ///
+    /// The `ancestor_headers`, newest first, must be the chain of headers before `header`.
+    pub fn from_state(
+        state: TrieState,
+        header: Header,
+        ancestor_headers: Vec<Header>,
+    ) -> eyre::Result<Self> {
+        if state.state_root != header.state_root {
+            bail!(
+                "the state has root {}, but block {} has state root {}",
+                state.state_root,
+                header.number,
+                header.state_root
+            );
+        }
+
+        let mut block_hashes = HashMap::new();
+        let mut child = &header;
+        for ancestor in &ancestor_headers {
+            let hash = ancestor.hash_slow();
+            if hash != child.parent_hash {
+                bail!("block {} is not the parent of block {}", ancestor.number, child.number);
+            }
+            block_hashes.insert(ancestor.number, hash);
+            child = ancestor;
+        }
+
+        Ok(Self::from_db(TrieDb { state, ancestor_headers, block_hashes }, header))
+    }
+
+    /// Create a new [`TrieExecutor`] executing calls on a devnet holding `accounts`, e.g. in tests.
+    ///
+    /// The block has a base fee of 1 gwei and a gas limit of 30M, and no ancestor headers.
+    pub fn devnet(accounts: &BTreeMap<Address, GenesisAccount>) -> Self {
+        let state = TrieState::from_accounts(accounts);
+        let header = Header {
+            number: 22_000_000,
+            timestamp: 1_750_000_000,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(1_000_000_000),
+            excess_blob_gas: Some(0),
+            state_root: state.state_root,
+            ..Default::default()
+        };
+        let db = TrieDb { state, ancestor_headers: Vec::new(), block_hashes: HashMap::new() };
+        Self::from_db(db, header)
+    }
+
+    /// Create a new [`TrieExecutor`] executing calls at the end of the last block of a state
+    /// dump. Only the consecutive blocks before it can be read with the BLOCKHASH opcode.
+    ///
+    /// A `genesis.json` has no block: use [`TrieExecutor::from_state`] with the genesis header
+    /// instead.
+    pub fn from_state_file(file: &StateFile) -> eyre::Result<Self> {
+        let Some((header, ancestors)) = file.headers.split_last() else {
+            bail!("the state file has no block: the genesis header must be given");
+        };
+        let mut ancestor_headers = Vec::new();
+        let mut child = header;
+        for ancestor in ancestors.iter().rev() {
+            if ancestor.hash_slow() != child.parent_hash {
+                break;
+            }
+            ancestor_headers.push(ancestor.clone());
+            child = ancestor;
+        }
+
+        Self::from_state(TrieState::from_accounts(&file.accounts), header.clone(), ancestor_headers)
+    }
+
+    /// Create a new [`TrieExecutor`] executing calls at the end of block `block_number`, from the
+    /// `debug_executionWitness` of the next block.
+    ///
+    /// Only the state accessed by the next block can be read, and no block hash.
+    pub async fn from_execution_witness<P: Provider<AnyNetwork>>(
+        provider: &P,
+        block_number: u64,
+    ) -> eyre::Result<Self> {
+        let block = provider
+            .get_block_by_number(block_number.into(), BlockTransactionsKind::Hashes)
+            .await
+            .wrap_err_with(|| format!("eth_getBlockByNumber({}) failed", block_number))?
+            .ok_or(eyre!("couldn't fetch block: {}", block_number))?;
+        let header = block_to_header(block)?;
+
+        let next_block = BlockNumberOrTag::Number(block_number + 1);
+        let witness: ExecutionWitness = provider
+            .raw_request("debug_executionWitness".into(), (next_block,))
+            .await
+            .wrap_err_with(|| format!("debug_executionWitness({}) failed", next_block))?;
+
+        let state = TrieState::from_execution_witness(header.state_root, witness)?;
+        Self::from_state(state, header, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{address, b256};
+    use alloy_trie::{
+        nodes::{BranchNode, ExtensionNode, LeafNode, RlpNode},
+        TrieMask,
+    };
+
+    use super::*;
+    use crate::verify::verify_account_proof;
+
+    fn accounts() -> BTreeMap<Address, GenesisAccount> {
+        let storage = (0..100u64)
+            .map(|slot| (B256::from(U256::from(slot)), B256::from(U256::from(slot * slot))))
+            .collect::<BTreeMap<_, _>>();
+        let mut accounts = (1..50u64)
+            .map(|index| {
+                let account = GenesisAccount::default().with_balance(U256::from(index));
+                (Address::with_last_byte(index as u8), account)
+            })
+            .collect::<BTreeMap<_, _>>();
+        accounts.insert(
+            address!("00000000000000000000000000000000000000aa"),
+            GenesisAccount::default()
+                .with_nonce(Some(1))
+                .with_code(Some(Bytes::from_static(&[0x60, 0x00])))
+                .with_storage(Some(storage)),
+        );
+        accounts
+    }
+
+    #[test]
+    fn test_trie_state() {
+        let contract = address!("00000000000000000000000000000000000000aa");
+        let absent = address!("00000000000000000000000000000000000000bb");
+        let state = TrieState::from_accounts(&accounts());
+
+        assert_eq!(state.account(absent).unwrap(), None);
+        assert_eq!(
+            state.account(Address::with_last_byte(7)).unwrap().unwrap().balance,
+            U256::from(7)
+        );
+        let account = state.account(contract).unwrap().unwrap();
+        assert_eq!(account.nonce, 1);
+        assert_eq!(state.codes[&account.code_hash], Bytes::from_static(&[0x60, 0x00]));
+        assert_eq!(state.storage(contract, B256::from(U256::from(9))).unwrap(), U256::from(81));
+        assert_eq!(state.storage(contract, B256::from(U256::from(0))).unwrap(), U256::ZERO);
+        assert_eq!(state.storage(contract, B256::from(U256::from(500))).unwrap(), U256::ZERO);
+
+        for (address, keys) in [
+            (contract, vec![B256::from(U256::from(9)), B256::from(U256::from(500))]),
+            (Address::with_last_byte(7), vec![]),
+            (absent, vec![B256::ZERO]),
+        ] {
+            let proof = state.proof(address, &keys).unwrap();
+            verify_account_proof(&proof, address, &keys, state.state_root).unwrap();
+        }
+
+        // Only the nodes of the tries are needed to read them.
+        let mut partial = state.clone();
+        partial.nodes.remove(&state.state_root);
+        assert!(matches!(
+            partial.account(contract),
+            Err(TrieStateError::MissingNode(hash)) if hash == state.state_root
+        ));
+    }
+
+    #[test]
+    fn test_execution_witness() {
+        let contract = address!("00000000000000000000000000000000000000aa");
+        let state = TrieState::from_accounts(&accounts());
+        let witness = ExecutionWitness {
+            state: state.nodes.clone().into_iter().collect(),
+            codes: state.codes.clone().into_iter().collect(),
+            keys: Default::default(),
+        };
+
+        let from_witness =
+            TrieState::from_execution_witness(state.state_root, witness.clone()).unwrap();
+        assert_eq!(from_witness.nodes, state.nodes);
+        assert_eq!(from_witness.codes, state.codes);
+        assert_eq!(
+            from_witness.storage(contract, B256::from(U256::from(9))).unwrap(),
+            U256::from(81)
+        );
+
+        // A node that does not hash to its key is rejected.
+        let mut tampered = witness;
+        tampered.state.insert(state.state_root, Bytes::from_static(&[0xc0]));
+        assert!(matches!(
+            TrieState::from_execution_witness(state.state_root, tampered),
+            Err(TrieStateError::InvalidHash(hash)) if hash == state.state_root
+        ));
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        // An extension over the whole path of the account, to a branch one nibble too deep.
+        let address = Address::with_last_byte(1);
+        let key = keccak256(address);
+        let leaf = alloy_rlp::encode(LeafNode::new(Nibbles::default(), vec![0x01]));
+        let branch =
+            alloy_rlp::encode(BranchNode::new(vec![RlpNode::from_rlp(&leaf)], TrieMask::new(1)));
+        let extension = alloy_rlp::encode(ExtensionNode::new(
+            Nibbles::unpack(key),
+            RlpNode::word_rlp(&keccak256(&branch)),
+        ));
+        let state = TrieState {
+            state_root: keccak256(&extension),
+            nodes: HashMap::from([
+                (keccak256(&extension), extension.into()),
+                (keccak256(&branch), branch.into()),
+            ]),
+            codes: HashMap::new(),
+        };
+
+        assert!(matches!(
+            state.account(address),
+            Err(TrieStateError::InvalidPath(invalid)) if invalid == key
+        ));
+    }
+
+    #[test]
+    fn test_state_file() {
+        let genesis = r#"{
+            "config": {},
+            "alloc": {
+                "0x00000000000000000000000000000000000000aa": {
+                    "balance": "0x10",
+                    "code": "0x6000",
+                    "storage": { "0x01": "0x02" }
+                }
+            }
+        }"#;
+        let file = StateFile::from_json(genesis).unwrap();
+        assert!(file.headers.is_empty());
+        let state = TrieState::from_accounts(&file.accounts);
+        let contract = address!("00000000000000000000000000000000000000aa");
+        assert_eq!(state.storage(contract, B256::with_last_byte(1)).unwrap(), U256::from(2));
+
+        let dump = r#"{
+            "block": null,
+            "accounts": {
+                "0x00000000000000000000000000000000000000aa": {
+                    "nonce": 0,
+                    "balance": "0x10",
+                    "code": "0x6000",
+                    "storage": { "0x1": "0x2" }
+                }
+            },
+            "best_block_number": null
+        }"#;
+        let file = StateFile::from_json(dump).unwrap();
+        assert_eq!(TrieState::from_accounts(&file.accounts).state_root, state.state_root);
+        assert!(TrieExecutor::from_state_file(&file).is_err());
+
+        // The empty state of a fresh devnet.
+        assert_eq!(
+            TrieState::from_accounts(&BTreeMap::new()).state_root,
+            b256!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Checks of the local execution and of the data served by the node.
+//!
+//! The [`HostExecutor`] runs calls in a local EVM, configured like the one running in the zkVM.
+//! If its configuration does not match the chain, for example because of a different chain spec,
+//! the proof would only fail much later. When call verification is enabled, every call is also
+//! sent to the node with `eth_call`, and the outputs are compared.
+//!
+//! The account and storage proofs served by the node are always checked against the state root of
+//! the block, so that a broken RPC is caught on the host rather than inside the zkVM.
+//!
+//! [`HostExecutor`]: crate::HostExecutor
+
+use std::fmt;
+
+use alloy_primitives::{keccak256, Address, B256};
+use alloy_rpc_types::{
+    serde_helpers::WithOtherFields,
+    state::{AccountOverride, StateOverride},
+    EIP1186AccountProofResponse, TransactionInput, TransactionRequest,
+};
+use alloy_transport::{RpcError, TransportError};
+use alloy_trie::{proof::verify_proof, Nibbles, TrieAccount, EMPTY_ROOT_HASH, KECCAK_EMPTY};
+use eyre::{bail, eyre};
+use reth_primitives::Header;
+use revm_primitives::{hex, Bytes, ExecutionResult, TxKind};
+use sp1_cc_client_executor::{ContractCalldata, ContractInput};
+
+/// Maximum number of differing words listed in a diff.
+const MAX_DIFF_WORDS: usize = 8;
+
+/// The JSON-RPC error code of reverted calls.
+const REVERT_CODE: i64 = 3;
+
+/// The outcome of a call, as seen by the local EVM or by the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CallOutcome {
+    /// The call returned this output.
+    Success(Bytes),
+    /// The call reverted with this output.
+    Revert(Bytes),
+    /// The call failed without output.
+    Failure(String),
+}
+
+impl CallOutcome {
+    /// The outcome of a local execution.
+    pub(crate) fn from_result(result: &ExecutionResult) -> Self {
+        match result {
+            ExecutionResult::Success { output, .. } => Self::Success(output.data().clone()),
+            ExecutionResult::Revert { output, .. } => Self::Revert(output.clone()),
+            ExecutionResult::Halt { reason, .. } => Self::Failure(format!("{:?}", reason)),
+        }
+    }
+
+    /// The outcome of an `eth_call`.
+    ///
+    /// Errors that are not about the call itself, such as transport errors, are returned as is.
+    pub(crate) fn from_eth_call(
+        result: Result<Bytes, TransportError>,
+    ) -> Result<Self, TransportError> {
+        match result {
+            Ok(output) => Ok(Self::Success(output)),
+            Err(RpcError::ErrorResp(error)) => Ok(match error.as_revert_data() {
+                Some(data) => Self::Revert(data),
+                None if error.code == REVERT_CODE || error.message.contains("revert") => {
+                    Self::Revert(Bytes::new())
+                }
+                None => Self::Failure(error.message.to_string()),
+            }),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl fmt::Display for CallOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Success(output) => write!(f, "returned {}", output),
+            Self::Revert(output) => write!(f, "reverted with {}", output),
+            Self::Failure(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// The `eth_call` request matching the transaction run by [`new_evm`] for `call`, in the block of
+/// `header`.
+///
+/// [`new_evm`]: sp1_cc_client_executor::new_evm
+pub(crate) fn call_request(
+    call: &ContractInput,
+    header: &Header,
+) -> WithOtherFields<TransactionRequest> {
+    let env = &call.env;
+    let to = match call.calldata {
+        ContractCalldata::Call(_) | ContractCalldata::Deployless { .. } => {
+            TxKind::Call(call.contract_address)
+        }
+        ContractCalldata::Create(_) => TxKind::Create,
+    };
+    // Without a gas price, none is set, so that the node does not check the balance of the
+    // caller, like the local EVM which runs with a zero gas price.
+    let gas_price = (!env.gas_price.is_zero()).then(|| env.gas_price.saturating_to::<u128>());
+    let (gas_price, max_fee_per_gas) = match env.gas_priority_fee {
+        Some(_) => (None, gas_price),
+        None => (gas_price, None),
+    };
+    let blobs = !env.blob_hashes.is_empty();
+    WithOtherFields::new(TransactionRequest {
+        from: Some(call.caller_address),
+        to: Some(to),
+        gas: Some(env.gas_limit.unwrap_or(header.gas_limit)),
+        gas_price,
+        max_fee_per_gas,
+        max_priority_fee_per_gas: env.gas_priority_fee.map(|fee| fee.saturating_to()),
+        value: Some(env.value),
+        nonce: env.nonce,
+        access_list: (!env.access_list.is_empty()).then(|| env.access_list.clone().into()),
+        blob_versioned_hashes: blobs.then(|| env.blob_hashes.clone()),
+        max_fee_per_blob_gas: blobs.then(|| env.max_fee_per_blob_gas.saturating_to()),
+        input: TransactionInput::both(call.calldata.to_bytes()),
+        ..Default::default()
+    })
+}
+
+/// The `eth_call` state overrides matching the overrides of `call`.
+///
+/// `eth_call` does not take both the whole storage and a diff of an account: the diff is merged
+/// into the whole storage instead.
+pub(crate) fn state_override(call: &ContractInput) -> StateOverride {
+    call.overrides
+        .iter()
+        .map(|(address, account)| {
+            let state_diff = (account.state.is_none() && !account.state_diff.is_empty())
+                .then(|| account.state_diff.iter().map(|(slot, value)| (*slot, *value)).collect());
+            let account_override = AccountOverride {
+                balance: account.balance,
+                nonce: account.nonce,
+                code: account.code.clone(),
+                state: account.state.as_ref().map(|_| account.storage().into_iter().collect()),
+                state_diff,
+                ..Default::default()
+            };
+            (*address, account_override)
+        })
+        .collect()
+}
+
+/// A short description of `call`, for error messages.
+pub(crate) fn describe_call(call: &ContractInput) -> String {
+    match call.calldata {
+        ContractCalldata::Call(_) => format!("the call to {}", call.contract_address),
+        ContractCalldata::Create(_) => "the contract creation".to_string(),
+        ContractCalldata::Deployless { .. } => {
+            format!("the deployless call to {}", call.contract_address)
+        }
+    }
+}
+
+/// Describes how the outcome of the call on the node differs from the local one, if it does.
+pub(crate) fn call_diff(local: &CallOutcome, node: &CallOutcome) -> Option<String> {
+    match (local, node) {
+        (CallOutcome::Success(local), CallOutcome::Success(node)) => {
+            (local != node).then(|| format!("outputs differ: {}", bytes_diff(local, node)))
+        }
+        (CallOutcome::Revert(local), CallOutcome::Revert(node)) => {
+            (local != node).then(|| format!("revert data differ: {}", bytes_diff(local, node)))
+        }
+        // Failures without output are reported differently by every node.
+        (CallOutcome::Failure(_), CallOutcome::Failure(_)) => None,
+        _ => Some(format!("the local EVM {}, but eth_call {}", local, node)),
+    }
+}
+
+/// A word by word diff of two outputs.
+fn bytes_diff(local: &[u8], node: &[u8]) -> String {
+    let mut diff = format!(
+        "the local output is {} bytes long, the eth_call output is {} bytes long",
+        local.len(),
+        node.len()
+    );
+
+    let word = |bytes: &[u8], index: usize| -> Vec<u8> {
+        bytes.iter().skip(index * 32).take(32).copied().collect()
+    };
+    let words = local.len().max(node.len()).div_ceil(32);
+    let differing =
+        (0..words).filter(|index| word(local, *index) != word(node, *index)).collect::<Vec<_>>();
+
+    for index in differing.iter().take(MAX_DIFF_WORDS) {
+        diff.push_str(&format!(
+            "\n  word {} (bytes {}..{}): local 0x{}, eth_call 0x{}",
+            index,
+            index * 32,
+            (index + 1) * 32,
+            hex::encode(word(local, *index)),
+            hex::encode(word(node, *index))
+        ));
+    }
+    if differing.len() > MAX_DIFF_WORDS {
+        diff.push_str(&format!(
+            "\n  ... and {} more differing words",
+            differing.len() - MAX_DIFF_WORDS
+        ));
+    }
+    diff
+}
+
+/// Checks that `proof` is the proof of the storage slots `keys` of `address`, and that it is valid
+/// against `state_root`.
+pub(crate) fn verify_account_proof(
+    proof: &EIP1186AccountProofResponse,
+    address: Address,
+    keys: &[B256],
+    state_root: B256,
+) -> eyre::Result<()> {
+    if proof.address != address {
+        bail!("the proof of account {} was served instead of account {}", proof.address, address);
+    }
+
+    // Some nodes serve zero hashes for the code and storage of accounts that do not exist.
+    let or_default = |hash: B256, default: B256| if hash.is_zero() { default } else { hash };
+    let account = TrieAccount {
+        nonce: proof.nonce,
+        balance: proof.balance,
+        storage_root: or_default(proof.storage_hash, EMPTY_ROOT_HASH),
+        code_hash: or_default(proof.code_hash, KECCAK_EMPTY),
+    };
+    let expected = (account != TrieAccount::default()).then(|| alloy_rlp::encode(account));
+    verify_proof(state_root, Nibbles::unpack(keccak256(address)), expected, &proof.account_proof)
+        .map_err(|error| eyre!("invalid proof of account {}: {}", address, error))?;
+
+    let mut served = proof.storage_proof.iter().map(|slot| slot.key.as_b256()).collect::<Vec<_>>();
+    served.sort_unstable();
+    let mut requested = keys.to_vec();
+    requested.sort_unstable();
+    if served != requested {
+        bail!(
+            "the proofs of storage slots {:?} of account {} were served instead of {:?}",
+            served,
+            address,
+            requested
+        );
+    }
+
+    for slot in &proof.storage_proof {
+        let key = slot.key.as_b256();
+        let expected = (!slot.value.is_zero()).then(|| alloy_rlp::encode(slot.value));
+        verify_proof(account.storage_root, Nibbles::unpack(keccak256(key)), expected, &slot.proof)
+            .map_err(|error| {
+                eyre!("invalid proof of storage slot {} of account {}: {}", key, address, error)
+            })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use alloy_rpc_types::EIP1186StorageProof;
+    use alloy_trie::{proof::ProofRetainer, HashBuilder};
+
+    use super::*;
+
+    /// Builds the trie of `leaves`, hashing their keys, and returns its root and the proof of
+    /// `target`.
+    fn trie_proof(leaves: &[(&[u8], Vec<u8>)], target: &[u8]) -> (B256, Vec<Bytes>) {
+        let target = Nibbles::unpack(keccak256(target));
+        let mut leaves = leaves
+            .iter()
+            .map(|(key, value)| (Nibbles::unpack(keccak256(key)), value))
+            .collect::<Vec<_>>();
+        leaves.sort();
+
+        let mut builder =
+            HashBuilder::default().with_proof_retainer(ProofRetainer::from_iter([target.clone()]));
+        for (key, value) in leaves {
+            builder.add_leaf(key, value);
+        }
+        let root = builder.root();
+        let proof = builder.take_proof_nodes().matching_nodes_sorted(&target);
+        (root, proof.into_iter().map(|(_, node)| node).collect())
+    }
+
+    #[test]
+    fn test_verify_account_proof() {
+        let address = Address::repeat_byte(1);
+        let other_address = Address::repeat_byte(2);
+        let key = B256::with_last_byte(1);
+        let other_key = B256::with_last_byte(2);
+        let value = U256::from(42);
+
+        let (storage_root, storage_proof) = trie_proof(
+            &[
+                (key.as_slice(), alloy_rlp::encode(value)),
+                (other_key.as_slice(), alloy_rlp::encode(U256::from(1))),
+            ],
+            key.as_slice(),
+        );
+        let account = TrieAccount {
+            nonce: 1,
+            balance: U256::from(100),
+            storage_root,
+            code_hash: KECCAK_EMPTY,
+        };
+        let other_account = TrieAccount { nonce: 2, ..Default::default() };
+        let (state_root, account_proof) = trie_proof(
+            &[
+                (address.as_slice(), alloy_rlp::encode(account)),
+                (other_address.as_slice(), alloy_rlp::encode(other_account)),
+            ],
+            address.as_slice(),
+        );
+
+        let proof = EIP1186AccountProofResponse {
+            address,
+            balance: account.balance,
+            code_hash: account.code_hash,
+            nonce: account.nonce,
+            storage_hash: storage_root,
+            account_proof,
+            storage_proof: vec![EIP1186StorageProof {
+                key: key.into(),
+                value,
+                proof: storage_proof,
+            }],
+        };
+        verify_account_proof(&proof, address, &[key], state_root).unwrap();
+
+        let tampered = EIP1186AccountProofResponse { balance: U256::from(1000), ..proof.clone() };
+        let error = verify_account_proof(&tampered, address, &[key], state_root).unwrap_err();
+        assert!(error.to_string().starts_with(&format!("invalid proof of account {}", address)));
+
+        let mut tampered = proof.clone();
+        tampered.storage_proof[0].value = U256::from(43);
+        let error = verify_account_proof(&tampered, address, &[key], state_root).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("invalid proof of storage slot {} of account {}", key, address)));
+
+        let error = verify_account_proof(&proof, other_address, &[key], state_root).unwrap_err();
+        assert!(error.to_string().contains("was served instead of account"));
+
+        let error =
+            verify_account_proof(&proof, address, &[key, other_key], state_root).unwrap_err();
+        assert!(error.to_string().contains("were served instead of"));
+    }
+
+    #[test]
+    fn test_call_diff() {
+        let output = Bytes::from(vec![1; 64]);
+        let success = CallOutcome::Success(output.clone());
+        assert_eq!(call_diff(&success, &success), None);
+
+        let mut changed = output.to_vec();
+        changed[40] = 2;
+        changed.push(3);
+        let diff = call_diff(&success, &CallOutcome::Success(changed.into())).unwrap();
+        assert!(diff.contains("64 bytes long, the eth_call output is 65 bytes long"));
+        assert!(!diff.contains("word 0"));
+        assert!(diff.contains("word 1 (bytes 32..64)"));
+        assert!(diff.contains("word 2 (bytes 64..96): local 0x, eth_call 0x03"));
+
+        let diff = call_diff(&success, &CallOutcome::Revert(Bytes::new())).unwrap();
+        assert!(diff.contains("the local EVM returned 0x0101"));
+        assert!(diff.contains("but eth_call reverted with 0x"));
+
+        let failure = CallOutcome::Failure("out of gas".into());
+        assert_eq!(call_diff(&failure, &CallOutcome::Failure("OutOfGas".into())), None);
+    }
+}
@@ -5,7 +5,11 @@ sp1_zkvm::entrypoint!(main);
 use alloy_primitives::{Address, U256, B256, hex};
 use alloy_sol_macro::sol;
 use alloy_sol_types::{SolCall, SolValue};
-use sp1_cc_client_executor::{io::EVMStateSketch, ClientExecutor, ContractInput};
+use sp1_cc_client_executor::{
+    archive::{SketchArchive, ARCHIVE_MAGIC},
+    io::EVMStateSketch,
+    ClientExecutor, ContractInput, ContractPublicValues,
+};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -48,8 +52,20 @@ pub fn main() {
     let contract_address: [u8; 20] = sp1_zkvm::io::read();
     let data: Vec<u8> = sp1_zkvm::io::read();
 
-    let state_sketch_bytes = sp1_zkvm::io::read::<Vec<u8>>();
-    let state_sketch = bincode::deserialize::<EVMStateSketch>(&state_sketch_bytes).unwrap();
+    // The state sketch is either archived, and read in place, or in its canonical encoding.
+    let state_sketch_bytes = sp1_zkvm::io::read_vec();
+    let archive;
+    let state_sketch;
+    let execute: Box<dyn Fn(ContractInput) -> ContractPublicValues + '_> =
+        if state_sketch_bytes.starts_with(&ARCHIVE_MAGIC) {
+            archive = SketchArchive::new(&state_sketch_bytes).unwrap();
+            let executor = ClientExecutor::from_archive(&archive);
+            Box::new(move |call| executor.execute(call).unwrap())
+        } else {
+            state_sketch = EVMStateSketch::decode(&state_sketch_bytes).unwrap();
+            let executor = ClientExecutor::new(&state_sketch).unwrap();
+            Box::new(move |call| executor.execute(call).unwrap())
+        };
 
     let address_input_bytes = sp1_zkvm::io::read::<Vec<u8>>();
     let mut address_input: AddressInput = bincode::deserialize(&address_input_bytes).unwrap();
@@ -75,10 +91,9 @@ pub fn main() {
     address_input.target = Address::from_slice(&dead_address);
     address_input.min_amount = amount;
 
-    let executor = ClientExecutor::new(&state_sketch).unwrap();
     let balance_call = IERC20::balanceOfCall { account: address_input.target };
     let call = ContractInput::new_call(address_input.contract, Address::default(), balance_call);
-    let public_vals = executor.execute(call).unwrap();
+    let public_vals = execute(call);
     let balance = IERC20::balanceOfCall::abi_decode_returns(&public_vals.contractOutput, true).unwrap();
 
     let min_amount = U256::from(address_input.min_amount);
@@ -89,7 +104,7 @@ pub fn main() {
 
     let get_dead_hash_amount_call = IERC20::getDeadHashAmountCall { h: B256::from(dead_address_hash_bytes) };
     let call = ContractInput::new_call(address_input.contract, Address::default(), get_dead_hash_amount_call);
-    let public_vals = executor.execute(call).unwrap();
+    let public_vals = execute(call);
     let dead_hash_amount = IERC20::getDeadHashAmountCall::abi_decode_returns(&public_vals.contractOutput, true).unwrap();
 
     assert!(
@@ -0,0 +1,181 @@
+use alloy_primitives::{Address, U256};
+use alloy_sol_macro::sol;
+use alloy_sol_types::SolCall;
+use sp1_cc_client_executor::ContractInput;
+use sp1_cc_host_executor::SketchExecutor;
+
+sol! {
+    interface IERC20Metadata {
+        function decimals() external view returns (uint8);
+        function symbol() external view returns (string);
+    }
+}
+
+/// Display metadata of an ERC20 token, used to convert between human-readable amounts and base
+/// units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    /// The number of decimals of the token.
+    pub decimals: u8,
+    /// The token symbol, if the token exposes one as a `string`.
+    pub symbol: Option<String>,
+}
+
+impl TokenInfo {
+    /// The metadata of a token without decimals or symbol: amounts are in base units.
+    pub fn base_units() -> Self {
+        Self { decimals: 0, symbol: None }
+    }
+
+    /// Fetch `decimals()` and `symbol()` of `token` through the executor.
+    ///
+    /// `decimals()` is optional in ERC20: if it reverts or does not return a `uint8`, amounts fall
+    /// back to [`TokenInfo::base_units`]. Errors of the executor, e.g. of its RPC, are returned.
+    ///
+    /// The executor records the state these calls read: use one separate from the executor of a
+    /// claim, so that this state stays out of the sketch of the claim.
+    pub async fn fetch(executor: &mut impl SketchExecutor, token: Address) -> eyre::Result<Self> {
+        let output = executor
+            .execute(ContractInput::new_call(
+                token,
+                Address::default(),
+                IERC20Metadata::decimalsCall {},
+            ))
+            .await?;
+        let Ok(decimals) = IERC20Metadata::decimalsCall::abi_decode_returns(&output, true) else {
+            return Ok(Self::base_units());
+        };
+
+        // Some tokens (e.g. MKR) return a `bytes32` symbol, which we simply don't display. A call
+        // that reverts returns its revert data, which does not decode either.
+        let output = executor
+            .execute(ContractInput::new_call(
+                token,
+                Address::default(),
+                IERC20Metadata::symbolCall {},
+            ))
+            .await?;
+        let symbol =
+            IERC20Metadata::symbolCall::abi_decode_returns(&output, true).ok().map(|ret| ret._0);
+
+        Ok(Self { decimals: decimals._0, symbol })
+    }
+
+    /// Parse a user supplied amount such as `12.5` or `12.5 USDC` into base units.
+    ///
+    /// If `raw` is set, the amount is expected to already be an integer amount of base units.
+    pub fn parse_amount(&self, input: &str, raw: bool) -> eyre::Result<u64> {
+        let input = input.trim();
+        let value = match input.split_once(char::is_whitespace) {
+            Some((value, symbol)) => {
+                let symbol = symbol.trim();
+                match &self.symbol {
+                    Some(expected) if expected.eq_ignore_ascii_case(symbol) => {}
+                    Some(expected) => {
+                        eyre::bail!(
+                            "amount is denominated in {symbol}, but the token is {expected}"
+                        )
+                    }
+                    None => eyre::bail!("token has no symbol, pass the amount without a unit"),
+                }
+                value
+            }
+            None => input,
+        };
+
+        let base_units = if raw {
+            value.parse::<U256>().map_err(|_| eyre::eyre!("invalid raw amount: {value}"))?
+        } else {
+            parse_units(value, self.decimals)?
+        };
+
+        base_units.try_into().map_err(|_| eyre::eyre!("amount {input} does not fit in a u64"))
+    }
+
+    /// Format an amount of base units, e.g. `12.5 USDC`.
+    pub fn format_amount(&self, amount: U256) -> String {
+        let value = format_units(amount, self.decimals);
+        match &self.symbol {
+            Some(symbol) => format!("{value} {symbol}"),
+            None => value,
+        }
+    }
+}
+
+/// Parse a decimal string into base units with the given number of decimals.
+fn parse_units(value: &str, decimals: u8) -> eyre::Result<U256> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (integer.is_empty() && fraction.is_empty()) || !is_digits(integer) || !is_digits(fraction) {
+        eyre::bail!("invalid amount: {value}");
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        eyre::bail!("amount {value} has more than {decimals} decimals");
+    }
+
+    let digits = format!("{integer}{fraction:0<width$}", width = decimals as usize);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(U256::ZERO);
+    }
+    digits.parse::<U256>().map_err(|_| eyre::eyre!("amount {value} is too large"))
+}
+
+/// Format base units as a decimal string with the given number of decimals.
+fn format_units(amount: U256, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+
+    let digits = format!("{digits:0>width$}", width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{integer}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usdc() -> TokenInfo {
+        TokenInfo { decimals: 6, symbol: Some("USDC".to_string()) }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        let token = usdc();
+        assert_eq!(token.parse_amount("12.5", false).unwrap(), 12_500_000);
+        assert_eq!(token.parse_amount("12.5 usdc", false).unwrap(), 12_500_000);
+        assert_eq!(token.parse_amount(".5", false).unwrap(), 500_000);
+        assert_eq!(token.parse_amount("3", false).unwrap(), 3_000_000);
+        assert_eq!(token.parse_amount("3", true).unwrap(), 3);
+        assert!(token.parse_amount("0.0000001", false).is_err());
+        assert!(token.parse_amount("1 DAI", false).is_err());
+        assert!(token.parse_amount("1e6", false).is_err());
+        assert!(token.parse_amount("100000000000000", false).is_err());
+    }
+
+    #[test]
+    fn test_format_amount() {
+        let token = usdc();
+        assert_eq!(token.format_amount(U256::from(12_500_000)), "12.5 USDC");
+        assert_eq!(token.format_amount(U256::from(1)), "0.000001 USDC");
+        assert_eq!(token.format_amount(U256::ZERO), "0 USDC");
+        assert_eq!(format_units(U256::from(10u64).pow(U256::from(18)), 18), "1");
+    }
+
+    #[test]
+    fn test_base_units() {
+        let token = TokenInfo::base_units();
+        assert_eq!(token.parse_amount("12", false).unwrap(), 12);
+        assert!(token.parse_amount("12.5", false).is_err());
+        assert_eq!(token.format_amount(U256::from(12)), "12");
+    }
+}
@@ -0,0 +1,208 @@
+//! The input of the wormhole program for a claim.
+
+use std::fmt;
+
+use alloy_primitives::{Address, B256, U256};
+use alloy_sol_macro::sol;
+use alloy_sol_types::SolCall;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sp1_cc_client_executor::{io::EVMStateSketch, ContractInput};
+use sp1_cc_host_executor::{prune_sketch, PruneReport, SketchExecutor};
+use sp1_sdk::{include_elf, SP1Stdin};
+
+use crate::{dead_address_hash, note::WormholeNote, IERC20};
+
+/// The ELF of the wormhole program.
+pub const ELF: &[u8] = include_elf!("zk-wormhole-program");
+
+sol! {
+    /// The public values committed by the wormhole program.
+    struct PublicValuesStruct {
+        uint64 amount;
+        address receiver;
+        bytes32 nullifier;
+        bytes32 deadAddressHash;
+        bytes32 blockHash;
+        address contractAddress;
+        bytes data;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AddressInput {
+    contract: Address,
+    target: Address,
+    min_amount: u64,
+}
+
+/// The input of the wormhole program for a claim, along with the balances it was built from.
+pub struct ClaimWitness {
+    /// The input of the wormhole program. It contains the note secret.
+    pub stdin: SP1Stdin,
+    /// The hash of the block the claim is proven against.
+    pub block_hash: B256,
+    /// The amount of tokens burnt to the note.
+    pub balance: U256,
+    /// The amount of tokens already claimed from the note.
+    pub claimed: U256,
+    /// The state sketch in the input of the program.
+    pub state_sketch: EVMStateSketch,
+    /// How the state sketch was pruned, if it was.
+    pub pruning: Option<ClaimPruning>,
+}
+
+/// The pruning of the state sketch of a claim.
+pub struct ClaimPruning {
+    /// What was removed from the state sketch.
+    pub report: PruneReport,
+    /// The input of the wormhole program with the state sketch before pruning, to measure the
+    /// cycles pruning saves. Only built for [`Pruning::Report`].
+    pub unpruned_stdin: Option<SP1Stdin>,
+}
+
+/// Whether [`prepare_claim`] prunes the state sketch of a claim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pruning {
+    /// Keep the whole state sketch.
+    Disabled,
+    /// Prune the state sketch down to the calls of the program.
+    Enabled,
+    /// Prune the state sketch, and also build the input of the program with the unpruned sketch,
+    /// to measure the cycles pruning saves.
+    Report,
+}
+
+impl fmt::Debug for ClaimPruning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClaimPruning").field("report", &self.report).finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for ClaimWitness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClaimWitness")
+            .field("block_hash", &self.block_hash)
+            .field("balance", &self.balance)
+            .field("claimed", &self.claimed)
+            .field("pruning", &self.pruning)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Build the input of the wormhole program to claim `amount` base units from `note` to
+/// `receiver`, at the block of `executor`.
+///
+/// Unless `pruning` is [`Pruning::Disabled`], the state sketch is pruned down to the calls of the
+/// program, dropping the state of any other call executed beforehand.
+pub async fn prepare_claim(
+    executor: &mut impl SketchExecutor,
+    note: &WormholeNote,
+    amount: u64,
+    receiver: Address,
+    pruning: Pruning,
+) -> eyre::Result<ClaimWitness> {
+    let contract_address = note.token;
+    let dead_address = note.dead_address();
+    let block_hash = executor.header().hash_slow();
+
+    // The calls of the program, in the order it executes them.
+    let balance_call = IERC20::balanceOfCall { account: Address::from(dead_address) };
+    let claimed_call = IERC20::getDeadHashAmountCall { h: dead_address_hash(&dead_address) };
+    let calls = [
+        ContractInput::new_call(contract_address, Address::default(), balance_call),
+        ContractInput::new_call(contract_address, Address::default(), claimed_call),
+    ];
+
+    let balance_output = executor.execute(calls[0].clone()).await?;
+    let balance = IERC20::balanceOfCall::abi_decode_returns(&balance_output, true)?._0;
+    let claimed_output = executor.execute(calls[1].clone()).await?;
+    let claimed = IERC20::getDeadHashAmountCall::abi_decode_returns(&claimed_output, true)?._0;
+
+    let state_sketch = executor.finalize().await?;
+    state_sketch.validate()?;
+
+    let stdin = |state_sketch: &EVMStateSketch| {
+        program_stdin(note, amount, receiver, block_hash, &state_sketch.to_archive())
+    };
+    if pruning == Pruning::Disabled {
+        return Ok(ClaimWitness {
+            stdin: stdin(&state_sketch)?,
+            block_hash,
+            balance,
+            claimed,
+            state_sketch,
+            pruning: None,
+        });
+    }
+
+    let (pruned, report) = prune_sketch(&state_sketch, &calls)?;
+    let unpruned_stdin =
+        if pruning == Pruning::Report { Some(stdin(&state_sketch)?) } else { None };
+    Ok(ClaimWitness {
+        stdin: stdin(&pruned)?,
+        block_hash,
+        balance,
+        claimed,
+        state_sketch: pruned,
+        pruning: Some(ClaimPruning { report, unpruned_stdin }),
+    })
+}
+
+/// The input of the wormhole program to claim `amount` base units from `note` to `receiver`, at
+/// block `block_hash` whose state is in `state_sketch`.
+///
+/// The state sketch is either archived with [`EVMStateSketch::to_archive`], which the program reads
+/// in place, or in the canonical encoding of [`EVMStateSketch::encode`].
+pub fn program_stdin(
+    note: &WormholeNote,
+    amount: u64,
+    receiver: Address,
+    block_hash: B256,
+    state_sketch: &[u8],
+) -> eyre::Result<SP1Stdin> {
+    let contract_address = note.token;
+    let dead_address = note.dead_address();
+    let address_input = AddressInput {
+        contract: contract_address,
+        target: Address::from(dead_address),
+        min_amount: amount,
+    };
+
+    // The program reads fixed-size arrays: alloy types are serialized with a length prefix by
+    // bincode, so they must be converted first.
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&*note.secret.secret);
+    stdin.write(&*note.secret.nonce);
+    stdin.write(&dead_address);
+    stdin.write(&amount);
+    stdin.write(&<[u8; 20]>::from(receiver));
+    stdin.write(&<[u8; 32]>::from(block_hash));
+    stdin.write(&<[u8; 20]>::from(contract_address));
+    stdin.write(&<[u8; 20]>::from(contract_address));
+    stdin.write(&Vec::<u8>::new());
+    stdin.write_slice(state_sketch);
+    stdin.write(&bincode::serialize(&address_input)?);
+    Ok(stdin)
+}
+
+/// The nullifier committed by the wormhole program for a claim.
+///
+/// This must match the computation done by the wormhole program in the zkVM.
+pub fn nullifier(
+    dead_address: &[u8; 20],
+    receiver: Address,
+    amount: u64,
+    block_hash: B256,
+    token: Address,
+    data: &[u8],
+) -> B256 {
+    let mut hasher = Sha256::new();
+    hasher.update(dead_address);
+    hasher.update(receiver);
+    hasher.update(amount.to_be_bytes());
+    hasher.update(block_hash);
+    hasher.update(token);
+    hasher.update(data);
+    B256::from(<[u8; 32]>::from(hasher.finalize()))
+}
@@ -0,0 +1,422 @@
+//! An encrypted local store of wormhole notes.
+//!
+//! The store is a single JSON file, modelled after the Ethereum keystore v3 format:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "crypto": {
+//!     "cipher": "xchacha20-poly1305",
+//!     "cipherparams": { "nonce": "<hex>" },
+//!     "ciphertext": "<hex>",
+//!     "kdf": "scrypt",
+//!     "kdfparams": { "dklen": 32, "n": 262144, "r": 8, "p": 1, "salt": "<hex>" }
+//!   }
+//! }
+//! ```
+//!
+//! The ciphertext is the JSON list of [`StoredNote`]s, so that nothing about the notes, not even
+//! their burn addresses, can be read without the password.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use alloy::hex;
+use alloy_primitives::{Address, B256};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    note::WormholeNote,
+    secret::{read_secret_file, write_secret_file},
+};
+
+/// Environment variable holding the keystore password.
+pub const PASSWORD_ENV: &str = "ZKW_KEYSTORE_PASSWORD";
+
+/// The current version of the keystore format.
+const KEYSTORE_VERSION: u32 = 1;
+const CIPHER: &str = "xchacha20-poly1305";
+const KDF: &str = "scrypt";
+/// The scrypt cost used by new keystores, `n = 2^18` as in the Ethereum keystore v3 format.
+const DEFAULT_LOG_N: u8 = 18;
+
+/// A note kept in a [`NoteStore`], along with its metadata.
+#[derive(Serialize, Deserialize)]
+pub struct StoredNote {
+    /// An optional label to refer to the note by.
+    pub label: Option<String>,
+    /// The encoded note.
+    note: Zeroizing<String>,
+    /// The chain id of the note.
+    pub chain_id: u64,
+    /// The wormhole token of the note.
+    pub token: Address,
+    /// The burn address of the note.
+    pub dead_address: Address,
+    /// The block the tokens were burnt in, if known.
+    pub deposit_block: Option<u64>,
+    /// When the note was added to the store, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// The claims made from the note.
+    pub claims: Vec<ClaimRecord>,
+}
+
+impl StoredNote {
+    /// Decode the stored note.
+    pub fn note(&self) -> eyre::Result<WormholeNote> {
+        self.note.parse()
+    }
+
+    /// The encoded note string.
+    pub fn encoded(&self) -> &str {
+        &self.note
+    }
+
+    /// The total amount claimed from the note, in base units.
+    pub fn claimed(&self) -> u128 {
+        self.claims.iter().map(|claim| claim.amount as u128).sum()
+    }
+}
+
+impl fmt::Debug for StoredNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredNote")
+            .field("label", &self.label)
+            .field("chain_id", &self.chain_id)
+            .field("token", &self.token)
+            .field("dead_address", &self.dead_address)
+            .field("deposit_block", &self.deposit_block)
+            .field("created_at", &self.created_at)
+            .field("claims", &self.claims)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A claim made from a [`StoredNote`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimRecord {
+    /// The claimed amount, in base units.
+    pub amount: u64,
+    /// The receiver of the claimed tokens.
+    pub receiver: Address,
+    /// The hash of the block the claim was proven against.
+    pub block_hash: B256,
+    /// The nullifier committed by the proof.
+    pub nullifier: B256,
+    /// When the claim was proven, in seconds since the Unix epoch.
+    pub claimed_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    crypto: CryptoParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CryptoParams {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    kdf: String,
+    kdfparams: KdfParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CipherParams {
+    nonce: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    dklen: usize,
+    n: u64,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+impl KdfParams {
+    fn derive_key(&self, password: &str) -> eyre::Result<Zeroizing<[u8; 32]>> {
+        if self.dklen != 32 || !self.n.is_power_of_two() {
+            eyre::bail!("unsupported scrypt parameters");
+        }
+        let params = scrypt::Params::new(self.n.trailing_zeros() as u8, self.r, self.p, 32)
+            .map_err(|e| eyre::eyre!("invalid scrypt parameters: {e}"))?;
+        let salt = hex::decode(&self.salt)?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        scrypt::scrypt(password.as_bytes(), &salt, &params, key.as_mut())
+            .map_err(|e| eyre::eyre!("scrypt failed: {e}"))?;
+        Ok(key)
+    }
+}
+
+impl ClaimRecord {
+    /// Create a record of a claim made now.
+    pub fn new(amount: u64, receiver: Address, block_hash: B256, nullifier: B256) -> Self {
+        Self { amount, receiver, block_hash, nullifier, claimed_at: now() }
+    }
+}
+
+/// An encrypted store of [`StoredNote`]s, backed by a file.
+///
+/// Changes are only persisted by [`NoteStore::save`].
+pub struct NoteStore {
+    path: PathBuf,
+    kdfparams: KdfParams,
+    key: Zeroizing<[u8; 32]>,
+    notes: Vec<StoredNote>,
+}
+
+impl NoteStore {
+    /// Open the store at `path`, or create an empty one if the file does not exist yet.
+    pub fn open_or_create(path: &Path, password: &str) -> eyre::Result<Self> {
+        if path.exists() {
+            Self::open(path, password)
+        } else {
+            Self::create(path, password, DEFAULT_LOG_N)
+        }
+    }
+
+    /// Open and decrypt the store at `path`.
+    pub fn open(path: &Path, password: &str) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("failed to read keystore {}: {e}", path.display()))?;
+        let file: KeystoreFile = serde_json::from_str(&contents)?;
+        if file.version != KEYSTORE_VERSION {
+            eyre::bail!("unsupported keystore version {}", file.version);
+        }
+        if file.crypto.cipher != CIPHER || file.crypto.kdf != KDF {
+            eyre::bail!(
+                "unsupported keystore cipher {} or kdf {}",
+                file.crypto.cipher,
+                file.crypto.kdf
+            );
+        }
+
+        let key = file.crypto.kdfparams.derive_key(password)?;
+        let nonce = hex::decode(&file.crypto.cipherparams.nonce)?;
+        if nonce.len() != 24 {
+            eyre::bail!("invalid keystore nonce");
+        }
+        let ciphertext = hex::decode(&file.crypto.ciphertext)?;
+        let plaintext = Zeroizing::new(
+            XChaCha20Poly1305::new(Key::from_slice(&key[..]))
+                .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+                .map_err(|_| eyre::eyre!("failed to decrypt keystore: wrong password?"))?,
+        );
+        let notes = serde_json::from_slice(&plaintext)?;
+
+        Ok(Self { path: path.to_path_buf(), kdfparams: file.crypto.kdfparams, key, notes })
+    }
+
+    /// Create a new empty store at `path`, with an scrypt cost of `2^log_n`.
+    ///
+    /// Nothing is written until [`NoteStore::save`] is called. Fails if scrypt does not support a
+    /// cost of `2^log_n`.
+    pub fn create(path: &Path, password: &str, log_n: u8) -> eyre::Result<Self> {
+        let (r, p) = (8, 1);
+        // Validated first: scrypt rejects the costs that do not fit a `usize`, so the shift below
+        // does not overflow.
+        scrypt::Params::new(log_n, r, p, 32)
+            .map_err(|e| eyre::eyre!("invalid scrypt cost 2^{log_n}: {e}"))?;
+        let kdfparams =
+            KdfParams { dklen: 32, n: 1 << log_n, r, p, salt: hex::encode(random_bytes::<32>()?) };
+        let key = kdfparams.derive_key(password)?;
+        Ok(Self { path: path.to_path_buf(), kdfparams, key, notes: Vec::new() })
+    }
+
+    /// The notes in the store.
+    pub fn notes(&self) -> &[StoredNote] {
+        &self.notes
+    }
+
+    /// Add a note to the store.
+    pub fn add(
+        &mut self,
+        note: &WormholeNote,
+        label: Option<String>,
+        deposit_block: Option<u64>,
+    ) -> eyre::Result<&StoredNote> {
+        let dead_address = Address::from(note.dead_address());
+        if self.notes.iter().any(|stored| stored.dead_address == dead_address) {
+            eyre::bail!("the keystore already contains the note for {dead_address}");
+        }
+        if let Some(label) = &label {
+            if self.notes.iter().any(|stored| stored.label.as_ref() == Some(label)) {
+                eyre::bail!("the keystore already contains a note labelled {label}");
+            }
+        }
+
+        self.notes.push(StoredNote {
+            label,
+            note: note.encode()?,
+            chain_id: note.chain_id,
+            token: note.token,
+            dead_address,
+            deposit_block,
+            created_at: now(),
+            claims: Vec::new(),
+        });
+        Ok(self.notes.last().unwrap())
+    }
+
+    /// Find a note by label, burn address or 1-based position in [`NoteStore::notes`].
+    pub fn get(&self, id: &str) -> eyre::Result<&StoredNote> {
+        Ok(&self.notes[self.position(id)?])
+    }
+
+    /// Record a claim made from the note `id`.
+    pub fn record_claim(&mut self, id: &str, claim: ClaimRecord) -> eyre::Result<()> {
+        let position = self.position(id)?;
+        self.notes[position].claims.push(claim);
+        Ok(())
+    }
+
+    /// Encrypt the store and write it to its file, readable only by the current user.
+    pub fn save(&self) -> eyre::Result<()> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(&self.notes)?);
+        let nonce = random_bytes::<24>()?;
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&self.key[..]))
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| eyre::eyre!("failed to encrypt keystore"))?;
+
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            crypto: CryptoParams {
+                cipher: CIPHER.to_string(),
+                cipherparams: CipherParams { nonce: hex::encode(nonce) },
+                ciphertext: hex::encode(ciphertext),
+                kdf: KDF.to_string(),
+                kdfparams: self.kdfparams.clone(),
+            },
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first, so that a failed write never corrupts the keystore.
+        let tmp_path = self.path.with_extension("tmp");
+        let _ = std::fs::remove_file(&tmp_path);
+        write_secret_file(&tmp_path, &serde_json::to_string_pretty(&file)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn position(&self, id: &str) -> eyre::Result<usize> {
+        let dead_address = id.parse::<Address>().ok();
+        self.notes
+            .iter()
+            .position(|note| {
+                note.label.as_deref() == Some(id) || Some(note.dead_address) == dead_address
+            })
+            .or_else(|| {
+                id.parse::<usize>()
+                    .ok()
+                    .filter(|position| (1..=self.notes.len()).contains(position))
+                    .map(|position| position - 1)
+            })
+            .ok_or_else(|| eyre::eyre!("no note {id} in the keystore"))
+    }
+}
+
+impl fmt::Debug for NoteStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoteStore")
+            .field("path", &self.path)
+            .field("notes", &self.notes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Read the keystore password from a file, or stdin if `path` is `-`, or else from the
+/// [`PASSWORD_ENV`] environment variable.
+pub fn read_password(path: Option<&Path>) -> eyre::Result<Zeroizing<String>> {
+    let password = match path {
+        Some(path) => read_secret_file(path)?,
+        None => Zeroizing::new(std::env::var(PASSWORD_ENV).map_err(|_| {
+            eyre::eyre!(
+                "no keystore password given: pass --password-file <PATH>, or set {PASSWORD_ENV}"
+            )
+        })?),
+    };
+    Ok(Zeroizing::new(password.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn random_bytes<const N: usize>() -> eyre::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| eyre::eyre!("failed to read from the OS CSPRNG: {e}"))?;
+    Ok(bytes)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::address;
+
+    use super::*;
+    use crate::secret::NoteSecret;
+
+    const TOKEN: Address = address!("6D46BE315b48f579387A5EA247E1E25D2FcCE7EE");
+
+    fn note(secret: &str) -> WormholeNote {
+        let secret = NoteSecret::from_reader(format!("{secret}\n0x99\n").as_bytes()).unwrap();
+        WormholeNote::new(17000, TOKEN, secret)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("zkw-keystore-test-{}", std::process::id()));
+        let path = dir.join("keystore.json");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut store = NoteStore::create(&path, "password", 10).unwrap();
+        store.add(&note("0x42"), Some("first".to_string()), Some(100)).unwrap();
+        store.add(&note("0x43"), None, None).unwrap();
+        assert!(store.add(&note("0x42"), None, None).is_err());
+        let claim = ClaimRecord {
+            amount: 5,
+            receiver: Address::ZERO,
+            block_hash: B256::ZERO,
+            nullifier: B256::ZERO,
+            claimed_at: 0,
+        };
+        store.record_claim("first", claim.clone()).unwrap();
+        store.save().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("zkw-"));
+        assert!(NoteStore::open(&path, "wrong").is_err());
+
+        let store = NoteStore::open(&path, "password").unwrap();
+        assert_eq!(store.notes().len(), 2);
+        let first = store.get("first").unwrap();
+        assert_eq!(first.deposit_block, Some(100));
+        assert_eq!(first.claims, vec![claim]);
+        assert_eq!(first.note().unwrap().dead_address(), note("0x42").dead_address());
+        let second = store.get(&store.notes()[1].dead_address.to_string()).unwrap();
+        assert_eq!(second.claimed(), 0);
+        assert!(store.get("3").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_cost() {
+        let path = std::env::temp_dir().join("zkw-keystore-test-cost.json");
+        assert!(NoteStore::create(&path, "password", 64).is_err());
+        assert!(NoteStore::create(&path, "password", u8::MAX).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! Host-side library of the ZK Wormhole, usable from other Rust tools.
+//!
+//! It provides burn address computation, the input of the wormhole program for a claim, note
+//! secrets and the portable note format, deterministic note derivation from a mnemonic and recovery
+//! of funded notes, an encrypted note keystore, and token amount handling.
+
+pub mod amount;
+pub mod claim;
+pub mod derivation;
+pub mod keystore;
+pub mod note;
+pub mod scan;
+pub mod secret;
+
+use alloy_primitives::B256;
+use alloy_sol_macro::sol;
+use sha2::{Digest, Sha256};
+
+sol! {
+    /// The view functions of a wormhole token used to check a burn address.
+    interface IERC20 {
+        function balanceOf(address account) external view returns (uint256);
+        function getDeadHashAmount(bytes32 h) external view returns (uint256);
+    }
+}
+
+/// Compute the unspendable CREATE2-style burn address of a note from its secret and nonce.
+///
+/// This must match the computation done by the wormhole program in the zkVM.
+pub fn compute_dead_address(secret: &str, nonce: &str) -> [u8; 20] {
+    let msg_sender: [u8; 20] = [0x01; 20];
+    let bytecode: [u8; 32] = [0x00; 32];
+
+    let mut hasher = Sha256::new();
+    hasher.update(secret);
+    let secret_hash = hasher.finalize();
+
+    let mut hasher = Sha256::new();
+    hasher.update(secret_hash);
+    hasher.update(nonce);
+    let salt = hasher.finalize();
+
+    let mut hasher = Sha256::new();
+    hasher.update([0xff]);
+    hasher.update(msg_sender);
+    hasher.update(salt);
+    hasher.update(bytecode);
+    let computed_address_full = hasher.finalize();
+
+    computed_address_full[12..].try_into().unwrap()
+}
+
+/// The hash of a burn address, under which the token records the amount already claimed from it.
+pub fn dead_address_hash(dead_address: &[u8; 20]) -> B256 {
+    B256::from(<[u8; 32]>::from(Sha256::digest(dead_address)))
+}
@@ -1,47 +1,40 @@
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
 
 use alloy::hex;
-use alloy_primitives::{Address,B256};
-use alloy_provider::RootProvider;
+use alloy_primitives::{address, Address, U256};
+use alloy_provider::{network::AnyNetwork, Provider, RootProvider};
+use alloy_rpc_client::ClientBuilder;
 use alloy_rpc_types::BlockNumberOrTag;
-use alloy_sol_types::{SolType, SolValue};
-use alloy_sol_macro::sol;
-use clap::Parser;
+use alloy_sol_types::SolType;
+use clap::{Parser, Subcommand};
+use eyre::WrapErr;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use sp1_cc_client_executor::ContractInput;
-use sp1_cc_host_executor::HostExecutor;
-use sp1_sdk::{include_elf, utils, HashableKey, ProverClient, SP1ProofWithPublicValues, SP1Stdin};
+use sp1_cc_client_executor::io::EVMStateSketch;
+use sp1_cc_host_executor::{
+    CacheConfig, HostExecutor, QuorumConfig, RetryConfig, SketchExecutor, SketchReport, StateFile,
+    TrieExecutor,
+};
+use sp1_sdk::{utils, HashableKey, ProverClient, SP1ProofWithPublicValues};
 use url::Url;
 
-sol! {
-    interface IERC20 {
-        function balanceOf(address account) external view returns (uint256);
-        function getDeadHashAmount(bytes32 h) external view returns (uint256);
-    }
-}
+use zeroize::Zeroizing;
+use zk_wormhole_host::{
+    amount::TokenInfo,
+    claim::{prepare_claim, ClaimWitness, Pruning, PublicValuesStruct, ELF},
+    derivation::{self, NoteSeed},
+    keystore::{self, ClaimRecord, NoteStore},
+    note::{self, WormholeNote},
+    scan::{scan, NoteStatus, ScanConfig},
+    secret::{self, NoteSecret},
+};
 
-sol! {
-    struct PublicValuesStruct {
-        uint64 amount;
-        address receiver;
-        bytes32 nullifier;
-        bytes32 deadAddressHash;
-        bytes32 blockHash;
-        address contractAddress;
-        bytes data;
-    }
-}
+/// Our zkwusd usd token.
+const DEFAULT_TOKEN: Address = address!("6D46BE315b48f579387A5EA247E1E25D2FcCE7EE");
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct AddressInput {
-    contract: Address,
-    target: Address,
-    min_amount: u64,
-}
+const HOLESKY_CHAIN_ID: u64 = 17000;
 
-const ELF: &[u8] = include_elf!("zk-wormhole-program");
+/// The RPC the host fetches the chain state from.
+const RPC_URL: &str = "https://ethereum-holesky-rpc.publicnode.com";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -51,53 +44,283 @@ struct SP1CCProofFixture {
     proof: String,
 }
 
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
 
-fn compute_dead_address(secret: String, nonce: String) -> [u8; 20] {
-    let msg_sender: [u8; 20] = [0x01; 20];
-    let bytecode: [u8; 32] = [0x00; 32];
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a new note from the OS CSPRNG.
+    NewNote {
+        /// The wormhole token the note is for.
+        #[clap(long, default_value_t = DEFAULT_TOKEN)]
+        contract_address: Address,
+
+        /// The chain id the note is for.
+        #[clap(long, default_value_t = HOLESKY_CHAIN_ID)]
+        chain_id: u64,
+
+        /// Write the note to this file instead of stdout. Never overwrites an existing file.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Generate a new BIP-39 mnemonic to derive notes from.
+    NewMnemonic {
+        /// Number of words of the mnemonic.
+        #[clap(long, default_value_t = 24)]
+        words: usize,
+
+        /// Write the mnemonic to this file instead of stdout. Never overwrites an existing file.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Derive the note at an index from a mnemonic.
+    Derive {
+        #[clap(flatten)]
+        mnemonic: MnemonicArgs,
+
+        /// Index of the note to derive.
+        #[clap(long)]
+        index: u32,
+
+        /// The wormhole token the note is for.
+        #[clap(long, default_value_t = DEFAULT_TOKEN)]
+        contract_address: Address,
+
+        /// The chain id the note is for.
+        #[clap(long, default_value_t = HOLESKY_CHAIN_ID)]
+        chain_id: u64,
+
+        /// Write the note to this file instead of stdout. Never overwrites an existing file.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Find the funded notes derived from a mnemonic.
+    Scan {
+        #[clap(flatten)]
+        mnemonic: MnemonicArgs,
+
+        /// The wormhole token to scan for.
+        #[clap(long, default_value_t = DEFAULT_TOKEN)]
+        contract_address: Address,
+
+        /// The chain id to scan on.
+        #[clap(long, default_value_t = HOLESKY_CHAIN_ID)]
+        chain_id: u64,
+
+        /// The first derivation index to scan.
+        #[clap(long, default_value_t = 0)]
+        start_index: u32,
+
+        /// Stop after this many consecutive unused indices.
+        #[clap(long, default_value_t = 20)]
+        gap_limit: u32,
+
+        /// The number of burn addresses queried per JSON-RPC batch.
+        #[clap(long, default_value_t = 50)]
+        batch_size: u32,
+    },
+    /// Manage the encrypted note keystore.
+    Keystore {
+        /// Path of the keystore file. Defaults to `~/.zk-wormhole/keystore.json`.
+        #[clap(long)]
+        keystore: Option<PathBuf>,
+
+        /// File containing the keystore password. Use `-` to read from stdin.
+        ///
+        /// If omitted, the `ZKW_KEYSTORE_PASSWORD` environment variable is used.
+        #[clap(long)]
+        password_file: Option<PathBuf>,
+
+        #[clap(subcommand)]
+        command: KeystoreCommand,
+    },
+    /// Print the burn address of a note.
+    Dead(SecretArgs),
+    /// Execute the wormhole program without generating a proof.
+    Execute(ClaimArgs),
+    /// Generate and verify a proof of the claim.
+    Prove(ClaimArgs),
+    /// Print the content of a state sketch saved with `--sketch-out`.
+    Sketch {
+        /// The sketch file.
+        path: PathBuf,
+
+        /// Also export the full content of the sketch to this file as JSON, for audits.
+        #[clap(long)]
+        json: Option<PathBuf>,
+    },
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(secret);
-    let secret_hash = hasher.finalize();
+#[derive(Subcommand, Debug)]
+enum KeystoreCommand {
+    /// Add a note to the keystore, creating the keystore if needed.
+    Add {
+        #[clap(flatten)]
+        secret: SecretArgs,
+
+        /// A label to refer to the note by.
+        #[clap(long)]
+        label: Option<String>,
+
+        /// The block the tokens were burnt in.
+        #[clap(long)]
+        deposit_block: Option<u64>,
+    },
+    /// List the notes in the keystore.
+    List,
+    /// Print a note string, to hand it to another person or device.
+    Export {
+        /// The label, burn address or position of the note.
+        id: String,
+    },
+    /// Prove a claim from a note, and record it in the claim history of the note.
+    Spend {
+        /// The label, burn address or position of the note.
+        id: String,
+
+        #[clap(flatten)]
+        options: ClaimOptions,
+    },
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(&secret_hash);
-    hasher.update(nonce);
-    let salt = hasher.finalize();
+/// Where to read the note from.
+#[derive(clap::Args, Debug)]
+struct SecretArgs {
+    /// File containing a note string, as created by `new-note`. Use `-` to read from stdin.
+    #[clap(long, conflicts_with = "secret_file")]
+    note: Option<PathBuf>,
+
+    /// File containing the note secret and nonce, one per line. Use `-` to read from stdin.
+    ///
+    /// If neither this nor `--note` is given, the `ZKW_NOTE` environment variable is used, or
+    /// else `ZKW_SECRET` and `ZKW_NONCE`.
+    #[clap(long)]
+    secret_file: Option<PathBuf>,
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(&[0xff]);
-    hasher.update(&msg_sender);
-    hasher.update(&salt);
-    hasher.update(&bytecode);
-    let computed_address_full = hasher.finalize();
+impl SecretArgs {
+    /// Load the note. A bare secret and nonce is assumed to be for `token` on Holesky.
+    fn load(&self, token: Option<Address>) -> eyre::Result<WormholeNote> {
+        let note = if let Some(path) = &self.note {
+            WormholeNote::from_file(path)?
+        } else if let Some(path) = &self.secret_file {
+            let secret = NoteSecret::from_file(path)?;
+            WormholeNote::new(HOLESKY_CHAIN_ID, token.unwrap_or(DEFAULT_TOKEN), secret)
+        } else if let Some(note) = WormholeNote::from_env()? {
+            note
+        } else if let Some(secret) = NoteSecret::from_env()? {
+            WormholeNote::new(HOLESKY_CHAIN_ID, token.unwrap_or(DEFAULT_TOKEN), secret)
+        } else {
+            eyre::bail!(
+                "no note given: pass --note <PATH> or --secret-file <PATH> (or - for stdin), or \
+                 set {} or {} and {}",
+                note::NOTE_ENV,
+                secret::SECRET_ENV,
+                secret::NONCE_ENV
+            );
+        };
+
+        Ok(note)
+    }
+}
 
-    computed_address_full[12..].try_into().unwrap()
+/// Where to read the mnemonic from.
+#[derive(clap::Args, Debug)]
+struct MnemonicArgs {
+    /// File containing the BIP-39 mnemonic. Use `-` to read from stdin.
+    ///
+    /// If omitted, the `ZKW_MNEMONIC` environment variable is used. An optional BIP-39 passphrase
+    /// is read from `ZKW_MNEMONIC_PASSPHRASE`.
+    #[clap(long)]
+    mnemonic_file: Option<PathBuf>,
 }
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    #[clap(long, default_value = "false")]
-    prove: bool,
+impl MnemonicArgs {
+    fn load(&self) -> eyre::Result<NoteSeed> {
+        let passphrase =
+            Zeroizing::new(std::env::var(derivation::PASSPHRASE_ENV).unwrap_or_default());
+        if let Some(path) = &self.mnemonic_file {
+            return NoteSeed::from_file(path, &passphrase);
+        }
+        NoteSeed::from_env(&passphrase)?.ok_or_else(|| {
+            eyre::eyre!(
+                "no mnemonic given: pass --mnemonic-file <PATH> (or - for stdin), or set {}",
+                derivation::MNEMONIC_ENV
+            )
+        })
+    }
+}
 
-    #[clap(long, default_value = "false")]
-    dead: bool,
+#[derive(clap::Args, Debug)]
+struct ClaimArgs {
+    #[clap(flatten)]
+    secret: SecretArgs,
 
-    #[clap(long, default_value = "0x6D46BE315b48f579387A5EA247E1E25D2FcCE7EE")]
-    contract_address: String, // our zkwusd usd token
+    #[clap(flatten)]
+    options: ClaimOptions,
+}
 
-    #[clap(long, default_value = "1000000000000000000")]
-    amount: u64,
+#[derive(clap::Args, Debug)]
+struct ClaimOptions {
+    /// The wormhole token. Defaults to the token of the note.
+    #[clap(long)]
+    contract_address: Option<Address>,
 
-    #[clap(long, default_value = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045")] //vitalik.eth
-    receiver: String,
+    /// Amount to claim, e.g. `12.5` or `12.5 USDC`. Interpreted as base units with `--raw`.
+    #[clap(long, default_value = "1")]
+    amount: String,
 
-    #[clap(long, default_value = "0x4242424242424242424242424242424242424242424242424242424242424242")]
-    secret: String,
+    #[clap(long, default_value = "false")]
+    raw: bool,
 
-    #[clap(long, default_value = "0x9999999999999999999999999999999999999999999999999999999999999999")]
-    nonce: String,
+    /// The address receiving the claimed tokens.
+    #[clap(long, default_value = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045")] //vitalik.eth
+    receiver: Address,
+
+    /// Check every call executed locally against the RPC's `eth_call` before proving.
+    #[clap(long)]
+    verify_calls: bool,
+
+    /// An RPC to fetch the state proofs from when the default one serves invalid ones.
+    #[clap(long)]
+    fallback_rpc_url: Option<String>,
+
+    /// Another RPC that must agree with the default one on the block and the state proofs. Can be
+    /// repeated.
+    #[clap(long = "quorum-rpc-url")]
+    quorum_rpc_urls: Vec<String>,
+
+    /// The number of RPCs that must agree with `--quorum-rpc-url`. Must be a majority of them,
+    /// which is the default.
+    #[clap(long)]
+    quorum: Option<usize>,
+
+    /// Claim against the last block of an anvil `--dump-state` file instead of the RPC, e.g. to
+    /// run against a local devnet.
+    #[clap(long, conflicts_with_all = ["verify_calls", "fallback_rpc_url", "quorum_rpc_urls"])]
+    state_file: Option<PathBuf>,
+
+    /// Cache the RPC responses in this directory. Responses are keyed by block hash, so the cache
+    /// serves the requests for a block it already holds, e.g. when a claim is run again.
+    #[clap(long, conflicts_with = "state_file")]
+    rpc_cache: Option<PathBuf>,
+
+    /// Save the state sketch of the claim to this file, to inspect it with `sketch`.
+    #[clap(long)]
+    sketch_out: Option<PathBuf>,
+
+    /// Keep the whole state sketch, instead of pruning it down to the calls of the program.
+    #[clap(long)]
+    no_prune: bool,
+
+    /// Also execute the program on the unpruned state sketch, to report the cycles pruning saves.
+    /// Only used by `execute`, which then executes the program twice.
+    #[clap(long, conflicts_with = "no_prune")]
+    report_pruning: bool,
 }
 
 fn save_fixture(vkey: String, proof: &SP1ProofWithPublicValues) {
@@ -123,10 +346,120 @@ fn save_fixture(vkey: String, proof: &SP1ProofWithPublicValues) {
     .expect("Failed to write fixture");
 
     // Log the success message with the absolute path
-    println!(
-        "Proof and public values saved to '{}'",
-        full_path.display()
-    );
+    println!("Proof and public values saved to '{}'", full_path.display());
+}
+
+/// Print a new note to `out`, or to stdout if `out` is not given.
+fn output_note(note: &WormholeNote, out: Option<PathBuf>) -> eyre::Result<()> {
+    match out {
+        Some(path) => {
+            note.write_to_file(&path)?;
+            println!("Note saved to '{}'", path.display());
+            print_dead_address(note.dead_address());
+        }
+        None => {
+            // Only the note goes to stdout, so that it can be redirected to a file.
+            println!("{}", *note.encode()?);
+            eprintln!("Dead address generated: 0x{}", hex::encode(note.dead_address()));
+        }
+    }
+    Ok(())
+}
+
+/// Connect to the RPC, checking that it serves `chain_id`, and caching its responses in
+/// `rpc_cache` if set.
+async fn connect(
+    chain_id: u64,
+    rpc_cache: Option<&Path>,
+) -> eyre::Result<RootProvider<AnyNetwork>> {
+    connect_to(RPC_URL, chain_id, rpc_cache).await
+}
+
+/// Connect to the RPC at `rpc_url`, checking that it serves `chain_id`, and caching its responses
+/// in `rpc_cache` if set.
+async fn connect_to(
+    rpc_url: &str,
+    chain_id: u64,
+    rpc_cache: Option<&Path>,
+) -> eyre::Result<RootProvider<AnyNetwork>> {
+    // let rpc_url = std::env::var("ETH_RPC_URL").unwrap_or_else(|_| panic!("Missing ETH_RPC_URL in env"));
+    let url = Url::parse(rpc_url)?;
+    let retry = RetryConfig::default();
+    // The chain is checked without the cache, so that the cache of a chain never holds the
+    // responses of another one.
+    let provider = check_chain(retry.http_provider(url.clone()), rpc_url, chain_id).await?;
+    Ok(match rpc_cache {
+        Some(dir) => CacheConfig::new(dir, chain_id).http_provider(url, &retry),
+        None => provider,
+    })
+}
+
+/// Connect to the default RPC and to `rpc_urls`, requiring `quorum` of them to agree on the block
+/// and the state proofs, and checking that they serve `chain_id`.
+async fn connect_quorum(
+    rpc_urls: &[String],
+    quorum: Option<usize>,
+    chain_id: u64,
+    rpc_cache: Option<&Path>,
+) -> eyre::Result<RootProvider<AnyNetwork>> {
+    let urls = std::iter::once(RPC_URL)
+        .chain(rpc_urls.iter().map(String::as_str))
+        .map(Url::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    let config = match quorum {
+        Some(quorum) => QuorumConfig::new(urls, quorum)?,
+        None => QuorumConfig::majority(urls),
+    };
+    let name = format!("the {} quorum RPCs", config.urls.len());
+    let retry = RetryConfig::default();
+    let provider = check_chain(config.http_provider(&retry)?, &name, chain_id).await?;
+    Ok(match rpc_cache {
+        Some(dir) => RootProvider::new(
+            ClientBuilder::default()
+                .layer(CacheConfig::new(dir, chain_id).layer())
+                .transport(config.transport(&retry)?, false),
+        ),
+        None => provider,
+    })
+}
+
+/// Check that `provider`, called `name` in errors, serves `chain_id`.
+async fn check_chain(
+    provider: RootProvider<AnyNetwork>,
+    name: &str,
+    chain_id: u64,
+) -> eyre::Result<RootProvider<AnyNetwork>> {
+    let rpc_chain_id = provider.get_chain_id().await?;
+    if rpc_chain_id != chain_id {
+        eyre::bail!(
+            "the note is for chain {}, but {} serves chain {}",
+            chain_id,
+            name,
+            rpc_chain_id
+        );
+    }
+    Ok(provider)
+}
+
+fn print_dead_address(dead_address: [u8; 20]) {
+    println!("");
+    println!("//////////////////////////////////////");
+    println!("//////////////////////////////////////");
+    println!("ZKwUSD ERC20: the fist ERC20 enabling private transfers natively.");
+    println!("//////////////////////////////////////");
+    println!("//////////////////////////////////////");
+    println!("");
+    println!("");
+    println!("Dead address generated: 0x{}", hex::encode(dead_address));
+    println!("//////////////////////////////////////");
+    println!("//////////////////////////////////////");
+    println!("");
+    println!("Now it's your time to shine.");
+    println!("Send to this address your ZkwUSD to:");
+    println!("0x{}", hex::encode(dead_address));
+    println!("and then generate the proof running:");
+    println!("./executables/zk-wormhole-host prove --note <note file>");
+    println!("//////////////////////////////////////");
 }
 
 #[tokio::main]
@@ -136,96 +469,286 @@ async fn main() -> eyre::Result<()> {
 
     let args = Args::parse();
 
-    let contract_address = Address::from_str(&args.contract_address)
-        .map_err(|_| eyre::eyre!("Invalid contract address"))?;
-    let secret = &args.secret;
-    let nonce = &args.nonce;
-    let amount = args.amount;
-    let receiver: [u8; 20] = hex::decode(&args.receiver[2..])?.try_into().map_err(|_| eyre::eyre!("Invalid receiver length"))?;
-    let wormAddress: [u8; 20] = hex::decode(&args.contract_address[2..])?.try_into().map_err(|_| eyre::eyre!("Invalid receiver length"))?;
+    match args.command {
+        Command::NewNote { contract_address, chain_id, out } => {
+            let note = WormholeNote::new(chain_id, contract_address, NoteSecret::generate()?);
+            return output_note(&note, out);
+        }
+        Command::NewMnemonic { words, out } => {
+            let mnemonic = NoteSeed::generate_mnemonic(words)?;
+            match out {
+                Some(path) => {
+                    let contents = Zeroizing::new(format!("{}\n", *mnemonic));
+                    secret::write_secret_file(&path, &contents)?;
+                    println!("Mnemonic saved to '{}'", path.display());
+                }
+                None => println!("{}", *mnemonic),
+            }
+            return Ok(());
+        }
+        Command::Derive { mnemonic, index, contract_address, chain_id, out } => {
+            let seed = mnemonic.load()?;
+            eprintln!("Derivation path: {}", derivation::derivation_path(index));
+            let note = WormholeNote::new(chain_id, contract_address, seed.derive(index));
+            return output_note(&note, out);
+        }
+        Command::Scan {
+            mnemonic,
+            contract_address,
+            chain_id,
+            start_index,
+            gap_limit,
+            batch_size,
+        } => {
+            let seed = mnemonic.load()?;
+            let provider = connect(chain_id, None).await?;
+            let mut host_executor =
+                HostExecutor::new(provider.clone(), BlockNumberOrTag::Latest).await?;
+            let token = TokenInfo::fetch(&mut host_executor, contract_address).await?;
+            let block_number = host_executor.header.number;
+
+            println!("Scanning notes at block {}", block_number);
+            let config = ScanConfig { start_index, gap_limit, batch_size };
+            let notes =
+                scan(&provider, &seed, contract_address, block_number.into(), &config).await?;
+
+            let mut unclaimed = U256::ZERO;
+            for note in &notes {
+                let status = match note.status() {
+                    NoteStatus::Funded => "funded",
+                    NoteStatus::PartiallyClaimed => "partially claimed",
+                    NoteStatus::FullyClaimed => "fully claimed",
+                };
+                println!(
+                    "#{} {} {}: burnt {}, claimed {}, unclaimed {}",
+                    note.index,
+                    note.dead_address,
+                    status,
+                    token.format_amount(note.balance),
+                    token.format_amount(note.claimed),
+                    token.format_amount(note.unclaimed())
+                );
+                unclaimed += note.unclaimed();
+            }
+            println!(
+                "Found {} funded notes, {} unclaimed",
+                notes.len(),
+                token.format_amount(unclaimed)
+            );
+            return Ok(());
+        }
+        Command::Keystore { keystore, password_file, command } => {
+            let path = match keystore {
+                Some(path) => path,
+                None => default_keystore_path()?,
+            };
+            let password = keystore::read_password(password_file.as_deref())?;
+            return run_keystore(&path, &password, command).await;
+        }
+        Command::Dead(secret_args) => {
+            print_dead_address(secret_args.load(None)?.dead_address());
+            return Ok(());
+        }
+        Command::Execute(args) => {
+            let note = args.secret.load(args.options.contract_address)?;
+            claim(&note, &args.options, false).await?;
+        }
+        Command::Prove(args) => {
+            let note = args.secret.load(args.options.contract_address)?;
+            claim(&note, &args.options, true).await?;
+        }
+        Command::Sketch { path, json } => {
+            let bytes = std::fs::read(&path)
+                .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+            let sketch = EVMStateSketch::decode(&bytes)
+                .wrap_err_with(|| format!("invalid sketch file {}", path.display()))?;
+            let report = SketchReport::new(&sketch);
+            println!("{}", report);
+            if let Some(json) = json {
+                std::fs::write(&json, serde_json::to_string_pretty(&report)?)
+                    .wrap_err_with(|| format!("failed to write {}", json.display()))?;
+                println!("Sketch exported to '{}'", json.display());
+            }
+        }
+    }
 
-    let dead_address = compute_dead_address(secret.to_string(), nonce.to_string());
+    Ok(())
+}
 
-    let rpc_url = "https://ethereum-holesky-rpc.publicnode.com";
-    
-    // let rpc_url = std::env::var("ETH_RPC_URL").unwrap_or_else(|_| panic!("Missing ETH_RPC_URL in env"));
-    let provider = RootProvider::new_http(Url::parse(&rpc_url)?);
-    let mut host_executor = HostExecutor::new(provider.clone(), BlockNumberOrTag::Latest).await?;
-    let block_hash = host_executor.header.hash_slow();
-
-    let balance_call = IERC20::balanceOfCall { account: Address::from_slice(&dead_address) };
-    host_executor
-        .execute(ContractInput::new_call(contract_address, Address::default(), balance_call))
-        .await?;
-
-    let mut hasher = Sha256::new();
-    hasher.update(&dead_address);
-    let dead_address_hash = hasher.finalize();
-    let dead_address_hash_bytes: [u8; 32] = dead_address_hash.into();
-
-    let get_dead_hash_amount_call = IERC20::getDeadHashAmountCall { h: alloy_primitives::FixedBytes(dead_address_hash_bytes) };
-    host_executor
-        .execute(ContractInput::new_call(contract_address, Address::default(), get_dead_hash_amount_call))
-        .await?;
-
-    let state_sketch = host_executor.finalize().await?;
-
-    let address_input = AddressInput {
-        contract: contract_address,
-        target: Address::from_slice(&dead_address),
-        min_amount: amount,
+/// The default keystore location, `~/.zk-wormhole/keystore.json`.
+fn default_keystore_path() -> eyre::Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| eyre::eyre!("HOME is not set, pass --keystore <PATH>"))?;
+    Ok(PathBuf::from(home).join(".zk-wormhole").join("keystore.json"))
+}
+
+async fn run_keystore(
+    path: &std::path::Path,
+    password: &str,
+    command: KeystoreCommand,
+) -> eyre::Result<()> {
+    match command {
+        KeystoreCommand::Add { secret, label, deposit_block } => {
+            let note = secret.load(None)?;
+            let mut store = NoteStore::open_or_create(path, password)?;
+            let stored = store.add(&note, label, deposit_block)?;
+            println!("Added note {} to '{}'", stored.dead_address, path.display());
+            store.save()?;
+        }
+        KeystoreCommand::List => {
+            let store = NoteStore::open(path, password)?;
+            for (position, stored) in store.notes().iter().enumerate() {
+                let deposit_block = stored
+                    .deposit_block
+                    .map(|block| format!(", deposited at block {}", block))
+                    .unwrap_or_default();
+                println!(
+                    "{} {} chain {} token {}: {}{}, claimed {} base units in {} claims",
+                    position + 1,
+                    stored.label.as_deref().unwrap_or("-"),
+                    stored.chain_id,
+                    stored.token,
+                    stored.dead_address,
+                    deposit_block,
+                    stored.claimed(),
+                    stored.claims.len()
+                );
+            }
+        }
+        KeystoreCommand::Export { id } => {
+            let store = NoteStore::open(path, password)?;
+            println!("{}", store.get(&id)?.encoded());
+        }
+        KeystoreCommand::Spend { id, options } => {
+            let mut store = NoteStore::open(path, password)?;
+            let note = store.get(&id)?.note()?;
+            let public_values = claim(&note, &options, true).await?;
+            store.record_claim(
+                &id,
+                ClaimRecord::new(
+                    public_values.amount,
+                    public_values.receiver,
+                    public_values.blockHash,
+                    public_values.nullifier,
+                ),
+            )?;
+            store.save()?;
+            println!("Recorded the claim in '{}'", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Fetch the metadata of the token of `note` at the block of `executor`, and build the input of
+/// the wormhole program for a claim from it.
+async fn prepare(
+    executor: &mut (impl SketchExecutor + Clone),
+    note: &WormholeNote,
+    options: &ClaimOptions,
+) -> eyre::Result<(ClaimWitness, TokenInfo)> {
+    // Fetch the metadata through a copy of the executor, so that the state it reads stays out of
+    // the sketch of the claim, even when it is not pruned.
+    let token = TokenInfo::fetch(&mut executor.clone(), note.token).await?;
+    let amount = token.parse_amount(&options.amount, options.raw)?;
+    let pruning = if options.no_prune {
+        Pruning::Disabled
+    } else if options.report_pruning {
+        Pruning::Report
+    } else {
+        Pruning::Enabled
     };
+    let witness = prepare_claim(executor, note, amount, options.receiver, pruning).await?;
+    Ok((witness, token))
+}
 
-    let mut stdin = SP1Stdin::new();
-    stdin.write(&secret);
-    stdin.write(&nonce);
-    stdin.write(&dead_address);
-    stdin.write(&amount);
-    stdin.write(&receiver);
-    stdin.write(&block_hash);
-    stdin.write(&wormAddress);
-    stdin.write(&contract_address);
-    stdin.write(&Vec::<u8>::new());
-    stdin.write(&bincode::serialize(&state_sketch)?);
-    stdin.write(&bincode::serialize(&address_input)?);
+/// Execute the wormhole program for a claim from `note`, and generate and verify a proof of it if
+/// `prove` is set.
+async fn claim(
+    note: &WormholeNote,
+    options: &ClaimOptions,
+    prove: bool,
+) -> eyre::Result<PublicValuesStruct> {
+    let contract_address = note.token;
+    if let Some(token) = options.contract_address {
+        if token != contract_address {
+            eyre::bail!("the note is for token {}, not {}", contract_address, token);
+        }
+    }
+    let (witness, token) = match &options.state_file {
+        Some(path) => {
+            let mut executor = TrieExecutor::from_state_file(&StateFile::load(path)?)?;
+            prepare(&mut executor, note, options).await?
+        }
+        None => {
+            let (provider, provider_name) = if options.quorum_rpc_urls.is_empty() {
+                (connect(note.chain_id, options.rpc_cache.as_deref()).await?, RPC_URL.to_string())
+            } else {
+                let provider = connect_quorum(
+                    &options.quorum_rpc_urls,
+                    options.quorum,
+                    note.chain_id,
+                    options.rpc_cache.as_deref(),
+                )
+                .await?;
+                (provider, format!("the {} quorum RPCs", options.quorum_rpc_urls.len() + 1))
+            };
+            let mut host_executor = HostExecutor::new(provider.clone(), BlockNumberOrTag::Latest)
+                .await?
+                .with_provider_name(provider_name)
+                .with_call_verification(options.verify_calls);
+            if let Some(fallback_rpc_url) = &options.fallback_rpc_url {
+                let fallback = connect_to(fallback_rpc_url, note.chain_id, None).await?;
+                host_executor =
+                    host_executor.with_fallback_provider(fallback_rpc_url.as_str(), fallback);
+            }
+            prepare(&mut host_executor, note, options).await?
+        }
+    };
+    if let Some(path) = &options.sketch_out {
+        std::fs::write(path, witness.state_sketch.encode())
+            .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+        println!("Sketch {} saved to '{}'", witness.state_sketch.content_hash(), path.display());
+    }
+    if let Some(pruning) = &witness.pruning {
+        println!("Sketch {}", pruning.report);
+    }
+    let balance = witness.balance;
+    let stdin = witness.stdin;
 
     let client = ProverClient::from_env();
-    if args.dead {
-        let dead_address = compute_dead_address(args.secret, args.nonce);
-        
-        println!("");
-        println!("//////////////////////////////////////");
-        println!("//////////////////////////////////////");
-        println!("ZKwUSD ERC20: the fist ERC20 enabling private transfers natively.");
-        println!("//////////////////////////////////////");
-        println!("//////////////////////////////////////");
-        println!("");
-        println!("");
-        println!("Dead address generated: 0x{}",  hex::encode(dead_address));
-        println!("//////////////////////////////////////");
-        println!("//////////////////////////////////////");
-        println!("");
-        println!("Now it's your time to shine.");
-        println!("Send to this address your ZkwUSD to:");
-        println!("0x{}",  hex::encode(dead_address));
-        println!("and then generate the proof running:");
-        println!("./executables/zk-wormhole-host --prove --secret <secret> --nonce <secret>");
-        println!("//////////////////////////////////////");
-    } else if !args.prove {
+    if !prove {
         let (output, report) = client
             .execute(ELF, &stdin)
             .run()
             .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
         println!("Program executed successfully with {} cycles", report.total_instruction_count());
+        if let Some(unpruned_stdin) =
+            witness.pruning.as_ref().and_then(|pruning| pruning.unpruned_stdin.as_ref())
+        {
+            let (_, unpruned_report) = client
+                .execute(ELF, unpruned_stdin)
+                .run()
+                .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
+            let saved = unpruned_report
+                .total_instruction_count()
+                .saturating_sub(report.total_instruction_count());
+            println!("Pruning the sketch saved {} cycles", saved);
+        }
         let decoded = <PublicValuesStruct as SolType>::abi_decode(output.as_slice(), true)?;
-        println!("Amount: {}", decoded.amount);
+        println!("Burn address balance: {}", token.format_amount(balance));
+        println!(
+            "Amount: {} ({} base units)",
+            token.format_amount(U256::from(decoded.amount)),
+            decoded.amount
+        );
         println!("Receiver: 0x{}", hex::encode(decoded.receiver));
         println!("Nullifier: 0x{}", hex::encode(decoded.nullifier));
         println!("Dead address hash: 0x{}", hex::encode(decoded.deadAddressHash));
         println!("Block hash: 0x{}", hex::encode(decoded.blockHash));
         println!("Contract address: 0x{}", hex::encode(decoded.contractAddress));
         println!("Worm address: 0x{}", hex::encode(decoded.contractAddress));
-        println!("Data: 0x{}", hex::encode(decoded.data));
+        println!("Data: 0x{}", hex::encode(&decoded.data));
+        Ok(decoded)
     } else {
         let (pk, vk) = client.setup(ELF);
         let proof = client
@@ -235,19 +758,23 @@ async fn main() -> eyre::Result<()> {
             .map_err(|e| eyre::eyre!("Proof generation failed: {}", e))?;
         println!("Generated proof");
 
-        let public_vals = <PublicValuesStruct as SolType>::abi_decode(proof.public_values.as_slice(), true)?;
-        println!("Amount: {}", public_vals.amount);
+        let public_vals =
+            <PublicValuesStruct as SolType>::abi_decode(proof.public_values.as_slice(), true)?;
+        println!(
+            "Amount: {} ({} base units)",
+            token.format_amount(U256::from(public_vals.amount)),
+            public_vals.amount
+        );
         println!("Receiver: 0x{}", hex::encode(public_vals.receiver));
         println!("Block hash: 0x{}", hex::encode(public_vals.blockHash));
         println!("Contract address: 0x{}", hex::encode(public_vals.contractAddress));
-        println!("Data: 0x{}", hex::encode(public_vals.data));
+        println!("Data: 0x{}", hex::encode(&public_vals.data));
 
         save_fixture(vk.bytes32(), &proof);
         println!("Saved proof to plonk-fixture.json");
 
         client.verify(&proof, &vk).map_err(|e| eyre::eyre!("Verification failed: {}", e))?;
         println!("Successfully verified proof!");
+        Ok(public_vals)
     }
-
-    Ok(())
-}
\ No newline at end of file
+}
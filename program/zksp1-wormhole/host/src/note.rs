@@ -0,0 +1,231 @@
+//! The portable wormhole note format.
+//!
+//! A note bundles everything needed to claim burnt tokens into a single string:
+//!
+//! ```text
+//! zkw-<chain id>-<token address>-v<version>-<base58(payload || checksum)>
+//! ```
+//!
+//! The payload holds the length-prefixed secret and nonce. The checksum is the first four bytes
+//! of `sha256(sha256(prefix || payload))`, where the prefix is everything up to and including
+//! the last `-`, so that changing the chain or token of a note invalidates it too.
+
+use std::{fmt, path::Path, str::FromStr};
+
+use alloy_primitives::Address;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::secret::{read_secret_file, write_secret_file, NoteSecret};
+
+/// Prefix of every note string.
+pub const NOTE_PREFIX: &str = "zkw";
+
+/// Environment variable holding a note string.
+pub const NOTE_ENV: &str = "ZKW_NOTE";
+
+/// Length of the checksum appended to the note payload.
+const CHECKSUM_LEN: usize = 4;
+
+/// A wormhole note: the secret of a burn address, along with the chain and token it was burnt on.
+pub struct WormholeNote {
+    /// The chain id the tokens were burnt on.
+    pub chain_id: u64,
+    /// The address of the wormhole token.
+    pub token: Address,
+    /// The note secret and nonce.
+    pub secret: NoteSecret,
+}
+
+impl WormholeNote {
+    /// The current version of the note format.
+    pub const VERSION: u8 = 1;
+
+    /// Create a new [`WormholeNote`].
+    pub fn new(chain_id: u64, token: Address, secret: NoteSecret) -> Self {
+        Self { chain_id, token, secret }
+    }
+
+    /// The burn address of this note.
+    pub fn dead_address(&self) -> [u8; 20] {
+        self.secret.dead_address()
+    }
+
+    /// Encode the note as a string.
+    ///
+    /// The result contains the note secret, so it is returned in a buffer that is zeroed on drop.
+    /// Fails if the secret or the nonce is longer than the 65535 bytes its length prefix holds.
+    pub fn encode(&self) -> eyre::Result<Zeroizing<String>> {
+        let prefix = note_prefix(self.chain_id, &self.token, Self::VERSION);
+
+        let mut payload = Zeroizing::new(Vec::new());
+        for (name, value) in [("secret", &self.secret.secret), ("nonce", &self.secret.nonce)] {
+            let len = u16::try_from(value.len())
+                .map_err(|_| eyre::eyre!("the note {name} is too long: {} bytes", value.len()))?;
+            payload.extend_from_slice(&len.to_be_bytes());
+            payload.extend_from_slice(value.as_bytes());
+        }
+        let checksum = checksum(&prefix, &payload);
+        payload.extend_from_slice(&checksum);
+
+        Ok(Zeroizing::new(format!("{prefix}{}", bs58::encode(payload.as_slice()).into_string())))
+    }
+
+    /// Read a note string from a file, or from stdin if `path` is `-`.
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        read_secret_file(path)?.parse()
+    }
+
+    /// Read a note string from the [`NOTE_ENV`] environment variable, if it is set.
+    pub fn from_env() -> eyre::Result<Option<Self>> {
+        std::env::var(NOTE_ENV).ok().map(|note| Zeroizing::new(note).parse()).transpose()
+    }
+
+    /// Write the note string to a new file readable only by the current user.
+    ///
+    /// Fails if the file already exists, so that an existing note is never overwritten.
+    pub fn write_to_file(&self, path: &Path) -> eyre::Result<()> {
+        let mut contents = self.encode()?;
+        contents.push('\n');
+        write_secret_file(path, &contents)
+    }
+}
+
+impl FromStr for WormholeNote {
+    type Err = eyre::Report;
+
+    fn from_str(note: &str) -> eyre::Result<Self> {
+        let note = note.trim();
+        let mut parts = note.splitn(5, '-');
+        let (Some(tag), Some(chain_id), Some(token), Some(version), Some(data)) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            eyre::bail!("malformed note: expected {NOTE_PREFIX}-<chain>-<token>-<version>-<data>");
+        };
+
+        if tag != NOTE_PREFIX {
+            eyre::bail!("malformed note: expected the note to start with `{NOTE_PREFIX}-`");
+        }
+        let version = version
+            .strip_prefix('v')
+            .and_then(|version| version.parse::<u8>().ok())
+            .ok_or_else(|| eyre::eyre!("malformed note version: {version}"))?;
+        if version != Self::VERSION {
+            eyre::bail!("unsupported note version {version}, expected {}", Self::VERSION);
+        }
+        let chain_id =
+            chain_id.parse::<u64>().map_err(|_| eyre::eyre!("malformed note chain: {chain_id}"))?;
+        let token =
+            Address::from_str(token).map_err(|_| eyre::eyre!("malformed note token: {token}"))?;
+
+        let data = Zeroizing::new(
+            bs58::decode(data).into_vec().map_err(|e| eyre::eyre!("malformed note data: {e}"))?,
+        );
+        if data.len() < CHECKSUM_LEN {
+            eyre::bail!("malformed note: missing checksum");
+        }
+        let (payload, expected_checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        let prefix = note_prefix(chain_id, &token, version);
+        if checksum(&prefix, payload) != expected_checksum {
+            eyre::bail!("invalid note checksum");
+        }
+
+        let mut payload = payload;
+        let mut read_value = |name: &str| -> eyre::Result<Zeroizing<String>> {
+            let (len, rest) = payload
+                .split_first_chunk::<2>()
+                .ok_or_else(|| eyre::eyre!("malformed note: truncated {name}"))?;
+            let len = u16::from_be_bytes(*len) as usize;
+            if rest.len() < len {
+                eyre::bail!("malformed note: truncated {name}");
+            }
+            let (value, rest) = rest.split_at(len);
+            payload = rest;
+            let value = std::str::from_utf8(value)
+                .map_err(|_| eyre::eyre!("malformed note: {name} is not valid UTF-8"))?;
+            Ok(Zeroizing::new(value.to_string()))
+        };
+        let secret = read_value("secret")?;
+        let nonce = read_value("nonce")?;
+        if !payload.is_empty() {
+            eyre::bail!("malformed note: trailing data");
+        }
+
+        Ok(Self { chain_id, token, secret: NoteSecret { secret, nonce } })
+    }
+}
+
+impl fmt::Debug for WormholeNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WormholeNote")
+            .field("chain_id", &self.chain_id)
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The human-readable part of a note string, including the trailing `-`.
+fn note_prefix(chain_id: u64, token: &Address, version: u8) -> String {
+    format!("{NOTE_PREFIX}-{chain_id}-{token:#x}-v{version}-")
+}
+
+fn checksum(prefix: &str, payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(prefix);
+    hasher.update(payload);
+    let hash = Sha256::digest(hasher.finalize());
+    hash[..CHECKSUM_LEN].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::address;
+
+    use super::*;
+
+    const TOKEN: Address = address!("6D46BE315b48f579387A5EA247E1E25D2FcCE7EE");
+
+    fn note() -> WormholeNote {
+        let secret = NoteSecret::from_reader("0x42\n0x99\n".as_bytes()).unwrap();
+        WormholeNote::new(17000, TOKEN, secret)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let encoded = note().encode().unwrap();
+        assert!(encoded.starts_with("zkw-17000-0x6d46be315b48f579387a5ea247e1e25d2fcce7ee-v1-"));
+
+        let decoded: WormholeNote = encoded.parse().unwrap();
+        assert_eq!(decoded.chain_id, 17000);
+        assert_eq!(decoded.token, TOKEN);
+        assert_eq!(decoded.secret.secret.as_str(), "0x42");
+        assert_eq!(decoded.secret.nonce.as_str(), "0x99");
+        assert_eq!(decoded.dead_address(), note().dead_address());
+    }
+
+    #[test]
+    fn test_invalid_notes() {
+        let encoded = note().encode().unwrap();
+
+        // Changing the chain invalidates the checksum.
+        let other_chain = encoded.replacen("17000", "1", 1);
+        assert!(other_chain.parse::<WormholeNote>().is_err());
+
+        // Corrupting the payload invalidates the checksum.
+        let mut corrupted = encoded.to_string();
+        let last = if corrupted.ends_with('2') { "3" } else { "2" };
+        corrupted.replace_range(corrupted.len() - 1.., last);
+        assert!(corrupted.parse::<WormholeNote>().is_err());
+
+        let other_version = encoded.replacen("-v1-", "-v2-", 1);
+        assert!(other_version.parse::<WormholeNote>().is_err());
+        assert!("zkw-17000".parse::<WormholeNote>().is_err());
+    }
+
+    #[test]
+    fn test_secret_too_long() {
+        let mut note = note();
+        note.secret.secret = Zeroizing::new("1".repeat(usize::from(u16::MAX) + 1));
+        assert!(note.encode().is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! Recovery of funded notes derived from a mnemonic.
+//!
+//! The scanner walks the derivation indices of a [`NoteSeed`] and, for each burn address, reads
+//! `balanceOf` and `getDeadHashAmount` from the wormhole token at a single block. Calls are sent
+//! as JSON-RPC batches, and the scan stops once `gap_limit` consecutive indices were never used.
+
+use alloy_primitives::{Address, Bytes, U256};
+use alloy_provider::{network::AnyNetwork, Provider};
+use alloy_rpc_client::BatchRequest;
+use alloy_rpc_types::BlockId;
+use alloy_sol_types::SolCall;
+use serde_json::json;
+
+use crate::{dead_address_hash, derivation::NoteSeed, IERC20};
+
+/// Parameters of a [`scan`].
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// The first derivation index to scan.
+    pub start_index: u32,
+    /// The number of consecutive unused indices after which the scan stops.
+    pub gap_limit: u32,
+    /// The number of burn addresses queried per JSON-RPC batch.
+    pub batch_size: u32,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self { start_index: 0, gap_limit: 20, batch_size: 50 }
+    }
+}
+
+/// The claim status of a note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteStatus {
+    /// Tokens were burnt to the note, and none were claimed yet.
+    Funded,
+    /// Part of the burnt tokens were claimed.
+    PartiallyClaimed,
+    /// All the burnt tokens were claimed.
+    FullyClaimed,
+}
+
+/// A note found by a [`scan`].
+#[derive(Debug, Clone)]
+pub struct ScannedNote {
+    /// The derivation index of the note.
+    pub index: u32,
+    /// The burn address of the note.
+    pub dead_address: Address,
+    /// The amount of tokens burnt to the note.
+    pub balance: U256,
+    /// The amount of tokens already claimed from the note.
+    pub claimed: U256,
+}
+
+impl ScannedNote {
+    /// The amount of tokens that can still be claimed.
+    pub fn unclaimed(&self) -> U256 {
+        self.balance.saturating_sub(self.claimed)
+    }
+
+    /// The claim status of the note.
+    pub fn status(&self) -> NoteStatus {
+        if self.claimed.is_zero() {
+            NoteStatus::Funded
+        } else if self.claimed < self.balance {
+            NoteStatus::PartiallyClaimed
+        } else {
+            NoteStatus::FullyClaimed
+        }
+    }
+}
+
+/// Scan the notes derived from `seed` for burnt `token`s at `block`.
+///
+/// Returns every note that was ever funded, in index order.
+pub async fn scan<P: Provider<AnyNetwork>>(
+    provider: &P,
+    seed: &NoteSeed,
+    token: Address,
+    block: BlockId,
+    config: &ScanConfig,
+) -> eyre::Result<Vec<ScannedNote>> {
+    if config.batch_size == 0 {
+        eyre::bail!("the scan batch size must be positive");
+    }
+    if config.gap_limit == 0 {
+        eyre::bail!("the scan gap limit must be positive");
+    }
+
+    let mut notes = Vec::new();
+    let mut unused = 0;
+    let mut index = config.start_index;
+    while unused < config.gap_limit {
+        let end = index.saturating_add(config.batch_size);
+        let dead_addresses = (index..end).map(|index| seed.dead_address(index)).collect::<Vec<_>>();
+
+        let mut batch = BatchRequest::new(provider.client());
+        let mut waiters = Vec::with_capacity(dead_addresses.len());
+        for dead_address in &dead_addresses {
+            let balance_call =
+                IERC20::balanceOfCall { account: Address::from(*dead_address) }.abi_encode();
+            let claimed_call =
+                IERC20::getDeadHashAmountCall { h: dead_address_hash(dead_address) }.abi_encode();
+            waiters.push((
+                batch.add_call::<_, Bytes>(
+                    "eth_call",
+                    &eth_call_params(token, balance_call, block),
+                )?,
+                batch.add_call::<_, Bytes>(
+                    "eth_call",
+                    &eth_call_params(token, claimed_call, block),
+                )?,
+            ));
+        }
+        batch.send().await?;
+
+        for ((index, dead_address), (balance, claimed)) in
+            (index..end).zip(dead_addresses).zip(waiters)
+        {
+            let dead_address = Address::from(dead_address);
+            let balance = balance.await.map_err(|e| {
+                eyre::eyre!("balanceOf({dead_address}) failed at index {index}: {e}")
+            })?;
+            let claimed = claimed.await.map_err(|e| {
+                eyre::eyre!("getDeadHashAmount({dead_address}) failed at index {index}: {e}")
+            })?;
+            let balance = IERC20::balanceOfCall::abi_decode_returns(&balance, true)?._0;
+            let claimed = IERC20::getDeadHashAmountCall::abi_decode_returns(&claimed, true)?._0;
+
+            if balance.is_zero() && claimed.is_zero() {
+                unused += 1;
+                if unused >= config.gap_limit {
+                    break;
+                }
+            } else {
+                unused = 0;
+                notes.push(ScannedNote { index, dead_address, balance, claimed });
+            }
+        }
+
+        if end == u32::MAX {
+            break;
+        }
+        index = end;
+    }
+
+    Ok(notes)
+}
+
+fn eth_call_params(to: Address, data: Vec<u8>, block: BlockId) -> (serde_json::Value, BlockId) {
+    (json!({ "to": to, "data": Bytes::from(data) }), block)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        task::{Context, Poll},
+    };
+
+    use alloy_json_rpc::{RequestPacket, Response, ResponsePacket, ResponsePayload};
+    use alloy_primitives::B256;
+    use alloy_provider::RootProvider;
+    use alloy_rpc_client::RpcClient;
+    use alloy_rpc_types::BlockNumberOrTag;
+    use alloy_transport::{BoxTransport, TransportError, TransportFut};
+    use serde_json::{value::RawValue, Value};
+    use tower::Service;
+
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
+
+    /// A transport answering the `eth_call`s of a scan from a fixed set of funded burn addresses.
+    #[derive(Debug, Clone, Default)]
+    struct TokenTransport {
+        balances: HashMap<Address, U256>,
+        claimed: HashMap<B256, U256>,
+    }
+
+    impl TokenTransport {
+        fn eth_call(&self, params: &RawValue) -> U256 {
+            let (call, _): (Value, Value) = serde_json::from_str(params.get()).unwrap();
+            let data: Bytes = serde_json::from_value(call["data"].clone()).unwrap();
+            if let Ok(call) = IERC20::balanceOfCall::abi_decode(&data, true) {
+                self.balances.get(&call.account).copied().unwrap_or_default()
+            } else {
+                let call = IERC20::getDeadHashAmountCall::abi_decode(&data, true).unwrap();
+                self.claimed.get(&call.h).copied().unwrap_or_default()
+            }
+        }
+    }
+
+    impl Service<RequestPacket> for TokenTransport {
+        type Response = ResponsePacket;
+        type Error = TransportError;
+        type Future = TransportFut<'static>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: RequestPacket) -> Self::Future {
+            let RequestPacket::Batch(requests) = request else { unimplemented!() };
+            let responses = requests
+                .iter()
+                .map(|request| {
+                    let output =
+                        Bytes::from(self.eth_call(request.params().unwrap()).to_be_bytes_vec());
+                    let result = serde_json::value::to_raw_value(&output).unwrap();
+                    Response { id: request.id().clone(), payload: ResponsePayload::Success(result) }
+                })
+                .collect();
+            Box::pin(async move { Ok(ResponsePacket::Batch(responses)) })
+        }
+    }
+
+    /// Scan the notes of the test mnemonic, the notes at `funded` being funded with 10 tokens, and
+    /// the ones at `claimed` claimed in full. Returns the indices of the notes found.
+    async fn scan_indices(funded: &[u32], claimed: &[u32], config: ScanConfig) -> Vec<u32> {
+        let seed = NoteSeed::from_mnemonic(MNEMONIC, "").unwrap();
+        let mut transport = TokenTransport::default();
+        for &index in funded {
+            transport.balances.insert(Address::from(seed.dead_address(index)), U256::from(10));
+        }
+        for &index in claimed {
+            transport.claimed.insert(dead_address_hash(&seed.dead_address(index)), U256::from(10));
+        }
+
+        let provider =
+            RootProvider::<AnyNetwork>::new(RpcClient::new(BoxTransport::new(transport), true));
+        let block = BlockNumberOrTag::Number(1).into();
+        let notes = scan(&provider, &seed, Address::ZERO, block, &config).await.unwrap();
+        notes.iter().map(|note| note.index).collect()
+    }
+
+    #[test]
+    fn test_note_status() {
+        let note = |balance: u64, claimed: u64| ScannedNote {
+            index: 0,
+            dead_address: Address::ZERO,
+            balance: U256::from(balance),
+            claimed: U256::from(claimed),
+        };
+
+        assert_eq!(note(10, 0).status(), NoteStatus::Funded);
+        assert_eq!(note(10, 0).unclaimed(), U256::from(10));
+        assert_eq!(note(10, 4).status(), NoteStatus::PartiallyClaimed);
+        assert_eq!(note(10, 4).unclaimed(), U256::from(6));
+        assert_eq!(note(10, 10).status(), NoteStatus::FullyClaimed);
+        assert_eq!(note(10, 10).unclaimed(), U256::ZERO);
+        // Tokens burnt to a note are moved away once claimed, so the claimed amount can exceed
+        // the balance left.
+        assert_eq!(note(0, 10).status(), NoteStatus::FullyClaimed);
+        assert_eq!(note(0, 10).unclaimed(), U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_gap_limit() {
+        // A funded note resets the count of unused indices, across batches too.
+        let config = ScanConfig { start_index: 0, gap_limit: 2, batch_size: 3 };
+        assert_eq!(scan_indices(&[0, 2, 4], &[], config.clone()).await, vec![0, 2, 4]);
+
+        // A fully claimed note is still found.
+        assert_eq!(scan_indices(&[0], &[2], config.clone()).await, vec![0, 2]);
+
+        // The scan stops in the middle of a batch once the gap limit is reached, even if the rest
+        // of the batch holds a funded note.
+        let config = ScanConfig { start_index: 0, gap_limit: 2, batch_size: 10 };
+        assert_eq!(scan_indices(&[0, 4], &[], config).await, vec![0]);
+
+        // The scan stops at the last index, even if the gap limit was not reached.
+        let config = ScanConfig { start_index: u32::MAX - 3, gap_limit: 20, batch_size: 2 };
+        assert_eq!(scan_indices(&[u32::MAX - 1], &[], config).await, vec![u32::MAX - 1]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config() {
+        let seed = NoteSeed::from_mnemonic(MNEMONIC, "").unwrap();
+        let provider = RootProvider::<AnyNetwork>::new(RpcClient::new(
+            BoxTransport::new(TokenTransport::default()),
+            true,
+        ));
+        let block = BlockNumberOrTag::Number(1).into();
+        for config in [
+            ScanConfig { batch_size: 0, ..Default::default() },
+            ScanConfig { gap_limit: 0, ..Default::default() },
+        ] {
+            assert!(scan(&provider, &seed, Address::ZERO, block, &config).await.is_err());
+        }
+    }
+}
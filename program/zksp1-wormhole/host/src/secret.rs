@@ -0,0 +1,146 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{BufRead, Read, Write},
+    path::Path,
+};
+
+use alloy::hex;
+use zeroize::Zeroizing;
+
+use crate::compute_dead_address;
+
+/// Environment variable holding the note secret.
+pub const SECRET_ENV: &str = "ZKW_SECRET";
+/// Environment variable holding the note nonce.
+pub const NONCE_ENV: &str = "ZKW_NONCE";
+
+/// The secret and nonce that determine a wormhole burn address.
+///
+/// Both values are kept in buffers that are zeroed on drop, and are never printed by [`Debug`].
+pub struct NoteSecret {
+    /// The note secret.
+    pub secret: Zeroizing<String>,
+    /// The note nonce.
+    pub nonce: Zeroizing<String>,
+}
+
+impl NoteSecret {
+    /// Generate a new secret and nonce from the OS CSPRNG.
+    pub fn generate() -> eyre::Result<Self> {
+        let mut bytes = Zeroizing::new([0u8; 64]);
+        getrandom::getrandom(bytes.as_mut())
+            .map_err(|e| eyre::eyre!("failed to read from the OS CSPRNG: {e}"))?;
+        Ok(Self {
+            secret: Zeroizing::new(format!("0x{}", hex::encode(&bytes[..32]))),
+            nonce: Zeroizing::new(format!("0x{}", hex::encode(&bytes[32..]))),
+        })
+    }
+
+    /// Read the secret and nonce from `reader`, one per line.
+    pub fn from_reader(mut reader: impl BufRead) -> eyre::Result<Self> {
+        let mut read_value = |name: &str| -> eyre::Result<Zeroizing<String>> {
+            let mut line = Zeroizing::new(String::new());
+            reader.read_line(&mut line)?;
+            let value = line.trim();
+            if value.is_empty() {
+                eyre::bail!("missing note {name}");
+            }
+            Ok(Zeroizing::new(value.to_string()))
+        };
+
+        let secret = read_value("secret")?;
+        let nonce = read_value("nonce")?;
+        Ok(Self { secret, nonce })
+    }
+
+    /// Read the secret and nonce from a file, or from stdin if `path` is `-`.
+    pub fn from_file(path: &Path) -> eyre::Result<Self> {
+        Self::from_reader(read_secret_file(path)?.as_bytes())
+    }
+
+    /// Read the secret and nonce from the [`SECRET_ENV`] and [`NONCE_ENV`] environment variables.
+    ///
+    /// Returns `None` if neither variable is set.
+    pub fn from_env() -> eyre::Result<Option<Self>> {
+        let secret = std::env::var(SECRET_ENV).ok().map(Zeroizing::new);
+        let nonce = std::env::var(NONCE_ENV).ok().map(Zeroizing::new);
+        match (secret, nonce) {
+            (Some(secret), Some(nonce)) => Ok(Some(Self { secret, nonce })),
+            (None, None) => Ok(None),
+            _ => eyre::bail!("both {SECRET_ENV} and {NONCE_ENV} must be set"),
+        }
+    }
+
+    /// Write the secret and nonce to a new file readable only by the current user.
+    ///
+    /// Fails if the file already exists, so that an existing note is never overwritten.
+    pub fn write_to_file(&self, path: &Path) -> eyre::Result<()> {
+        write_secret_file(path, &Zeroizing::new(format!("{}\n{}\n", *self.secret, *self.nonce)))
+    }
+
+    /// The burn address of this note.
+    pub fn dead_address(&self) -> [u8; 20] {
+        compute_dead_address(&self.secret, &self.nonce)
+    }
+}
+
+/// Read a file holding secret material, or stdin if `path` is `-`.
+pub fn read_secret_file(path: &Path) -> eyre::Result<Zeroizing<String>> {
+    let mut contents = Zeroizing::new(String::new());
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut contents)?;
+    } else {
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| eyre::eyre!("failed to read secret file {}: {e}", path.display()))?;
+    }
+    Ok(contents)
+}
+
+/// Write secret material to a new file readable only by the current user.
+///
+/// Fails if the file already exists.
+pub fn write_secret_file(path: &Path, contents: &str) -> eyre::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| eyre::eyre!("failed to create secret file {}: {e}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+impl fmt::Debug for NoteSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoteSecret").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_reader() {
+        let note = NoteSecret::from_reader("0x42\n  0x99  \n".as_bytes()).unwrap();
+        assert_eq!(note.secret.as_str(), "0x42");
+        assert_eq!(note.nonce.as_str(), "0x99");
+        assert!(NoteSecret::from_reader("0x42\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let a = NoteSecret::generate().unwrap();
+        let b = NoteSecret::generate().unwrap();
+        assert_eq!(a.secret.len(), 66);
+        assert_ne!(a.secret, b.secret);
+        assert_ne!(a.secret, a.nonce);
+        assert!(!format!("{a:?}").contains(a.secret.as_str()));
+    }
+}
@@ -0,0 +1,195 @@
+//! End-to-end tests of a claim: the host builds the input of the wormhole program against a
+//! recorded Holesky fixture, or a devnet where the token is deployed, and the program is executed
+//! on it.
+
+use std::{collections::BTreeMap, path::Path};
+
+use alloy_genesis::GenesisAccount;
+use alloy_primitives::{address, keccak256, Address, Bytes, B256, U256};
+use alloy_rpc_types::BlockNumberOrTag;
+use alloy_sol_types::SolType;
+use sp1_cc_client_executor::ContractInput;
+use sp1_cc_host_executor::{HostExecutor, RpcFixture, TrieExecutor};
+use sp1_sdk::ProverClient;
+use url::Url;
+use zk_wormhole_host::{
+    claim::{
+        nullifier, prepare_claim, program_stdin, ClaimWitness, Pruning, PublicValuesStruct, ELF,
+    },
+    dead_address_hash,
+    derivation::NoteSeed,
+    note::WormholeNote,
+};
+
+const HOLESKY_CHAIN_ID: u64 = 17000;
+
+const TOKEN: Address = address!("6D46BE315b48f579387A5EA247E1E25D2FcCE7EE");
+
+const RECEIVER: Address = address!("00000000000000000000000000000000000000aa");
+
+const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                        abandon abandon about";
+
+/// The deployment of [`TOKEN`] on Holesky, as broadcast by forge.
+const TOKEN_DEPLOYMENT: &str =
+    include_str!("../../contracts/broadcast/DeployWormholeWrapper.s.sol/17000/run-latest.json");
+
+/// The storage slot of the `_balances` mapping of the token.
+const BALANCES_SLOT: u64 = 0;
+
+/// The storage slot of the `s_deadHashToAmount` mapping of the token.
+const DEAD_HASH_AMOUNTS_SLOT: u64 = 8;
+
+/// Prepare a claim of nothing from the first note of [`MNEMONIC`] against the recorded Holesky
+/// fixture, so that the claim is valid whatever the balance of the burn address.
+async fn prepare_fixture_claim() -> eyre::Result<(WormholeNote, ClaimWitness)> {
+    dotenv::dotenv().ok();
+
+    // Replay the recorded fixture, or record it from `HOLESKY_RPC_URL`.
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join("claim");
+    let rpc_url = std::env::var("HOLESKY_RPC_URL").ok().map(|url| Url::parse(&url)).transpose()?;
+    let fixture = RpcFixture::replay_or_record(&dir, HOLESKY_CHAIN_ID, rpc_url).await?;
+
+    let seed = NoteSeed::from_mnemonic(MNEMONIC, "")?;
+    let note = WormholeNote::new(HOLESKY_CHAIN_ID, TOKEN, seed.derive(0));
+
+    let block_number = BlockNumberOrTag::Number(fixture.block_number);
+    let mut host_executor = HostExecutor::new(fixture.provider, block_number).await?;
+    let witness = prepare_claim(&mut host_executor, &note, 0, RECEIVER, Pruning::Report).await?;
+    assert_eq!(witness.block_hash, host_executor.header.hash_slow());
+    Ok((note, witness))
+}
+
+/// Prepare a claim of `amount` from the first note of [`MNEMONIC`] on a devnet where the token is
+/// deployed, `balance` base units were burnt to the note and `claimed` already claimed from it.
+async fn prepare_devnet_claim(
+    balance: u64,
+    claimed: u64,
+    amount: u64,
+) -> eyre::Result<(WormholeNote, ClaimWitness)> {
+    // Run the creation code of the token on an empty devnet, to get its runtime code.
+    let deployment = serde_json::from_str::<serde_json::Value>(TOKEN_DEPLOYMENT)?;
+    let init_code: Bytes =
+        serde_json::from_value(deployment["transactions"][0]["transaction"]["input"].clone())?;
+    let mut executor = TrieExecutor::devnet(&BTreeMap::new());
+    let code = executor.execute(ContractInput::new_create(Address::default(), init_code)).await?;
+
+    let seed = NoteSeed::from_mnemonic(MNEMONIC, "")?;
+    let note = WormholeNote::new(HOLESKY_CHAIN_ID, TOKEN, seed.derive(0));
+    let dead_address = note.dead_address();
+    let storage = [
+        (mapping_slot(B256::left_padding_from(&dead_address), BALANCES_SLOT), balance),
+        (mapping_slot(dead_address_hash(&dead_address), DEAD_HASH_AMOUNTS_SLOT), claimed),
+    ];
+    let token = GenesisAccount::default().with_code(Some(code)).with_storage(Some(
+        storage.into_iter().map(|(slot, value)| (slot, B256::from(U256::from(value)))).collect(),
+    ));
+
+    let mut executor = TrieExecutor::devnet(&BTreeMap::from([(TOKEN, token)]));
+    let witness = prepare_claim(&mut executor, &note, amount, RECEIVER, Pruning::Enabled).await?;
+    assert_eq!(witness.balance, U256::from(balance));
+    assert_eq!(witness.claimed, U256::from(claimed));
+    Ok((note, witness))
+}
+
+/// The storage slot of `key` in the mapping at slot `slot`.
+fn mapping_slot(key: B256, slot: u64) -> B256 {
+    keccak256([key.as_slice(), B256::from(U256::from(slot)).as_slice()].concat())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_devnet_claim() -> eyre::Result<()> {
+    let (note, witness) = prepare_devnet_claim(1000, 300, 700).await?;
+    witness.state_sketch.validate()?;
+
+    let client = ProverClient::builder().mock().build();
+    let (output, _) = client
+        .execute(ELF, &witness.stdin)
+        .run()
+        .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
+    let public_values = <PublicValuesStruct as SolType>::abi_decode(output.as_slice(), true)?;
+
+    let dead_address = note.dead_address();
+    assert_eq!(public_values.amount, 700);
+    assert_eq!(public_values.receiver, RECEIVER);
+    assert_eq!(public_values.blockHash, witness.block_hash);
+    assert_eq!(public_values.deadAddressHash, dead_address_hash(&dead_address));
+    assert_eq!(
+        public_values.nullifier,
+        nullifier(&dead_address, RECEIVER, 700, witness.block_hash, TOKEN, &[])
+    );
+
+    // The program rejects a claim of more than what is left.
+    let (_, witness) = prepare_devnet_claim(1000, 300, 701).await?;
+    assert!(client.execute(ELF, &witness.stdin).run().is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_claim_e2e() -> eyre::Result<()> {
+    let (note, witness) = prepare_fixture_claim().await?;
+    let dead_address = note.dead_address();
+    assert!(witness.claimed <= witness.balance);
+    witness.state_sketch.validate()?;
+    let pruning = witness.pruning.as_ref().expect("the sketch is pruned");
+    assert_eq!(pruning.report.after, witness.state_sketch.encoded_sizes());
+
+    let client = ProverClient::builder().mock().build();
+    let (output, _) = client
+        .execute(ELF, &witness.stdin)
+        .run()
+        .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
+    let public_values = <PublicValuesStruct as SolType>::abi_decode(output.as_slice(), true)?;
+
+    assert_eq!(public_values.amount, 0);
+    assert_eq!(public_values.receiver, RECEIVER);
+    assert_eq!(public_values.blockHash, witness.block_hash);
+    assert_eq!(public_values.contractAddress, TOKEN);
+    assert_eq!(public_values.deadAddressHash, dead_address_hash(&dead_address));
+    assert_eq!(
+        public_values.nullifier,
+        nullifier(&dead_address, RECEIVER, 0, witness.block_hash, TOKEN, &[])
+    );
+    assert!(public_values.data.is_empty());
+
+    // Pruning must not change what the program commits to.
+    let unpruned_stdin = pruning.unpruned_stdin.as_ref().expect("the unpruned input is kept");
+    let (unpruned_output, _) = client
+        .execute(ELF, unpruned_stdin)
+        .run()
+        .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
+    assert_eq!(unpruned_output.as_slice(), output.as_slice());
+
+    Ok(())
+}
+
+/// Compares the cycles of the program reading the state sketch in place from its archive, as the
+/// host sends it, and decoding it from its canonical encoding, on a devnet claim. Run with
+/// `--ignored --nocapture` to see the numbers.
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn bench_sketch_encoding() -> eyre::Result<()> {
+    let (note, witness) = prepare_devnet_claim(1000, 0, 0).await?;
+    let client = ProverClient::builder().mock().build();
+
+    let mut runs = Vec::new();
+    for (encoding, sketch) in [
+        ("canonical", witness.state_sketch.encode()),
+        ("archive", witness.state_sketch.to_archive()),
+    ] {
+        let stdin = program_stdin(&note, 0, RECEIVER, witness.block_hash, &sketch)?;
+        let (output, report) = client
+            .execute(ELF, &stdin)
+            .run()
+            .map_err(|e| eyre::eyre!("Execution failed: {}", e))?;
+        let cycles = report.total_instruction_count();
+        println!("{}: {} bytes, {} cycles", encoding, sketch.len(), cycles);
+        runs.push((output.as_slice().to_vec(), cycles));
+    }
+
+    // Both encodings must give the same output.
+    assert_eq!(runs[0].0, runs[1].0);
+
+    Ok(())
+}